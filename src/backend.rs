@@ -0,0 +1,120 @@
+use std::ffi::OsStr;
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+
+use colorize::colorize;
+use log::debug;
+
+use crate::move_files::{self, rename_with, MoveOptions};
+use crate::{ItemMeta, TrashResult};
+
+/// Where trashed items are kept. History, what came from where, stays with `Trash`: a
+/// backend only stores items and hands them back.
+pub trait TrashBackend: Debug {
+    /// Where an item trashed from `original` would go, steering clear of paths `taken` says
+    /// are spoken for, and why it goes there
+    fn place(&self, original: &Path, taken: &dyn Fn(&Path) -> bool) -> (PathBuf, String);
+
+    /// Moves `original` to `stored`, as picked by `place`, and returns where it ended up. Any
+    /// copying between filesystems goes the way `opts` says.
+    fn move_to_trash(&self, original: &Path, stored: &Path, meta: &ItemMeta, opts: &MoveOptions, on_copied: &mut dyn FnMut(u64)) -> TrashResult<PathBuf>;
+
+    /// Moves an item out to `dest` the way it was when it was trashed
+    fn restore(&self, stored: &Path, dest: &Path, meta: &ItemMeta, opts: &MoveOptions, on_copied: &mut dyn FnMut(u64)) -> TrashResult<()>;
+
+    /// Deletes an item for good, if it is still there
+    fn purge(&self, stored: &Path) -> TrashResult<()>;
+
+    /// Everything being kept, whether history knows about it or not
+    fn list(&self) -> TrashResult<Vec<PathBuf>>;
+}
+
+/// A plain directory, the temp directory unless config says otherwise
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dir {
+    path: PathBuf
+}
+
+impl Dir {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+impl TrashBackend for Dir {
+    fn place(&self, original: &Path, taken: &dyn Fn(&Path) -> bool) -> (PathBuf, String) {
+        let path = self.path.join(original.file_name().unwrap_or(OsStr::new("item")));
+
+        if !taken(&path) {
+            return (path, "trash".to_string())
+        }
+
+        let renamed = crate::new_item_name(&path, taken);
+        debug!("{}", colorize!(b->"Trash path already exists. Switching to", Fgb->&renamed));
+
+        (renamed, format!("trash, renamed because {} is already taken in the trash", path.display()))
+    }
+
+    fn move_to_trash(&self, original: &Path, stored: &Path, _meta: &ItemMeta, opts: &MoveOptions, on_copied: &mut dyn FnMut(u64)) -> TrashResult<PathBuf> {
+        // A directory goes in whole: on the same filesystem that's one rename, however big it is
+        rename_with(original, stored, opts, on_copied)?;
+        Ok(stored.to_path_buf())
+    }
+
+    fn restore(&self, stored: &Path, dest: &Path, meta: &ItemMeta, opts: &MoveOptions, on_copied: &mut dyn FnMut(u64)) -> TrashResult<()> {
+        Ok(crate::move_back(stored, dest, meta, opts, on_copied)?)
+    }
+
+    fn purge(&self, stored: &Path) -> TrashResult<()> {
+        if stored.symlink_metadata().is_ok() {
+            crate::delete_permanently(stored)?;
+        }
+
+        Ok(())
+    }
+
+    fn list(&self) -> TrashResult<Vec<PathBuf>> {
+        list_dir(&self.path)
+    }
+}
+
+/// The entries of `dir` by name, none if it isn't there yet
+pub(crate) fn list_dir(dir: &Path) -> TrashResult<Vec<PathBuf>> {
+    match move_files::read_dir(dir, true) {
+        Ok(entries) => Ok(entries.into_iter().map(|e| e.path()).collect()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dir() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let dir = Dir::new(tmp_dir.path().join("trash"));
+        let original = tmp_dir.path().join("a.txt");
+
+        std::fs::create_dir(tmp_dir.path().join("trash")).unwrap();
+        std::fs::write(&original, "a").unwrap();
+
+        let (stored, _) = dir.place(&original, &|p| p.exists());
+        dir.move_to_trash(&original, &stored, &ItemMeta::default(), &MoveOptions::default(), &mut |_| {}).unwrap();
+
+        std::fs::write(&original, "b").unwrap();
+        let (again, reason) = dir.place(&original, &|p| p.exists());
+
+        // Taken names get a number, after the extension rather than in place of it
+        assert_eq!(again, tmp_dir.path().join("trash/a.txt.~1~"));
+        assert!(reason.contains("renamed"));
+        assert_eq!(dir.place(&original, &|p| p.exists() || p.ends_with("a.txt.~1~")).0, tmp_dir.path().join("trash/a.txt.~2~"));
+        assert_eq!(dir.list().unwrap(), vec![stored.clone()]);
+
+        dir.purge(&stored).unwrap();
+
+        assert!(dir.list().unwrap().is_empty());
+        assert!(dir.purge(&stored).is_ok());
+    }
+}
@@ -0,0 +1,398 @@
+use std::{env, fs};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::lock::LockMode;
+use crate::logging::LogFormat;
+use crate::schedule::Cron;
+use crate::webhook::Webhook;
+use crate::{TrashError, TrashResult};
+
+/// Machine wide settings and ignore patterns set by an admin
+#[cfg(unix)]
+const SYSTEM_DIR: Option<&str> = Some("/etc/trash");
+#[cfg(not(unix))]
+const SYSTEM_DIR: Option<&str> = None;
+/// Looked for in the working directory and every directory above it, the nearest one counts
+const PROJECT_FILE: &str = ".trash.toml";
+/// Settings a project file can't have. Anyone can leave one in a repository, and these would
+/// send trashed items, history or the paths in it wherever they like.
+const PROJECT_LOCKED: [&str; 4] = ["trash_dir", "history", "metrics_file", "webhook"];
+
+/// Settings merged from `config.toml` in the system directory, then the one in the user's
+/// config directory, then the nearest project `.trash.toml`, each overriding the one before,
+/// except that a project file can't override the system one. Command line flags override all of them.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Where trashed items go instead of the temp directory, `~/` is the home directory
+    #[serde(deserialize_with = "home_path")]
+    pub trash_dir: Option<PathBuf>,
+    /// Where the history file is instead of the config directory, `~/` is the home directory
+    #[serde(deserialize_with = "home_path")]
+    pub history: Option<PathBuf>,
+    /// Keep trashed items in the freedesktop.org trash with a `.trashinfo` each, so file managers
+    /// list them and can restore them too
+    pub freedesktop: bool,
+    /// Trash through Finder into `~/.Trash`, or `.Trashes` on an external volume, so Put Back works
+    pub macos_trash: bool,
+    /// Keep each trashed item in a directory of its own named by a UUID, with a `<uuid>.json`
+    /// beside it saying where it came from, instead of under its own name
+    pub uuid_layout: bool,
+    /// How long items stay in the trash before `purge_expired` deletes them
+    #[serde(deserialize_with = "age")]
+    pub expire_after: Option<Duration>,
+    /// Also purge expired items at the start of every command, not just when scheduled
+    pub expire_on_run: bool,
+    /// Once the trash is bigger than this, the oldest items are purged to make room
+    #[serde(deserialize_with = "size")]
+    pub max_size: Option<u64>,
+    /// Ask once before trashing more than three items or any directory, as if -I was always given
+    pub confirm_bulk: bool,
+    /// Always flush trashed items and history to disk, as if --sync was given
+    pub sync: bool,
+    /// Trash items from other filesystems to `.Trash-$uid` at the top of theirs, instead of
+    /// copying them to the trash, as if --xdev local-trash was given
+    pub mount_trash: bool,
+    /// How log messages look when --log-format isn't given, `plain` for no colors
+    pub log_format: Option<LogFormat>,
+    /// Whether to wait for or fail on another run changing the trash that is already going
+    pub lock: Option<LockMode>,
+    /// Where `trash maintain` keeps metrics for a Prometheus textfile collector
+    pub metrics_file: Option<PathBuf>,
+    pub schedule: Schedule,
+    pub webhook: Option<Webhook>
+}
+
+/// Maintenance tasks run by `trash maintain`, each on its own cron schedule
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Schedule {
+    pub purge_expired: Option<Cron>,
+    /// Checks every item in history is still in the trash
+    pub verify: Option<Cron>
+}
+
+pub fn config_path(dir: &Path) -> PathBuf {
+    dir.join("config.toml")
+}
+
+/// Config files in order of precedence, lowest first
+#[derive(Debug)]
+pub struct Layers {
+    pub system: Option<PathBuf>,
+    pub user: PathBuf,
+    /// Can't set `PROJECT_LOCKED`, and whatever `system` sets wins over it
+    pub project: Option<PathBuf>
+}
+
+impl Layers {
+    /// Just the user's file, as for an isolated trash
+    pub fn user(user: PathBuf) -> Self {
+        Self { system: None, user, project: None }
+    }
+}
+
+/// The config files that apply here. An isolated trash (--root) only has its own.
+pub fn layers(dir: &Path, isolated: bool) -> Layers {
+    let user = config_path(dir);
+
+    if isolated {
+        return Layers::user(user)
+    }
+
+    let project = env::current_dir()
+        .ok()
+        .and_then(|cwd| cwd.ancestors().map(|dir| dir.join(PROJECT_FILE)).find(|path| path.is_file()));
+
+    Layers {
+        system: SYSTEM_DIR.map(|dir| Path::new(dir).join("config.toml")),
+        user,
+        project
+    }
+}
+
+/// Ignore files, the system one last so its patterns can't be taken back with `!`
+pub fn ignore_layers(dir: &Path, isolated: bool) -> Vec<PathBuf> {
+    let user = dir.join("ignore");
+
+    match SYSTEM_DIR {
+        Some(dir) if !isolated => vec![user, Path::new(dir).join("ignore")],
+        _ => vec![user]
+    }
+}
+
+impl Config {
+    /// Missing files are skipped. Tables are merged key by key, so a later file only
+    /// overrides the settings it has.
+    pub fn load_layers(layers: &Layers) -> TrashResult<Self> {
+        Self::from_table(merged(layers)?)
+    }
+
+    fn from_table(table: toml::Table) -> TrashResult<Self> {
+        let config = Self::deserialize(toml::Value::Table(table)).map_err(|e| TrashError(e.to_string()))?;
+
+        if config.schedule.purge_expired.is_some() && config.expire_after.is_none() {
+            return Err(TrashError::new("purge_expired is scheduled but expire_after isn't set"))
+        }
+
+        if config.expire_on_run && config.expire_after.is_none() {
+            return Err(TrashError::new("expire_on_run is set but expire_after isn't"))
+        }
+
+        Ok(config)
+    }
+}
+
+/// A missing file is the same as an empty one
+fn read_table(path: &Path) -> TrashResult<toml::Table> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(toml::Table::new()),
+        Err(e) => return Err(TrashError(format!("{}: {}", path.display(), e)))
+    };
+
+    let table: toml::Table = toml::from_str(&text).map_err(|e| TrashError(format!("{}: {}", path.display(), e)))?;
+
+    // Checked on its own first, so mistakes are blamed on the right file
+    Config::deserialize(toml::Value::Table(table.clone())).map_err(|e| TrashError(format!("{}: {}", path.display(), e)))?;
+
+    Ok(table)
+}
+
+fn merged(layers: &Layers) -> TrashResult<toml::Table> {
+    let system = match &layers.system {
+        Some(path) => read_table(path)?,
+        None => toml::Table::new()
+    };
+
+    let mut merged = system.clone();
+    merge(&mut merged, read_table(&layers.user)?);
+
+    if let Some(path) = &layers.project {
+        let mut project = read_table(path)?;
+
+        if let Some(key) = PROJECT_LOCKED.iter().find(|key| project.contains_key(**key)) {
+            return Err(TrashError(format!("{}: {} can't be set in a project {}", path.display(), key, PROJECT_FILE)))
+        }
+
+        unset(&mut project, &system);
+        merge(&mut merged, project);
+    }
+
+    Ok(merged)
+}
+
+/// Drops whatever `set` has from `table`, key by key inside tables
+fn unset(table: &mut toml::Table, set: &toml::Table) {
+    table.retain(|key, value| match (value.as_table_mut(), set.get(key)) {
+        (Some(table), Some(toml::Value::Table(set))) => {
+            unset(table, set);
+            true
+        },
+        (_, set) => set.is_none()
+    });
+}
+
+/// The value of a dotted key like `schedule.purge_expired`, as merged from every file
+pub fn get(layers: &Layers, key: &str) -> TrashResult<toml::Value> {
+    let table = merged(layers)?;
+    let mut parts = key.split('.');
+    let mut value = parts.next().and_then(|part| table.get(part));
+
+    for part in parts {
+        value = value.and_then(|v| v.as_table()).and_then(|t| t.get(part));
+    }
+
+    value.cloned().ok_or_else(|| TrashError(format!("{} isn't set", key)))
+}
+
+/// Sets a dotted key in the config file at `path`. `raw` is read as a TOML value, or taken as
+/// a string if it isn't one, and the file is only replaced if the result is a valid config.
+pub fn set(path: &Path, key: &str, raw: &str) -> TrashResult<()> {
+    let value = toml::from_str::<toml::Table>(&format!("value = {}", raw))
+        .ok()
+        .and_then(|mut t| t.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(raw.to_string()));
+
+    set_value(path, key, value)
+}
+
+pub fn set_value(path: &Path, key: &str, value: toml::Value) -> TrashResult<()> {
+    let mut table = read_table(path)?;
+
+    let (parents, last) = match key.rsplit_once('.') {
+        Some((parents, last)) => (parents.split('.').collect(), last),
+        None => (vec![], key)
+    };
+
+    let mut current = &mut table;
+
+    for part in parents {
+        current = current
+            .entry(part.to_string())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| TrashError(format!("{} isn't a table", part)))?;
+    }
+
+    current.insert(last.to_string(), value);
+
+    Config::from_table(table.clone()).map_err(|e| TrashError(format!("Not set, {}", e.0)))?;
+
+    let text = toml::to_string(&table).map_err(|e| TrashError(e.to_string()))?;
+    let tmp = path.with_extension("toml.tmp");
+
+    fs::write(&tmp, text)?;
+    fs::rename(&tmp, path)?;
+
+    Ok(())
+}
+
+fn merge(base: &mut toml::Table, over: toml::Table) {
+    for (key, value) in over {
+        match (base.get_mut(&key).and_then(toml::Value::as_table_mut), value) {
+            (Some(base), toml::Value::Table(over)) => merge(base, over),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+impl Schedule {
+    pub fn tasks(&self) -> Vec<(Task, &Cron)> {
+        [(Task::PurgeExpired, &self.purge_expired), (Task::Verify, &self.verify)]
+            .into_iter()
+            .filter_map(|(task, cron)| Some((task, cron.as_ref()?)))
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Task {
+    PurgeExpired,
+    Verify
+}
+
+impl Task {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::PurgeExpired => "purge_expired",
+            Self::Verify => "verify"
+        }
+    }
+}
+
+fn home_path<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<PathBuf>, D::Error> {
+    let path = String::deserialize(deserializer)?;
+
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => Ok(Some(home.join(rest))),
+        (Some(_), None) => Err(serde::de::Error::custom("~/ needs a home directory")),
+        (None, _) => Ok(Some(PathBuf::from(path)))
+    }
+}
+
+fn age<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    let age = String::deserialize(deserializer)?;
+    crate::parse_age(&age).map(Some).map_err(serde::de::Error::custom)
+}
+
+fn size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    let size = String::deserialize(deserializer)?;
+    crate::parse_size(&size).map(|bytes| Some(bytes as u64)).map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_load_config() {
+        let dir = tempdir().unwrap();
+        let layers = Layers::user(dir.path().join("config.toml"));
+        let path = &layers.user;
+
+        assert!(Config::load_layers(&layers).unwrap().schedule.tasks().is_empty());
+
+        fs::write(path, "expire_after = \"30d\"\n\n[schedule]\npurge_expired = \"0 3 * * *\"\n").unwrap();
+        let config = Config::load_layers(&layers).unwrap();
+
+        assert_eq!(config.expire_after, Some(Duration::from_secs(30 * 24 * 60 * 60)));
+        assert_eq!(config.schedule.tasks()[0].0, Task::PurgeExpired);
+
+        fs::write(path, "max_size = \"5GB\"\n").unwrap();
+        assert_eq!(Config::load_layers(&layers).unwrap().max_size, Some(5 * 1024 * 1024 * 1024));
+
+        fs::write(path, "expire_on_run = true\n").unwrap();
+        assert!(Config::load_layers(&layers).is_err());
+
+        fs::write(path, "[schedule]\npurge_expired = \"0 3 * * *\"\n").unwrap();
+        assert!(Config::load_layers(&layers).is_err());
+
+        fs::write(path, "expire_after = \"30d\"\n\n[schedule]\npurge_expired = \"0 3 * *\"\n").unwrap();
+        assert!(Config::load_layers(&layers).is_err());
+    }
+
+    #[test]
+    fn test_get_set() {
+        let dir = tempdir().unwrap();
+        let layers = Layers::user(dir.path().join("config.toml"));
+        let path = &layers.user;
+
+        set(path, "expire_after", "30d").unwrap();
+        set(path, "schedule.purge_expired", "0 3 * * *").unwrap();
+
+        assert_eq!(get(&layers, "expire_after").unwrap().as_str(), Some("30d"));
+        assert_eq!(get(&layers, "schedule.purge_expired").unwrap().as_str(), Some("0 3 * * *"));
+        assert!(get(&layers, "schedule.verify").is_err());
+
+        // Nothing is written unless the result is a valid config
+        assert!(set(path, "trash_bin", "/tmp").is_err());
+        assert!(set(path, "expire_after", "soon").is_err());
+        assert!(set(path, "expire_after.days", "3").is_err());
+
+        assert_eq!(Config::load_layers(&layers).unwrap().expire_after, Some(Duration::from_secs(30 * 24 * 60 * 60)));
+    }
+
+    #[test]
+    fn test_layers() {
+        let dir = tempdir().unwrap();
+        let (system, user, project) = (dir.path().join("system.toml"), dir.path().join("user.toml"), dir.path().join(PROJECT_FILE));
+        let layers = Layers { system: Some(system.clone()), user: user.clone(), project: Some(project.clone()) };
+
+        fs::write(&system, "max_size = \"10GB\"\nlock = \"fail\"\n\n[schedule]\nverify = \"0 4 * * 0\"\n").unwrap();
+        fs::write(&user, "expire_after = \"30d\"\nmax_size = \"20GB\"\n\n[schedule]\npurge_expired = \"0 3 * * *\"\n").unwrap();
+        fs::write(&project, "expire_after = \"7d\"\nlock = \"wait\"\n\n[schedule]\nverify = \"0 5 * * *\"\npurge_expired = \"0 6 * * *\"\n").unwrap();
+
+        let config = Config::load_layers(&layers).unwrap();
+
+        assert_eq!(config.expire_after, Some(Duration::from_secs(7 * 24 * 60 * 60)));
+        assert_eq!(config.max_size, Some(20 * 1024 * 1024 * 1024));
+        assert_eq!(config.schedule.tasks().len(), 2);
+
+        // The project file only gets what the system one leaves
+        assert_eq!(config.lock, Some(LockMode::Fail));
+        assert_eq!(config.schedule.verify, "0 4 * * 0".parse().ok());
+        assert_eq!(config.schedule.purge_expired, "0 6 * * *".parse().ok());
+
+        // And never where things go
+        for line in ["trash_dir = \"/tmp/t\"", "history = \"/tmp/h\"", "metrics_file = \"/tmp/m\"", "[webhook]\nurl = \"http://example.com\""] {
+            fs::write(&project, line).unwrap();
+            let err = Config::load_layers(&layers).unwrap_err();
+
+            assert!(err.0.contains(&project.display().to_string()));
+        }
+
+        // A mistake is blamed on the file it's in
+        fs::write(&project, "").unwrap();
+        fs::write(&user, "expire = \"30d\"\n").unwrap();
+        let err = Config::load_layers(&layers).unwrap_err();
+
+        assert!(err.0.contains(&user.display().to_string()));
+    }
+}
@@ -0,0 +1,300 @@
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use jiff::civil::DateTime;
+use jiff::tz::TimeZone;
+use jiff::Timestamp;
+use log::warn;
+use colorize::colorize;
+
+use crate::backend::{self, TrashBackend};
+use crate::move_files::{rename_with, MoveOptions};
+use crate::schedule::to_system_time;
+use crate::{ItemMeta, TrashResult};
+
+/// Cached sizes of trashed directories, one `size mtime name` line each
+const DIRECTORY_SIZES: &str = "directorysizes";
+
+/// The freedesktop.org trash that file managers use, items in `files/` and a `.trashinfo`
+/// for each in `info/` saying where it came from
+#[derive(Debug, Clone, PartialEq)]
+pub struct FreeDesktop {
+    dir: PathBuf
+}
+
+impl FreeDesktop {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// `~/.local/share/Trash`
+    pub fn home() -> Option<Self> {
+        dirs::data_dir().map(|dir| Self::new(dir.join("Trash")))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Where in `files/` an item trashed from `original` can go, without taking a name
+    /// used in either `files/` or `info/`
+    pub fn free_path(&self, original: &Path, taken: impl Fn(&Path) -> bool) -> PathBuf {
+        let name = original.file_name().unwrap_or(OsStr::new("item"));
+        let mut path = self.dir.join("files").join(name);
+        let mut count = 1;
+
+        while taken(&path) || self.info_path(&path).exists() {
+            count += 1;
+
+            let mut numbered = name.to_os_string();
+            numbered.push(format!(".{}", count));
+            path.set_file_name(numbered);
+        }
+
+        path
+    }
+
+    /// Writes the `.trashinfo` for an item about to be moved to `stored`. It is created new,
+    /// so a name taken in the meantime fails rather than being overwritten.
+    pub fn write_info(&self, stored: &Path, original: &Path, at: SystemTime) -> TrashResult<()> {
+        fs::create_dir_all(self.dir.join("files"))?;
+        fs::create_dir_all(self.dir.join("info"))?;
+
+        let date = Timestamp::try_from(at)
+            .map(|t| t.to_zoned(TimeZone::system()).datetime().strftime("%Y-%m-%dT%H:%M:%S").to_string())
+            .unwrap_or_default();
+
+        let mut file = OpenOptions::new().write(true).create_new(true).open(self.info_path(stored))?;
+        write!(file, "[Trash Info]\nPath={}\nDeletionDate={}\n", percent_encode(original), date)?;
+
+        Ok(())
+    }
+
+    /// Drops the `.trashinfo` of an item that has left `files/`, if it was ever there
+    pub fn remove_info(&self, stored: &Path) -> TrashResult<()> {
+        if stored.parent() != Some(&self.dir.join("files")) {
+            return Ok(())
+        }
+
+        match fs::remove_file(self.info_path(stored)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+
+        let name = entry_name(stored);
+
+        self.edit_sizes(|lines| {
+            let before = lines.len();
+            lines.retain(|line| line.splitn(3, ' ').nth(2) != Some(name.as_str()));
+            lines.len() != before
+        })
+    }
+
+    /// Adds a trashed directory to `directorysizes`, so file managers can tell how big the
+    /// trash is without walking it. The entry is tied to the `.trashinfo`'s mtime.
+    pub fn note_size(&self, stored: &Path, size: u64) -> TrashResult<()> {
+        let mtime = fs::metadata(self.info_path(stored))?.modified()?;
+        let secs = mtime.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+        let name = entry_name(stored);
+
+        self.edit_sizes(|lines| {
+            lines.retain(|line| line.splitn(3, ' ').nth(2) != Some(name.as_str()));
+            lines.push(format!("{} {} {}", size, secs, name));
+            true
+        })
+    }
+
+    /// Rewrites `directorysizes` through a temporary file if `edit` changed anything,
+    /// so a file manager reading it never sees half of it
+    fn edit_sizes(&self, edit: impl FnOnce(&mut Vec<String>) -> bool) -> TrashResult<()> {
+        let path = self.dir.join(DIRECTORY_SIZES);
+
+        let mut lines: Vec<String> = match fs::read_to_string(&path) {
+            Ok(text) => text.lines().map(String::from).collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e.into())
+        };
+
+        if !edit(&mut lines) {
+            return Ok(())
+        }
+
+        let tmp = self.dir.join(format!("{}.{}", DIRECTORY_SIZES, std::process::id()));
+        fs::write(&tmp, lines.iter().map(|line| format!("{}\n", line)).collect::<String>())?;
+        fs::rename(&tmp, &path)?;
+
+        Ok(())
+    }
+
+    pub fn info_path(&self, stored: &Path) -> PathBuf {
+        let mut name = stored.file_name().unwrap_or_default().to_os_string();
+        name.push(".trashinfo");
+
+        self.dir.join("info").join(name)
+    }
+}
+
+impl TrashBackend for FreeDesktop {
+    fn place(&self, original: &Path, taken: &dyn Fn(&Path) -> bool) -> (PathBuf, String) {
+        (self.free_path(original, taken), "trash, listed in the freedesktop trash".to_string())
+    }
+
+    fn move_to_trash(&self, original: &Path, stored: &Path, meta: &ItemMeta, opts: &MoveOptions, on_copied: &mut dyn FnMut(u64)) -> TrashResult<PathBuf> {
+        self.write_info(stored, original, meta.trashed_at.unwrap_or_else(SystemTime::now))?;
+
+        if let Err(e) = rename_with(original, stored, opts, on_copied) {
+            self.forget(stored);
+            return Err(e.into())
+        }
+
+        if stored.symlink_metadata().is_ok_and(|m| m.is_dir()) {
+            if let Err(e) = self.note_size(stored, meta.size) {
+                warn!("{}", colorize!(Fyb->"Could not record the size of", b->stored, b->"-", b->e));
+            }
+        }
+
+        Ok(stored.to_path_buf())
+    }
+
+    fn restore(&self, stored: &Path, dest: &Path, meta: &ItemMeta, opts: &MoveOptions, on_copied: &mut dyn FnMut(u64)) -> TrashResult<()> {
+        crate::move_back(stored, dest, meta, opts, on_copied)?;
+        self.forget(stored);
+
+        Ok(())
+    }
+
+    fn purge(&self, stored: &Path) -> TrashResult<()> {
+        if stored.symlink_metadata().is_ok() {
+            crate::delete_permanently(stored)?;
+        }
+
+        self.forget(stored);
+
+        Ok(())
+    }
+
+    fn list(&self) -> TrashResult<Vec<PathBuf>> {
+        backend::list_dir(&self.dir.join("files"))
+    }
+}
+
+impl FreeDesktop {
+    /// `remove_info` for an item that is gone either way, where a leftover `.trashinfo` is only
+    /// worth a warning
+    fn forget(&self, stored: &Path) {
+        if let Err(e) = self.remove_info(stored) {
+            warn!("{}", colorize!(Fyb->"Could not remove the trashinfo for", b->stored, b->"-", b->e));
+        }
+    }
+}
+
+/// How `directorysizes` names an item in `files/`
+fn entry_name(stored: &Path) -> String {
+    percent_encode(Path::new(stored.file_name().unwrap_or_default()))
+}
+
+/// `Path` and `DeletionDate` from a `[Trash Info]` section
+pub fn parse_trashinfo(text: &str) -> Option<(PathBuf, Option<SystemTime>)> {
+    let lines = text.lines().map(str::trim).skip_while(|line| *line != "[Trash Info]").skip(1);
+    let (mut path, mut date) = (None, None);
+
+    for line in lines.take_while(|line| !line.starts_with('[')) {
+        match line.split_once('=') {
+            Some(("Path", value)) => path = Some(percent_decode(value)?),
+            Some(("DeletionDate", value)) => date = value.parse::<DateTime>().ok().and_then(to_system_time),
+            _ => {}
+        }
+    }
+
+    Some((path?, date))
+}
+
+fn percent_encode(path: &Path) -> String {
+    os_bytes(path.as_os_str())
+        .iter()
+        .map(|&b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => (b as char).to_string(),
+            _ => format!("%{:02X}", b)
+        })
+        .collect()
+}
+
+fn percent_decode(text: &str) -> Option<PathBuf> {
+    let mut bytes = vec![];
+    let mut rest = text.as_bytes();
+
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+
+    Some(PathBuf::from(os_string(bytes)))
+}
+
+#[cfg(unix)]
+fn os_bytes(text: &OsStr) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    text.as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn os_bytes(text: &OsStr) -> Vec<u8> {
+    text.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(unix)]
+fn os_string(bytes: Vec<u8>) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+    OsString::from_vec(bytes)
+}
+
+#[cfg(not(unix))]
+fn os_string(bytes: Vec<u8>) -> OsString {
+    OsString::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trashinfo() {
+        let dir = tempfile::tempdir().unwrap();
+        let fd = FreeDesktop::new(dir.path().join("Trash"));
+        let original = Path::new("/home/me/my notes%.txt");
+
+        let stored = fd.free_path(original, |p| p.exists());
+        fd.write_info(&stored, original, SystemTime::now()).unwrap();
+
+        assert_eq!(stored, dir.path().join("Trash/files/my notes%.txt"));
+
+        let text = fs::read_to_string(fd.info_path(&stored)).unwrap();
+
+        assert!(text.starts_with("[Trash Info]\nPath=/home/me/my%20notes%25.txt\nDeletionDate="));
+        assert_eq!(parse_trashinfo(&text).unwrap().0, original);
+
+        // The info file alone is enough to make the name taken
+        assert_eq!(fd.free_path(original, |p| p.exists()), dir.path().join("Trash/files/my notes%.txt.2"));
+        assert!(fd.write_info(&stored, original, SystemTime::now()).is_err());
+
+        fd.note_size(&stored, 4096).unwrap();
+        fd.note_size(&stored, 8192).unwrap();
+
+        let sizes = fs::read_to_string(dir.path().join("Trash/directorysizes")).unwrap();
+        assert!(sizes.starts_with("8192 ") && sizes.ends_with(" my%20notes%25.txt\n"));
+
+        fd.remove_info(&stored).unwrap();
+        assert!(!fd.info_path(&stored).exists());
+        assert_eq!(fs::read_to_string(dir.path().join("Trash/directorysizes")).unwrap(), "");
+        assert_eq!(parse_trashinfo("Path=/x\n"), None);
+    }
+}
@@ -0,0 +1,216 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use colorize::colorize;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{move_files, secure, History, HistoryPairs, TrashError, TrashResult};
+
+/// Bumped, along with a step in `MIGRATIONS`, whenever what's saved for an item changes.
+/// 1 is the single array every history file was before it had a version.
+pub const VERSION: u32 = 2;
+
+/// Brings one operation, as plain JSON, from the version at its index + 1 to the next
+const MIGRATIONS: [fn(&mut Value); VERSION as usize - 1] = [v1_to_v2];
+
+/// The first line of the history file
+#[derive(Serialize, Deserialize, Debug)]
+struct Header {
+    version: u32
+}
+
+/// Reads the history file, one operation per line after the header, upgrading older versions
+/// on the way. The second value says whether the file has to be rewritten before anything can
+/// be appended to it: it is from an older version, or a crash cut off its last line.
+pub fn load(path: &Path) -> TrashResult<(History, bool)> {
+    let text = fs::read_to_string(path).map_err(|e| TrashError(format!("{}: {}", path.display(), e)))?;
+
+    if let Some(ops) = old_format(&text) {
+        return Ok((upgrade(ops, 1).map_err(|e| TrashError(format!("{}: {}", path.display(), e)))?, true))
+    }
+
+    let mut lines = text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+
+    // An empty file is a new history, anything else starts with its header
+    let version = match lines.next() {
+        Some((_, line)) => match serde_json::from_str::<Header>(line) {
+            Ok(header) => header.version,
+            Err(_) => return Err(TrashError(format!("{}: the first line isn't a history version", path.display())))
+        },
+        None => VERSION
+    };
+
+    if version > VERSION || version == 0 {
+        return Err(TrashError(format!("{} is from a newer version of trash (history version {})", path.display(), version)))
+    }
+
+    let lines: Vec<(usize, &str)> = lines.collect();
+    let mut stale = version < VERSION;
+    let mut ops = Vec::with_capacity(lines.len());
+
+    for (i, &(n, line)) in lines.iter().enumerate() {
+        match serde_json::from_str::<Value>(line) {
+            Ok(op) => ops.push(op),
+            Err(_) if i + 1 == lines.len() && !text.ends_with('\n') => {
+                warn!("{}", colorize!(Fyb->"Dropping the last operation in", b->path, b->"- it was cut off"));
+                stale = true;
+            },
+            Err(e) => return Err(TrashError(format!("{}: line {}: {}", path.display(), n + 1, e)))
+        }
+    }
+
+    let hist = upgrade(ops, version).map_err(|e| TrashError(format!("{}: {}", path.display(), e)))?;
+
+    Ok((hist, stale))
+}
+
+/// Adds operations to the end of the history file in a single write, flushed to disk if `sync`
+pub fn append(path: &Path, ops: &[HistoryPairs], sync: bool) -> TrashResult<()> {
+    if ops.is_empty() {
+        return Ok(())
+    }
+
+    let mut file = OpenOptions::new().append(true).open(path)?;
+    let mut text = String::new();
+
+    if file.metadata()?.len() == 0 {
+        text.push_str(&header());
+    }
+
+    for pairs in ops {
+        text.push_str(&serde_json::to_string(pairs)?);
+        text.push('\n');
+    }
+
+    file.write_all(text.as_bytes())?;
+
+    if sync {
+        file.sync_data()?;
+    }
+
+    Ok(())
+}
+
+/// Writes the whole history to a temporary file and moves it over the old one, so a crash
+/// leaves either the old history or the new one. With `sync` the new one is on disk before
+/// this returns.
+pub fn rewrite(path: &Path, hist: &History, sync: bool) -> TrashResult<()> {
+    let tmp = path.with_extension(format!("tmp.{}", std::process::id()));
+    let mut file = secure::create_private_file(&tmp)?;
+
+    file.write_all(header().as_bytes())?;
+
+    for pairs in hist {
+        serde_json::to_writer(&mut file, pairs)?;
+        file.write_all(b"\n")?;
+    }
+
+    if sync {
+        file.sync_all()?;
+    }
+
+    drop(file);
+    fs::rename(&tmp, path)?;
+
+    if sync {
+        move_files::sync_dir(path.parent().unwrap_or(Path::new(".")))?;
+    }
+
+    Ok(())
+}
+
+fn header() -> String {
+    format!("{}\n", serde_json::json!(Header { version: VERSION }))
+}
+
+/// The operations of a version 1 file, which was one array of them
+fn old_format(text: &str) -> Option<Vec<Value>> {
+    let Ok(Value::Array(ops)) = serde_json::from_str::<Value>(text) else {
+        return None
+    };
+
+    // A lone operation line is an array too, but of items rather than of operations
+    ops.iter()
+        .all(|op| op.as_array().is_some_and(|pairs| pairs.iter().all(Value::is_array)))
+        .then_some(ops)
+}
+
+fn upgrade(ops: Vec<Value>, version: u32) -> TrashResult<History> {
+    ops.into_iter()
+        .map(|mut op| {
+            for step in &MIGRATIONS[version as usize - 1..] {
+                step(&mut op);
+            }
+
+            Ok(serde_json::from_value(op)?)
+        })
+        .collect()
+}
+
+/// The very first files had only the two paths for an item, no details
+fn v1_to_v2(op: &mut Value) {
+    for pair in op.as_array_mut().into_iter().flatten().filter_map(Value::as_array_mut) {
+        if pair.len() == 2 {
+            pair.push(serde_json::json!({}));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HistoryPair, ItemMeta};
+    use std::path::PathBuf;
+
+    fn op(name: &str) -> HistoryPairs {
+        vec![HistoryPair(PathBuf::from("/a").join(name), PathBuf::from("/tmp/trash").join(name), ItemMeta::default())]
+    }
+
+    #[test]
+    fn test_load_append_rewrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trash-history.json");
+
+        fs::write(&path, "").unwrap();
+        append(&path, &[op("a")], false).unwrap();
+        rewrite(&path, &vec![op("a")], true).unwrap();
+        append(&path, &[op("b"), op("c")], true).unwrap();
+
+        let (hist, stale) = load(&path).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().collect::<Vec<_>>()[0], r#"{"version":2}"#);
+        assert_eq!(hist.len(), 3);
+        assert_eq!(hist[2][0].0, Path::new("/a/c"));
+        assert!(!stale);
+
+        // A line cut off by a crash is dropped, anything else wrong is an error
+        let mut text = fs::read_to_string(&path).unwrap();
+        text.push_str("[[\"/a/d\", \"/tm");
+        fs::write(&path, &text).unwrap();
+
+        assert!(matches!(load(&path), Ok((hist, true)) if hist.len() == 3));
+
+        fs::write(&path, "[[\"/a/d\"\n[]\n").unwrap();
+        assert!(load(&path).is_err());
+    }
+
+    #[test]
+    fn test_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trash-history.json");
+
+        // Version 1 is read and upgraded, but has to be rewritten
+        fs::write(&path, r#"[[["/a/b.txt", "/tmp/trash/b.txt"]], [["/a/c.txt", "/tmp/trash/c.txt", {"size": 3}]]]"#).unwrap();
+        let (hist, stale) = load(&path).unwrap();
+
+        assert!(stale);
+        assert_eq!(hist[1][0].2.size, 3);
+
+        // Nothing newer is guessed at, it could be lost on the next write
+        fs::write(&path, format!("{{\"version\":{}}}\n", VERSION + 1)).unwrap();
+        assert!(load(&path).unwrap_err().0.contains("newer version"));
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use glob::{MatchOptions, Pattern};
+use log::warn;
+
+use crate::{TrashError, TrashResult};
+
+const OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false
+};
+
+#[derive(Debug)]
+struct Rule {
+    pattern: Pattern,
+    /// `!pattern`, takes back an earlier match
+    negate: bool,
+    /// `pattern/`, only matches directories
+    dir_only: bool,
+    /// Has a slash, so is matched against the whole path rather than just the file name
+    anchored: bool
+}
+
+/// Paths that are never trashed, from `ignore` files next to the configs in gitignore syntax:
+/// `*.gpg` matches the file name anywhere, `/srv/keep` and `~/.password-store/` match from the
+/// root or home directory, `!` takes a match back and the last matching line wins.
+#[derive(Debug, Default)]
+pub struct Ignore {
+    rules: Vec<Rule>
+}
+
+impl Ignore {
+    /// Patterns from every file, in order. Missing files ignore nothing.
+    pub fn load(paths: &[PathBuf]) -> TrashResult<Self> {
+        let mut rules = vec![];
+
+        for path in paths {
+            match fs::read_to_string(path) {
+                Ok(text) => {
+                    let ignore = Self::parse(&text, dirs::home_dir().as_deref()).map_err(|e| TrashError(format!("{}: {}", path.display(), e)))?;
+                    rules.extend(ignore.rules);
+                },
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(TrashError::from(e))
+            }
+        }
+
+        Ok(Self { rules })
+    }
+
+    pub fn parse(text: &str, home: Option<&Path>) -> Result<Self, String> {
+        let mut rules = vec![];
+
+        for line in text.lines().map(str::trim_end) {
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+
+            let (negate, line) = match line.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, line.strip_prefix('\\').unwrap_or(line))
+            };
+
+            let (dir_only, line) = match line.strip_suffix('/') {
+                Some(rest) => (true, rest),
+                None => (false, line)
+            };
+
+            let pattern = match (line.strip_prefix("~/"), home) {
+                (Some(rest), Some(home)) => format!("{}/{}", Pattern::escape(&home.to_string_lossy()), rest),
+                (Some(_), None) => return Err(format!("{} needs a home directory", line)),
+                // Slashes anywhere but the start still match at any depth
+                (None, _) if line.starts_with('/') => line.to_string(),
+                (None, _) if line.contains('/') => format!("**/{}", line),
+                (None, _) => line.to_string()
+            };
+
+            let anchored = pattern.contains('/');
+            let pattern = Pattern::new(&pattern).map_err(|e| format!("{}: {}", line, e))?;
+
+            rules.push(Rule { pattern, negate, dir_only, anchored });
+        }
+
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether `path`, or any directory it is in, is ignored
+    pub fn ignored(&self, path: &Path) -> bool {
+        !self.is_empty() && path.ancestors().any(|p| self.matches(p, p != path || crate::is_dir(path)))
+    }
+
+    /// The first ignored path inside `dir`, which would go along with it if it were trashed
+    pub fn find_inside(&self, dir: &Path) -> Option<PathBuf> {
+        if self.is_empty() {
+            return None
+        }
+
+        self.search(dir, &mut crate::file_id(dir).into_iter().collect())
+    }
+
+    /// `seen` holds the device and inode of every directory searched, so one bind mounted in
+    /// several places, or looping back up the tree, is only searched once
+    fn search(&self, dir: &Path, seen: &mut HashSet<(u64, u64)>) -> Option<PathBuf> {
+        for entry in crate::move_files::read_dir(dir, true).ok()? {
+            let path = entry.path();
+            let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+
+            if self.matches(&path, is_dir) {
+                return Some(path)
+            }
+
+            if !is_dir {
+                continue
+            }
+
+            if crate::file_id(&path).is_some_and(|id| !seen.insert(id)) {
+                warn!("Not searching {} again, it is a directory already searched under another path", path.display());
+                continue
+            }
+
+            if let Some(found) = self.search(&path, seen) {
+                return Some(found)
+            }
+        }
+
+        None
+    }
+
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        let name = path.file_name().map(Path::new);
+
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| {
+                (is_dir || !rule.dir_only) && match (rule.anchored, name) {
+                    (true, _) => rule.pattern.matches_path_with(path, OPTIONS),
+                    (false, Some(name)) => rule.pattern.matches_path_with(name, OPTIONS),
+                    (false, None) => false
+                }
+            })
+            .is_some_and(|rule| !rule.negate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ignore() {
+        let dir = tempfile::tempdir().unwrap();
+        let home = dir.path();
+        let store = home.join(".password-store");
+
+        fs::create_dir_all(store.join("web")).unwrap();
+        fs::write(store.join("web/mail.txt"), "").unwrap();
+        fs::write(home.join("notes.txt"), "").unwrap();
+
+        let ignore = Ignore::parse("# secrets\n*.gpg\n!public.gpg\n~/.password-store/\n/etc/*.conf\n", Some(home)).unwrap();
+
+        assert!(ignore.ignored(Path::new("/any/where/key.gpg")));
+        assert!(!ignore.ignored(Path::new("/any/where/public.gpg")));
+        assert!(ignore.ignored(&store.join("web/mail.txt")));
+        assert!(ignore.ignored(Path::new("/etc/app.conf")));
+        assert!(!ignore.ignored(Path::new("/etc/app/app.conf")));
+        assert!(!ignore.ignored(&home.join("notes.txt")));
+
+        // A link to the store isn't the directory itself
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&store, home.join("store_link")).unwrap();
+            let ignore = Ignore::parse("store_link/\n", Some(home)).unwrap();
+
+            assert!(!ignore.ignored(&home.join("store_link")));
+        }
+
+        assert_eq!(ignore.find_inside(home), Some(store));
+        assert_eq!(ignore.find_inside(&home.join("nowhere")), None);
+
+        assert!(Ignore::parse("[", None).is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_search_skips_seen() {
+        let dir = tempfile::tempdir().unwrap();
+        let inner = dir.path().join("a/b");
+
+        fs::create_dir_all(&inner).unwrap();
+        fs::write(inner.join("key.gpg"), "").unwrap();
+
+        let ignore = Ignore::parse("*.gpg\n", None).unwrap();
+
+        // a/b as if it had already been searched under another path, like a second bind mount of it
+        let mut seen = crate::file_id(&inner).into_iter().collect();
+
+        assert_eq!(ignore.search(dir.path(), &mut seen), None);
+        assert_eq!(ignore.search(dir.path(), &mut HashSet::new()), Some(inner.join("key.gpg")));
+    }
+}
@@ -0,0 +1,174 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::move_files::{self, rename_with, MoveOptions};
+use crate::{HistoryPair, TrashResult};
+
+/// Items on their way into the trash, written down before each one moves and again once it
+/// has, so a run that dies part way leaves a record of them. Emptied once history is saved.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    /// Flush every entry to disk before going on
+    sync: bool
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+enum Entry {
+    Begin(HistoryPair),
+    Done {
+        id: String,
+        #[serde(with = "crate::paths")]
+        stored: PathBuf
+    }
+}
+
+impl Journal {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, sync: false }
+    }
+
+    pub fn set_sync(&mut self, sync: bool) {
+        self.sync = sync;
+    }
+
+    /// Before `pair.0` starts moving to `pair.1`
+    pub fn begin(&self, pair: &HistoryPair) -> TrashResult<()> {
+        self.add(&Entry::Begin(pair.clone()))
+    }
+
+    /// Once the item with `id` is all in the trash, at `stored`
+    pub fn done(&self, id: &str, stored: &Path) -> TrashResult<()> {
+        self.add(&Entry::Done { id: id.to_string(), stored: stored.to_path_buf() })
+    }
+
+    /// Every item written down since the journal was last emptied, in the order they were
+    /// started, with where it ended up and whether it got there
+    pub fn pending(&self) -> TrashResult<Vec<(HistoryPair, bool)>> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into())
+        };
+
+        let mut pending: Vec<(HistoryPair, bool)> = vec![];
+
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            match serde_json::from_str(line) {
+                Ok(Entry::Begin(pair)) => pending.push((pair, false)),
+                Ok(Entry::Done { id, stored }) => {
+                    if let Some((pair, done)) = pending.iter_mut().rfind(|(p, _)| p.2.id == id) {
+                        pair.1 = stored;
+                        *done = true;
+                    }
+                },
+                // Most likely the line being written when the run died
+                Err(e) => warn!("Ignoring a line of {}: {}", self.path.display(), e)
+            }
+        }
+
+        Ok(pending)
+    }
+
+    pub fn clear(&self) -> TrashResult<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(())
+        }
+    }
+
+    fn add(&self, entry: &Entry) -> TrashResult<()> {
+        let line = format!("{}\n", serde_json::to_string(entry)?);
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        file.write_all(line.as_bytes())?;
+
+        if self.sync {
+            file.sync_data()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Next to the history, like the lock
+pub fn journal_path(hist_path: &Path) -> PathBuf {
+    hist_path.with_file_name("trash-journal")
+}
+
+/// Moves back what a move across filesystems got into the trash before it was cut off. Every
+/// file is in at least one place during such a move, and an original is only removed once its
+/// copy is complete, so where both are left it's the original that is kept.
+pub(crate) fn put_back(stored: &Path, original: &Path, opts: &MoveOptions) -> io::Result<()> {
+    let meta = stored.symlink_metadata()?;
+
+    match original.symlink_metadata() {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => rename_with(stored, original, opts, &mut |_| {}),
+        Err(e) => Err(e),
+        Ok(orig) if meta.is_dir() && orig.is_dir() => {
+            for entry in move_files::read_dir(stored, opts.sorted)? {
+                put_back(&entry.path(), &original.join(entry.file_name()), opts)?;
+            }
+
+            fs::remove_dir(stored)
+        },
+        Ok(orig) if !meta.is_dir() && !orig.is_dir() => fs::remove_file(stored),
+        Ok(_) => Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} is in the way", original.display())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ItemMeta;
+
+    #[test]
+    fn test_pending() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal::new(journal_path(&dir.path().join("trash-history.json")));
+        let pair = |id: &str| HistoryPair(PathBuf::from("/a").join(id), PathBuf::from("/trash").join(id), ItemMeta { id: id.to_string(), ..Default::default() });
+
+        assert!(journal.pending().unwrap().is_empty());
+
+        journal.begin(&pair("abc")).unwrap();
+        journal.begin(&pair("def")).unwrap();
+        journal.done("abc", Path::new("/trash/abc.1")).unwrap();
+
+        let pending = journal.pending().unwrap();
+
+        assert_eq!(pending.len(), 2);
+        assert_eq!((pending[0].0.1.as_path(), pending[0].1), (Path::new("/trash/abc.1"), true));
+        assert!(!pending[1].1);
+
+        journal.clear().unwrap();
+
+        assert!(journal.pending().unwrap().is_empty());
+        assert!(journal.clear().is_ok());
+    }
+
+    #[test]
+    fn test_put_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let (original, stored) = (dir.path().join("d"), dir.path().join("trash/d"));
+
+        // a.txt made it across, b.txt was being copied and c.txt hadn't been started
+        fs::create_dir_all(&original).unwrap();
+        fs::create_dir_all(&stored).unwrap();
+        fs::write(original.join("b.txt"), "bbbb").unwrap();
+        fs::write(original.join("c.txt"), "c").unwrap();
+        fs::write(stored.join("a.txt"), "a").unwrap();
+        fs::write(stored.join("b.txt"), "bb").unwrap();
+
+        put_back(&stored, &original, &MoveOptions::default()).unwrap();
+
+        assert!(!stored.exists());
+        assert_eq!(fs::read_to_string(original.join("a.txt")).unwrap(), "a");
+        assert_eq!(fs::read_to_string(original.join("b.txt")).unwrap(), "bbbb");
+        assert!(original.join("c.txt").exists());
+    }
+}
@@ -0,0 +1,238 @@
+use std::ffi::OsStr;
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use colorize::colorize;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::backend::TrashBackend;
+use crate::move_files::{self, rename_with, MoveOptions};
+use crate::{paths, ItemMeta, TrashResult};
+
+/// Each item in a directory of its own named by a random UUID, with a `<uuid>.json` next to
+/// it saying where it came from. Names never collide, and what is in the trash can still be
+/// told from the trash alone if history is lost.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keyed {
+    path: PathBuf
+}
+
+/// What is known about an item without history
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Sidecar {
+    #[serde(with = "paths")]
+    pub original: PathBuf,
+    pub trashed_at: Option<SystemTime>,
+    pub size: u64
+}
+
+impl Keyed {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Every item with a sidecar, and what it says
+    pub fn scan(&self) -> TrashResult<Vec<(PathBuf, Sidecar)>> {
+        let mut found = vec![];
+
+        for key_dir in self.key_dirs()? {
+            let side = sidecar_path(&key_dir);
+
+            let sidecar: Sidecar = match fs::read_to_string(&side).map(|text| serde_json::from_str(&text)) {
+                Ok(Ok(sidecar)) => sidecar,
+                Ok(Err(e)) => {
+                    warn!("{}", colorize!(Fyb->"Skipping", b->&side, b->"-", b->e));
+                    continue
+                },
+                Err(_) => continue
+            };
+
+            if let Some(stored) = sidecar.original.file_name().map(|name| key_dir.join(name)) {
+                found.push((stored, sidecar));
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// The UUID named directories, skipping anything else that ended up in the trash
+    fn key_dirs(&self) -> TrashResult<Vec<PathBuf>> {
+        Ok(crate::backend::list_dir(&self.path)?
+            .into_iter()
+            .filter(|p| p.file_name().and_then(OsStr::to_str).is_some_and(is_uuid) && crate::is_dir(p))
+            .collect())
+    }
+
+    /// Drops an item's sidecar and directory once it has left, warning if either stays behind
+    fn forget(&self, stored: &Path) {
+        let Some(key_dir) = stored.parent().filter(|p| p.parent() == Some(&self.path)) else {
+            return
+        };
+
+        for res in [remove(&sidecar_path(key_dir), |p| fs::remove_file(p)), remove(key_dir, |p| fs::remove_dir(p))] {
+            if let Err(e) = res {
+                warn!("{}", colorize!(Fyb->"Could not clean up after", b->stored, b->"-", b->e));
+            }
+        }
+    }
+}
+
+impl TrashBackend for Keyed {
+    fn place(&self, original: &Path, taken: &dyn Fn(&Path) -> bool) -> (PathBuf, String) {
+        let name = original.file_name().unwrap_or(OsStr::new("item"));
+        let mut key_dir = self.path.join(new_uuid());
+
+        while taken(&key_dir) || taken(&sidecar_path(&key_dir)) {
+            key_dir = self.path.join(new_uuid());
+        }
+
+        (key_dir.join(name), "trash, in a directory of its own".to_string())
+    }
+
+    fn move_to_trash(&self, original: &Path, stored: &Path, meta: &ItemMeta, opts: &MoveOptions, on_copied: &mut dyn FnMut(u64)) -> TrashResult<PathBuf> {
+        let key_dir = stored.parent().unwrap_or(&self.path);
+        let sidecar = Sidecar {
+            original: original.to_path_buf(),
+            trashed_at: Some(meta.trashed_at.unwrap_or_else(SystemTime::now)),
+            size: meta.size
+        };
+
+        // Made new, so a directory or sidecar taken in the meantime fails rather than being shared
+        fs::create_dir_all(&self.path)?;
+        fs::create_dir(key_dir)?;
+
+        let written = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(sidecar_path(key_dir))
+            .and_then(|file| serde_json::to_writer(file, &sidecar).map_err(std::io::Error::from));
+
+        if let Err(e) = written.and_then(|_| rename_with(original, stored, opts, on_copied)) {
+            self.forget(stored);
+            return Err(e.into())
+        }
+
+        Ok(stored.to_path_buf())
+    }
+
+    fn restore(&self, stored: &Path, dest: &Path, meta: &ItemMeta, opts: &MoveOptions, on_copied: &mut dyn FnMut(u64)) -> TrashResult<()> {
+        crate::move_back(stored, dest, meta, opts, on_copied)?;
+        self.forget(stored);
+
+        Ok(())
+    }
+
+    fn purge(&self, stored: &Path) -> TrashResult<()> {
+        if stored.symlink_metadata().is_ok() {
+            crate::delete_permanently(stored)?;
+        }
+
+        self.forget(stored);
+
+        Ok(())
+    }
+
+    /// Whatever is in each UUID directory, sidecar or not
+    fn list(&self) -> TrashResult<Vec<PathBuf>> {
+        let mut items = vec![];
+
+        for key_dir in self.key_dirs()? {
+            items.extend(crate::backend::list_dir(&key_dir)?);
+        }
+
+        Ok(items)
+    }
+}
+
+fn sidecar_path(key_dir: &Path) -> PathBuf {
+    let mut name = key_dir.file_name().unwrap_or_default().to_os_string();
+    name.push(".json");
+
+    key_dir.with_file_name(name)
+}
+
+/// `remove` on `path`, where it already being gone is fine
+fn remove(path: &Path, remove: impl Fn(&Path) -> std::io::Result<()>) -> std::io::Result<()> {
+    match remove(&move_files::long_path(path)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(())
+    }
+}
+
+/// A random version 4 UUID, like `0f8a1c2e-5b7d-4e3f-9a1b-2c3d4e5f6a7b`
+fn new_uuid() -> String {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    let half = |n: u128| {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(n);
+        hasher.finish() as u128
+    };
+
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_nanos();
+    let mut n = half(now) << 64 | half(now.rotate_left(64));
+
+    // Version 4, variant 1
+    n = n & !(0xf << 76) | 0x4 << 76;
+    n = n & !(0x3 << 62) | 0x2 << 62;
+
+    let hex = format!("{:032x}", n);
+
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+fn is_uuid(name: &str) -> bool {
+    name.len() == 36 && name.char_indices().all(|(i, c)| match i {
+        8 | 13 | 18 | 23 => c == '-',
+        _ => c.is_ascii_hexdigit()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keyed() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let keyed = Keyed::new(tmp_dir.path().join("trash"));
+        let original = tmp_dir.path().join("a.txt");
+        let meta = ItemMeta { size: 1, ..Default::default() };
+
+        std::fs::write(&original, "a").unwrap();
+
+        let (stored, _) = keyed.place(&original, &|p| p.exists());
+        keyed.move_to_trash(&original, &stored, &meta, &MoveOptions::default(), &mut |_| {}).unwrap();
+
+        // The same name again gets a directory of its own rather than a new name
+        std::fs::write(&original, "b").unwrap();
+        let (again, _) = keyed.place(&original, &|p| p.exists());
+        keyed.move_to_trash(&original, &again, &meta, &MoveOptions::default(), &mut |_| {}).unwrap();
+
+        let key = stored.parent().unwrap().file_name().unwrap().to_str().unwrap();
+
+        assert!(is_uuid(key) && key.as_bytes()[14] == b'4');
+        assert_ne!(stored.parent(), again.parent());
+        assert_eq!((stored.file_name(), again.file_name()), (original.file_name(), original.file_name()));
+
+        // Everything can be found from the sidecars alone
+        let mut found = keyed.scan().unwrap();
+        found.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut expected = vec![stored.clone(), again.clone()];
+        expected.sort();
+
+        assert_eq!(found.iter().map(|f| f.0.clone()).collect::<Vec<_>>(), expected);
+        assert!(found.iter().all(|f| f.1.original == original && f.1.size == 1 && f.1.trashed_at.is_some()));
+        assert_eq!(keyed.list().unwrap().len(), 2);
+
+        keyed.restore(&stored, &original, &meta, &MoveOptions::default(), &mut |_| {}).unwrap();
+        keyed.purge(&again).unwrap();
+
+        assert_eq!(std::fs::read_to_string(&original).unwrap(), "a");
+        assert!(std::fs::read_dir(tmp_dir.path().join("trash")).unwrap().next().is_none());
+    }
+}
@@ -0,0 +1,4016 @@
+//! Moving files into a trash and back again, with a history of every operation.
+//!
+//! The `trash` command is a thin layer over [`Trash`]. Nothing here prints anything: progress
+//! and skipped items are reported through the `log` crate, and the few questions a trash can
+//! ask (see [`Trash::set_prompt`]) are answered no unless told otherwise.
+//!
+//! ```no_run
+//! use trash::{config::Config, Overrides, Trash};
+//!
+//! # fn main() -> trash::TrashResult<()> {
+//! let mut trash = Trash::try_new(None, &Config::default(), &Overrides::default())?;
+//!
+//! trash.remove(vec![String::from("notes.txt")])?;
+//! trash.write()?;
+//!
+//! let summary = trash.restore_last(1);
+//! trash.write()?;
+//!
+//! assert_eq!(summary.restored, 1);
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod backend;
+pub mod config;
+pub mod freedesktop;
+pub mod history;
+pub mod ignore;
+pub mod journal;
+pub mod keyed;
+pub mod lock;
+pub mod logging;
+pub mod macos;
+pub mod metrics;
+pub mod migrate;
+pub mod mounts;
+pub mod move_files;
+pub mod paths;
+pub mod plan;
+pub mod progress;
+pub mod schedule;
+pub mod secure;
+pub mod state;
+pub mod table;
+pub mod webhook;
+
+use std::{env, fs};
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::OsStr;
+use std::sync::{Condvar, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::fs::{File, FileTimes};
+use std::path::{Path, PathBuf};
+use std::io::{self, IsTerminal};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use clap::ValueEnum;
+use config::Config;
+use serde::{Serialize, Deserialize};
+use glob::{glob, Pattern};
+use log::{info, error, warn, debug};
+use colorize::colorize;
+
+use backend::TrashBackend;
+use freedesktop::FreeDesktop;
+use ignore::Ignore;
+use journal::Journal;
+use migrate::Source;
+use mounts::PerMount;
+use move_files::{rename_with, MoveOptions};
+use plan::{Action, Plan};
+use progress::{Progress, ProgressFormat};
+use state::{Rewrite, State};
+use webhook::{Event, Webhook};
+
+/// Everything worth keeping about an item besides where it came from and where it is now
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct ItemMeta {
+    /// Short letters-only handle that stays the same no matter what else is in history
+    pub id: String,
+    pub size: u64,
+    /// The directory the item was trashed from, in case it has to be recreated on restore
+    pub parent: Option<DirMeta>,
+    pub mtime: Option<SystemTime>,
+    pub atime: Option<SystemTime>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub trashed_at: Option<SystemTime>,
+    /// Who ran the command that trashed it
+    pub user: Option<String>,
+    /// Where it pointed, if it was a symlink
+    #[serde(with = "paths::option")]
+    pub link: Option<PathBuf>
+}
+
+/// Mode and ownership of a directory so it can be recreated the way it was
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct DirMeta {
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32
+}
+
+/// One trashed item: its original path, where it is in the trash and what else is known about it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HistoryPair(
+    #[serde(with = "paths")] pub PathBuf,
+    #[serde(with = "paths")] pub PathBuf,
+    #[serde(default)] pub ItemMeta
+);
+
+/// The items trashed by one command
+pub type HistoryPairs = Vec<HistoryPair>;
+/// Every operation, oldest first, as saved in the history file
+pub type History = Vec<HistoryPairs>;
+
+/// A trash directory and the history of what was moved into it. Changes to history stay in
+/// memory until [`Trash::write`].
+#[derive(Debug)]
+pub struct Trash {
+    hist: History,
+    hist_path: PathBuf,
+    /// How many operations at the start of `hist` the history file holds as they are now.
+    /// Anything after them is appended on write; None rewrites the whole file.
+    on_disk: Option<usize>,
+    /// Items being moved in that aren't in the history file yet
+    journal: Journal,
+    trash_path: PathBuf,
+    explain: bool,
+    conflict: Conflict,
+    in_trash: InTrash,
+    skip_identical: bool,
+    /// Ask once before a bulk or recursive trashing, like rm -I
+    confirm_bulk: bool,
+    /// Flush trashed items, the journal and history to disk before going on
+    sync: bool,
+    /// How files are moved and copied, `sorted` also meaning targets in name order rather
+    /// than the order given
+    moves: MoveOptions,
+    /// Stop at the first target that can't be trashed and put back the ones that were
+    strict: bool,
+    /// Trash what symlinks given as targets point to, not the links
+    follow_symlinks: bool,
+    /// What happens to targets on another filesystem
+    xdev: Xdev,
+    /// Targets that couldn't be trashed, and why
+    failed: Vec<(PathBuf, String)>,
+    /// How many targets the last remove moved into the trash
+    trashed: usize,
+    progress: Progress,
+    webhook: Option<Webhook>,
+    /// What explain mode would have done
+    plan: Plan,
+    ignore: Ignore,
+    /// Where items are kept, `trash_path` unless set otherwise, and the per-mount trashes
+    backend: PerMount,
+    /// The oldest items are purged after trashing anything that takes the trash over this
+    max_size: Option<u64>,
+    /// Answers the questions `confirm_bulk` and `Conflict::Prompt` ask, no to everything by default
+    prompt: fn(&str) -> bool
+}
+
+/// What to do when restoring an item whose original path has been taken by something new
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Conflict {
+    /// Restore next to the existing file as `<name>.restored`
+    Rename,
+    /// Ask for each conflicting item
+    Prompt,
+    /// Leave the item in the trash
+    #[default]
+    Skip
+}
+
+/// What to do with targets that are already inside the trash
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum InTrash {
+    /// Leave them where they are and say so
+    #[default]
+    Skip,
+    /// Delete them for good, along with their history
+    Delete,
+    /// Stop with an error
+    Error
+}
+
+/// What to do with targets on another filesystem than the trash, which can't just be renamed into it
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Xdev {
+    /// Copy them into the trash and delete the originals
+    #[default]
+    Copy,
+    /// Leave them where they are and say so
+    Skip,
+    /// Put them in the trash at the top of their own filesystem, `.Trash-$uid`, or copy them
+    /// where that can't be used
+    LocalTrash
+}
+
+/// How an item is picked out on the command line: by its ID or by its position in history
+#[derive(Clone, Debug, PartialEq)]
+pub enum ItemRef {
+    Id(String),
+    Index(HistIndex)
+}
+
+/// A position in the history as numbered by the view, counting from 1: `N` is a
+/// whole operation and `N:M` is a single item within it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HistIndex {
+    pub op: usize,
+    pub item: Option<usize>
+}
+
+/// Outcome of a bulk restore, printed once everything has been attempted
+#[derive(Debug, Default)]
+pub struct RestoreSummary {
+    pub restored: usize,
+    pub renamed: Vec<(PathBuf, PathBuf)>,
+    pub conflicts: Vec<PathBuf>,
+    pub identical: Vec<PathBuf>,
+    pub missing: Vec<PathBuf>,
+    pub failed: Vec<(PathBuf, TrashError)>,
+    pub skipped_ops: Vec<usize>,
+    /// Versions left in the trash because a newer one of the same path was restored
+    pub older: usize
+}
+
+#[derive(Debug)]
+pub struct TrashError(pub String);
+pub type TrashResult<T> = Result<T, TrashError>;
+
+impl TrashError {
+    pub fn new(err: &str) -> Self {
+        Self(err.to_string())
+    } 
+}
+
+impl From<std::io::Error> for TrashError {
+    fn from(value: std::io::Error) -> Self {
+        Self(value.to_string())
+    }
+}
+
+impl From<&str> for TrashError {
+    fn from(value: &str) -> Self {
+        Self(value.to_string())
+    }
+}
+
+impl From<serde_json::Error> for TrashError {
+    fn from(value: serde_json::Error) -> Self {
+        Self(value.to_string())
+    }
+}
+
+impl std::fmt::Display for TrashError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", colorize!(Frb->"trash error:", b->self.0.as_str()))
+    }
+}
+
+impl Trash {
+    /// Opens the trash at `trash_path` with the history in `hist_path`, which has to exist
+    pub fn new(hist_path: PathBuf, trash_path: PathBuf) -> TrashResult<Self> {
+        let (mut hist, stale) = history::load(&hist_path)?;
+        let assigned = assign_ids(&mut hist);
+        let on_disk = (!stale && !assigned).then_some(hist.len());
+
+        Ok(Self {
+            journal: Journal::new(journal::journal_path(&hist_path)),
+            hist_path,
+            hist,
+            on_disk,
+            backend: PerMount::new(Box::new(backend::Dir::new(trash_path.clone())), &trash_path),
+            trash_path,
+            explain: false,
+            conflict: Conflict::default(),
+            in_trash: InTrash::default(),
+            skip_identical: true,
+            confirm_bulk: false,
+            sync: false,
+            moves: MoveOptions::default(),
+            strict: false,
+            follow_symlinks: false,
+            xdev: Xdev::default(),
+            failed: vec![],
+            trashed: 0,
+            progress: Progress::default(),
+            webhook: None,
+            plan: Plan::default(),
+            ignore: Ignore::default(),
+            max_size: None,
+            prompt: |_| false
+        })
+    }
+
+    /// Opens the trash where `config` (or `root`) puts it, set up the way `config` says
+    pub fn try_new(root: Option<&Path>, config: &Config, overrides: &Overrides) -> TrashResult<Self> {
+        let (hist_path, trash_path) = resolve_paths(root, config, overrides)?;
+        let mut trash = Self::new(hist_path, trash_path)?;
+
+        trash.set_webhook(config.webhook.clone());
+        trash.set_max_size(config.max_size);
+        trash.set_confirm_bulk(config.confirm_bulk);
+        trash.set_sync(config.sync);
+        trash.set_xdev(if config.mount_trash { Xdev::LocalTrash } else { Xdev::Copy });
+
+        if config.macos_trash && !cfg!(target_os = "macos") {
+            warn!("{}", colorize!(Fyb->"Ignoring macos_trash, this isn't macOS"));
+        } else if config.macos_trash {
+            trash.set_backend(Box::new(macos::Finder));
+        } else if let Some(fd) = FreeDesktop::home().filter(|_| config.freedesktop) {
+            trash.set_backend(Box::new(fd));
+        } else if config.uuid_layout {
+            trash.set_backend(Box::new(keyed::Keyed::new(trash.trash_path.clone())));
+        }
+
+        Ok(trash)
+    }
+
+    /// Every operation in history, oldest first, each with the items it trashed
+    pub fn history(&self) -> &History {
+        &self.hist
+    }
+
+    pub fn hist_path(&self) -> &Path {
+        &self.hist_path
+    }
+
+    pub fn trash_path(&self) -> &Path {
+        &self.trash_path
+    }
+
+    /// Whether changes are only being planned, see `toggle_explain`
+    pub fn explaining(&self) -> bool {
+        self.explain
+    }
+
+    /// Refuses a trash someone else could tamper with, see `secure::check_private`
+    pub fn check_private(&self) -> TrashResult<()> {
+        let hist_dir = self.hist_path.parent().unwrap_or(Path::new("."));
+
+        for path in [hist_dir, &self.hist_path, &self.trash_path] {
+            secure::check_private(path).map_err(|e| TrashError(format!("{} (--insecure to use it anyway)", e.0)))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn undo(&mut self) -> TrashResult<()> {
+        match self.hist.len() {
+            0 => Err(TrashError::new("No history found!")),
+            len => self.undo_op(len - 1)
+        }
+    }
+
+    /// Undoes operation `entry` (counting from 1, as in history) wherever it is in history. Newer
+    /// operations that trashed the same paths, or paths inside or around them, have to be undone
+    /// first, or they'd be restored on top of each other in the wrong order.
+    pub fn undo_entry(&mut self, entry: usize) -> TrashResult<()> {
+        let op = match entry.checked_sub(1).filter(|&op| op < self.hist.len()) {
+            Some(op) => op,
+            None => return Err(TrashError(format!("No operation #{} in history", entry)))
+        };
+
+        let deps = self.dependents(op);
+
+        if deps.is_empty() {
+            return self.undo_op(op)
+        }
+
+        for &(newer, ours, theirs) in deps.iter() {
+            warn!("{}", colorize!(b->ours, Fyb->"overlaps", b->theirs, Fyb->"trashed later by", b->format!("#{}", newer + 1)));
+        }
+
+        let mut newer: Vec<usize> = deps.iter().map(|d| d.0 + 1).collect();
+        newer.dedup();
+
+        let order = newer.iter().rev().map(|n| format!("#{}", n)).collect::<Vec<_>>().join(", ");
+
+        Err(TrashError(format!(
+            "Nothing undone, newer operations touch the same paths as #{}. Undo {} first, in that order, starting with trash undo --entry {}",
+            entry,
+            order,
+            newer.last().unwrap()
+        )))
+    }
+
+    /// Items of newer operations whose original path is the same as, inside, or around one from
+    /// operation `op`, as (newer operation, path from `op`, path from the newer one)
+    fn dependents(&self, op: usize) -> Vec<(usize, &Path, &Path)> {
+        let ours = &self.hist[op];
+
+        self.hist
+            .iter()
+            .enumerate()
+            .skip(op + 1)
+            .flat_map(|(i, pairs)| pairs.iter().map(move |theirs| (i, theirs)))
+            .flat_map(|(i, theirs)| {
+                ours.iter()
+                    .filter(|o| o.0.starts_with(&theirs.0) || theirs.0.starts_with(&o.0))
+                    .map(move |o| (i, o.0.as_path(), theirs.0.as_path()))
+            })
+            .collect()
+    }
+
+    fn undo_op(&mut self, op: usize) -> TrashResult<()> {
+        let last = self.hist.remove(op);
+        self.on_disk = None;
+
+        let mut unresolved: Vec<HistoryPair> = Vec::with_capacity(last.len());
+
+        self.progress.start(last.len(), last.iter().map(|p| p.2.size).sum());
+
+        for l in last {
+            let HistoryPair(old, new, meta) = l;
+
+            self.progress.item(&new, meta.size);
+
+            info!("{}", colorize!(b->"Moving", Fgb->&new, b->"to", Fgb->&old));
+
+            if self.explain {
+                self.plan_restore(&old, &new, "undo");
+                continue
+            }
+
+            let dest = match self.destination(&old) {
+                Some(dest) => dest,
+                None => {
+                    error!("{}", colorize!(Frb->"Skipped", b->&old, b->"- something already exists at the original path"));
+                    unresolved.push(HistoryPair(old, new, meta));
+                    continue
+                }
+            };
+
+            if let Err(e) = self.backend.restore(&new, &dest, &meta, &self.moves, &mut |n| self.progress.copied(n)) {
+                unresolved.push(HistoryPair(old, new, meta));
+                error!("{}", e)
+            } else {
+                if dest != old {
+                    info!("{}", colorize!(Fyb->"Restored", b->&old, Fyb->"as", b->&dest));
+                }
+            }
+
+        };
+
+        self.progress.finish();
+
+        if !unresolved.is_empty() {
+            self.hist.insert(op, unresolved)
+        }
+
+        Ok(())
+    }
+
+    pub fn remove<S: AsRef<OsStr>>(&mut self, target: Vec<S>) -> TrashResult<()> {
+        let mut hist_item: HistoryPairs = vec![];
+        self.failed.clear();
+
+        // Whatever got moved before an error still has to make it into history
+        let res = self.remove_into(target, &mut hist_item);
+        self.progress.finish();
+
+        if res.is_err() && self.strict {
+            self.roll_back(&mut hist_item);
+        }
+
+        self.trashed = hist_item.len();
+        let protected = self.hist.len();
+
+        if !hist_item.is_empty() {
+            self.hist.push(hist_item);
+        }
+
+        if let (Some(max), false) = (self.max_size, self.explain) {
+            if let Err(e) = self.evict(max, protected) {
+                warn!("{}", colorize!(Fyb->"Could not make room in the trash -", b->e));
+            }
+        }
+
+        res
+    }
+
+    /// Purges the oldest items until the trash fits in `max` bytes again. Only operations
+    /// before `protected` go, so whatever was just trashed is never evicted straight away.
+    fn evict(&mut self, max: u64, protected: usize) -> TrashResult<()> {
+        let mut total: u64 = self.hist.iter().flatten().map(|p| p.2.size).sum();
+        let mut items = vec![];
+
+        let oldest = self.hist[..protected]
+            .iter()
+            .enumerate()
+            .flat_map(|(op, pairs)| pairs.iter().enumerate().map(move |(item, p)| ((op, item), p.2.size)));
+
+        for (idx, size) in oldest {
+            if total <= max {
+                break
+            }
+
+            total -= size;
+            items.push(idx);
+        }
+
+        if total > max {
+            warn!("{}", colorize!(Fyb->"The trash is still over max_size, at", b->human_size(total)));
+        }
+
+        if items.is_empty() {
+            return Ok(())
+        }
+
+        info!("{}", colorize!(Fyb->"Over max_size, purging the", b->items.len(), Fyb->"oldest item(s)"));
+
+        let (count, freed) = self.purge_items(items)?;
+
+        if let Some(hook) = &self.webhook {
+            hook.notify(&Event::Evicted { items: count, bytes_freed: freed, max_size: max });
+        }
+
+        Ok(())
+    }
+
+    fn remove_into<S: AsRef<OsStr>>(&mut self, target: Vec<S>, hist_item: &mut HistoryPairs) -> TrashResult<()> {
+        let (mut skipped, mut deleted) = (0, 0);
+        let mut targets = vec![];
+
+        // There's no reliable way to tell between normal args and globs, so all are treated as globs
+        for t in target {
+            let found: Vec<PathBuf> = match t.as_ref().to_str() {
+                Some(pattern) => glob(pattern).map_err(|e| TrashError(format!("{}: {}", pattern, e)))?.filter_map(Result::ok).collect(),
+                // Only UTF-8 can be matched as a glob, anything else has to be the exact path
+                None => Some(PathBuf::from(t.as_ref())).filter(|p| exists(p)).into_iter().collect()
+            };
+
+            for ent in found {
+                match ent {
+                    ent if ent == self.hist_path => continue,
+                    // A dangling link has nothing to follow
+                    ent if ent.is_symlink() && (!self.follow_symlinks || !ent.exists()) => targets.push(absolute_link(&ent)?),
+                    ent => targets.push(move_files::canonicalize(&ent)?)
+                }
+            }
+        }
+
+        if self.moves.sorted {
+            targets.sort();
+        }
+
+        targets.retain(|t| {
+            let reason = if self.ignore.ignored(t) {
+                "it matches the ignore file".to_string()
+            } else if let Some(inside) = is_dir(t).then(|| self.ignore.find_inside(t)).flatten() {
+                format!("{} inside it matches the ignore file", inside.display())
+            } else {
+                return true
+            };
+
+            warn!("{}", colorize!(Fyb->"Skipping", b->t, b->"-", b->&reason, b->"(--no-ignore to trash it anyway)"));
+            self.record(Action::Skip { path: t.clone(), reason });
+
+            false
+        });
+
+        let trash_dev = file_id(&self.trash_path).map(|id| id.0);
+        let device = |t: &Path| t.symlink_metadata().ok().and_then(|m| meta_id(&m, t)).map(|id| id.0);
+
+        if self.xdev == Xdev::Skip {
+            targets.retain(|t| {
+                if device(t) == trash_dev {
+                    return true
+                }
+
+                warn!("{}", colorize!(Fyb->"Skipping", b->t, b->"- it is on another filesystem than the trash (--xdev copy to trash it anyway)"));
+                self.record(Action::Skip { path: t.clone(), reason: "on another filesystem than the trash, --xdev skip".to_string() });
+
+                false
+            });
+        }
+
+        if self.confirm_bulk && !self.explain && !self.bulk_confirmed(&targets) {
+            info!("{}", colorize!(Fyb->"Nothing trashed"));
+            return Ok(())
+        }
+
+        // Sizing everything up front means walking it twice, so only when it's needed: for
+        // progress, or to check there's room for whatever has to be copied across
+        let mut sizes = Vec::with_capacity(targets.len());
+        // Bytes and files to be copied onto each filesystem, with a directory on it to check
+        let mut copies: Vec<(Option<u64>, PathBuf, u64, u64)> = vec![];
+
+        for t in targets.iter() {
+            // The backend may keep it well away from trash_path, only the directory matters here
+            let (planned, _) = self.backend.place(t, &|_| false);
+            let dest = planned.ancestors().skip(1).find(|p| exists(p)).unwrap_or(&self.trash_path).to_path_buf();
+            let dest_dev = file_id(&dest).map(|id| id.0);
+            let across = device(t) != dest_dev;
+
+            if across {
+                debug!("{}", colorize!(Fgb->t, b->"is on another filesystem than", b->&dest, b->"and will be copied"));
+            }
+
+            let (bytes, files) = if across || self.progress.enabled() { disk_usage(t) } else { (0, 0) };
+
+            if across {
+                match copies.iter_mut().find(|c| c.0 == dest_dev) {
+                    Some(copy) => {
+                        copy.2 += bytes;
+                        copy.3 += files;
+                    },
+                    None => copies.push((dest_dev, dest, bytes, files))
+                }
+            }
+
+            sizes.push(bytes);
+        }
+
+        for (_, dir, bytes, files) in copies {
+            check_space(&dir, bytes, files)?;
+        }
+
+        self.progress.start(targets.len(), sizes.iter().sum());
+
+        let total = targets.len();
+
+        for (old_path, size) in targets.into_iter().zip(sizes) {
+            self.progress.item(&old_path, size);
+
+            // Only the link goes, never what it points to
+            if !old_path.is_symlink() && self.holds_trash(&old_path) {
+                warn!("{}", colorize!(Fyb->"Skipping", b->&old_path, b->"- the trash is inside it"));
+                self.record(Action::Skip { path: old_path, reason: "the trash is inside it".to_string() });
+                continue
+            }
+
+            if let Some(trashed) = self.trash_relative(&old_path) {
+                match self.in_trash {
+                    InTrash::Skip => {
+                        warn!("{}", colorize!(Fyb->"Skipping", b->&old_path, b->"- it is already in the trash"));
+                        self.record(Action::Skip { path: old_path, reason: "already in the trash".to_string() });
+                        skipped += 1;
+                    },
+                    InTrash::Delete => {
+                        info!("{}", colorize!(Frb->"Permanently deleting", Fgb->&old_path));
+                        deleted += 1;
+
+                        if self.explain {
+                            self.record(Action::Delete { path: old_path, reason: "already in the trash, --in-trash delete".to_string() });
+                        } else {
+                            self.backend.purge(&old_path)?;
+                            self.forget(&trashed);
+                        }
+                    },
+                    InTrash::Error => {
+                        return Err(TrashError(format!("{} is already in the trash", old_path.display())))
+                    }
+                }
+
+                continue
+            }
+
+            let taken = |p: &Path| exists(p) || self.plan.claims(p);
+            let (new_path, reason) = self.backend.place(&old_path, &taken);
+
+            info!("{}", colorize!(b->"Moving", Fgb->&old_path, b->"to", Fgb->&new_path));
+
+            if self.explain {
+                self.record(Action::Move { from: old_path, to: new_path, reason });
+                continue
+            }
+
+            let mut meta = ItemMeta::read(&old_path);
+            meta.id = self.new_id(hist_item);
+
+            match self.move_in(&old_path, &new_path, &meta) {
+                Ok(new_path) => hist_item.push(HistoryPair(old_path, new_path, meta)),
+                Err(e) if self.strict => return Err(TrashError(format!("{}: {}", old_path.display(), e.0))),
+                Err(e) => {
+                    debug!("{}", colorize!(Frb->"Could not trash", b->&old_path, b->"-", b->&e.0));
+                    self.failed.push((old_path, e.0));
+                }
+            }
+        }
+
+        if skipped > 0 {
+            warn!("{}", colorize!(Fyb->"Skipped", b->skipped, b->"item(s) already in the trash"));
+        }
+
+        if deleted > 0 {
+            warn!("{}", colorize!(Frb->"Permanently deleted", b->deleted, b->"item(s) from the trash"));
+        }
+
+        if !self.failed.is_empty() {
+            return Err(TrashError(format!("{} of {} item(s) could not be trashed", self.failed.len(), total)))
+        }
+
+        Ok(())
+    }
+
+    /// Moves an item into the trash and returns where it ended up, which is `new_path` unless
+    /// the backend chose the name
+    fn move_in(&mut self, old_path: &Path, new_path: &Path, meta: &ItemMeta) -> TrashResult<PathBuf> {
+        self.journal.begin(&HistoryPair(old_path.to_path_buf(), new_path.to_path_buf(), meta.clone()))?;
+
+        // Anything at `new_path` after a failed move is then part of the item
+        let fresh = !exists(new_path);
+
+        let stored = match self.backend.move_to_trash(old_path, new_path, meta, &self.moves, &mut |n| self.progress.copied(n)) {
+            Ok(stored) => stored,
+            Err(e) => {
+                if fresh && exists(new_path) {
+                    put_back_partial(old_path, new_path, &self.moves);
+                }
+
+                return Err(e)
+            }
+        };
+
+        if self.sync {
+            sync_moved(old_path, &stored)?;
+        }
+
+        // The item is in the trash either way, recovery only needs the journal if the run dies
+        if let Err(e) = self.journal.done(&meta.id, &stored) {
+            warn!("{}", colorize!(Fyb->"Could not update the journal -", b->e));
+        }
+
+        Ok(stored)
+    }
+
+    /// Sorts out whatever a run that died while trashing left behind, going by the journal.
+    /// Items that made it into the trash are added to history as one operation, and items
+    /// caught half way across filesystems are moved back. Returns how many were added.
+    pub fn recover(&mut self) -> TrashResult<usize> {
+        let mut recovered: HistoryPairs = vec![];
+
+        for (pair, done) in self.journal.pending()? {
+            // History was saved after all, only the journal wasn't emptied
+            if self.find_by_id(&pair.2.id).is_some() {
+                continue
+            }
+
+            match (pair.0.symlink_metadata().is_ok(), pair.1.symlink_metadata().is_ok()) {
+                (original, true) if done || !original => recovered.push(pair),
+                (true, true) => match journal::put_back(&pair.1, &pair.0, &self.moves) {
+                    Ok(_) => info!("{}", colorize!(Fyb->"Rolled back", b->&pair.0, b->"- it was only part way into the trash")),
+                    Err(e) => error!("{}", colorize!(Frb->"Could not roll back", b->&pair.0, b->"-", b->e))
+                },
+                // Only `done` says where a backend that picks its own names, like Finder, put it
+                (false, false) if !done => match self.find_untracked(&pair, &recovered) {
+                    Some(stored) => {
+                        warn!("{}", colorize!(Fyb->"Found", b->&pair.0, Fyb->"in the trash at", b->&stored, Fyb->"rather than", b->&pair.1));
+                        recovered.push(HistoryPair(pair.0, stored, pair.2));
+                    },
+                    None => error!("{}", colorize!(Frb->"Lost track of", b->&pair.0, b->"- an interrupted run was moving it to", b->&pair.1, b->"but it isn't there, look for it in the trash"))
+                },
+                // Never moved, or already back out of the trash
+                _ => {}
+            }
+        }
+
+        let count = recovered.len();
+
+        if count > 0 {
+            warn!("{}", colorize!(Fyb->"Recovered", b->count, Fyb->"item(s) an interrupted run moved into the trash"));
+            self.hist.push(recovered);
+        }
+
+        Ok(count)
+    }
+
+    /// The one item in the trash that history doesn't know about which could be `pair`: named
+    /// like it and last modified at the same time
+    fn find_untracked(&self, pair: &HistoryPair, recovered: &HistoryPairs) -> Option<PathBuf> {
+        let stem = pair.0.file_stem()?.as_encoded_bytes();
+        let mtime = pair.2.mtime?;
+
+        let mut found = self.backend.list().ok()?.into_iter().filter(|p| {
+            p.file_name().is_some_and(|name| name.as_encoded_bytes().starts_with(stem))
+                && p.symlink_metadata().and_then(|m| m.modified()).ok() == Some(mtime)
+                && self.hist.iter().flatten().chain(recovered).all(|known| known.1 != *p)
+        });
+
+        match (found.next(), found.next()) {
+            (Some(stored), None) => Some(stored),
+            _ => None
+        }
+    }
+
+    /// Puts back everything an aborted --strict run moved, newest first. Anything that can't be
+    /// put back is left in `hist_item`, so it still makes it into history.
+    fn roll_back(&mut self, hist_item: &mut HistoryPairs) {
+        let mut stuck = vec![];
+
+        for pair in hist_item.drain(..).rev() {
+            match self.backend.restore(&pair.1, &pair.0, &pair.2, &self.moves, &mut |_| {}) {
+                Ok(_) => info!("{}", colorize!(Fyb->"Rolled back", b->&pair.0)),
+                Err(e) => {
+                    error!("{}", colorize!(Frb->"Could not roll back", b->&pair.0, b->"-", b->e));
+                    stuck.push(pair);
+                }
+            }
+        }
+
+        stuck.reverse();
+        *hist_item = stuck;
+    }
+
+    /// Targets the last remove couldn't trash, and why
+    pub fn failures(&self) -> &[(PathBuf, String)] {
+        &self.failed
+    }
+
+    /// How many targets the last remove moved into the trash
+    pub fn trashed(&self) -> usize {
+        self.trashed
+    }
+
+    /// Asks once, like rm -I, when more than three items or any directory would go
+    fn bulk_confirmed(&self, targets: &[PathBuf]) -> bool {
+        let dirs = targets.iter().filter(|t| is_dir(t)).count();
+
+        if targets.len() <= BULK_ITEMS && dirs == 0 {
+            return true
+        }
+
+        let recursive = if dirs > 0 { format!(", {} of them recursively", dirs) } else { String::new() };
+
+        (self.prompt)(&format!("Trash {} item(s){}?", targets.len(), recursive))
+    }
+
+    /// If `path` is somewhere inside the trash, returns the same location spelled
+    /// relative to `trash_path`. Directories are compared by device and inode rather
+    /// than by name, so going through a bind mount of the trash is caught as well.
+    fn trash_relative(&self, path: &Path) -> Option<PathBuf> {
+        let trash_id = file_id(&self.trash_path);
+
+        path.ancestors()
+            .skip(1)
+            .find(|p| match trash_id {
+                Some(id) => file_id(p) == Some(id),
+                None => *p == self.trash_path
+            })
+            .and_then(|p| path.strip_prefix(p).ok())
+            .map(|rel| self.trash_path.join(rel))
+    }
+
+    /// Whether `path` is the trash itself or one of the directories holding it
+    fn holds_trash(&self, path: &Path) -> bool {
+        match file_id(path) {
+            Some(id) => self.trash_path.ancestors().any(|p| file_id(p) == Some(id)),
+            None => self.trash_path.starts_with(path)
+        }
+    }
+
+    /// Carries out a saved plan as one operation. Nothing is done unless the plan is for this
+    /// trash and every path it touches still looks the way it did when it was made.
+    pub fn apply(&mut self, plan: &Plan) -> TrashResult<()> {
+        match &plan.trash {
+            Some(trash) if *trash == self.trash_path => {},
+            Some(trash) => return Err(TrashError(format!("The plan is for the trash at {}, not {}", trash.display(), self.trash_path.display()))),
+            None => return Err(TrashError::new("Only plans saved by trash plan can be applied"))
+        }
+
+        if plan.actions.iter().any(|a| !matches!(a, Action::Move { .. } | Action::Delete { .. } | Action::Skip { .. })) {
+            return Err(TrashError::new("Only plans saved by trash plan can be applied"))
+        }
+
+        let changed = plan.changed();
+
+        if !changed.is_empty() {
+            return Err(TrashError(format!("Nothing done, the plan is out of date: {}", changed.join(", "))))
+        }
+
+        let mut hist_item: HistoryPairs = vec![];
+
+        let res = self.apply_into(plan, &mut hist_item);
+
+        if !hist_item.is_empty() {
+            self.hist.push(hist_item);
+        }
+
+        res
+    }
+
+    fn apply_into(&mut self, plan: &Plan, hist_item: &mut HistoryPairs) -> TrashResult<()> {
+        for action in plan.actions.iter() {
+            if self.explain {
+                self.record(action.clone());
+                continue
+            }
+
+            match action {
+                Action::Move { from, to, .. } => {
+                    info!("{}", colorize!(b->"Moving", Fgb->from, b->"to", Fgb->to));
+
+                    let mut meta = ItemMeta::read(from);
+                    meta.id = self.new_id(hist_item);
+
+                    let to = self.move_in(from, to, &meta)?;
+                    hist_item.push(HistoryPair(from.clone(), to, meta));
+                },
+                Action::Delete { path, .. } => {
+                    info!("{}", colorize!(Frb->"Permanently deleting", Fgb->path));
+
+                    self.backend.purge(path)?;
+
+                    if let Some(trashed) = self.trash_relative(path) {
+                        self.forget(&trashed);
+                    }
+                },
+                Action::Skip { path, reason } => info!("{}", colorize!(Fyb->"Skipping", b->path, b->"-", b->reason)),
+                _ => unreachable!()
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves every item in another tool's trash into this one, keeping where it was trashed from
+    /// and when. The other tool's record of each item is dropped once it has moved.
+    pub fn migrate(&mut self, source: Source, dir: &Path) -> TrashResult<()> {
+        let found = source.scan(dir)?;
+
+        if found.is_empty() {
+            info!("{}", colorize!(b->"Nothing to migrate from", Fgb->dir));
+            return Ok(())
+        }
+
+        let mut hist_item: HistoryPairs = vec![];
+
+        let res = self.migrate_into(source, dir, found, &mut hist_item);
+
+        if !hist_item.is_empty() {
+            info!("{}", colorize!(b->"Migrated", b->hist_item.len(), b->"item(s) from", b->source.name()));
+            self.hist.push(hist_item);
+        }
+
+        res
+    }
+
+    /// Puts the newest version of each item matching `pattern` that `source` trashed back
+    /// where it came from, straight out of that tool's trash in `dir`. Nothing is added to or
+    /// taken from this trash's history.
+    pub fn restore_foreign(&mut self, source: Source, dir: &Path, pattern: &Pattern) -> TrashResult<RestoreSummary> {
+        let found = migrate::newest(source.scan(dir)?.into_iter().filter(|f| f.matches(pattern)).collect());
+        let mut summary = RestoreSummary::default();
+
+        for item in found {
+            if self.is_identical(&item.original, &item.stored) {
+                self.record(Action::Skip { path: item.original.clone(), reason: SKIP_IDENTICAL.to_string() });
+                summary.identical.push(item.original);
+                continue
+            }
+
+            let dest = match self.destination(&item.original) {
+                Some(dest) => dest,
+                None => {
+                    self.record(Action::Skip { path: item.original.clone(), reason: SKIP_CONFLICT.to_string() });
+                    summary.conflicts.push(item.original);
+                    continue
+                }
+            };
+
+            info!("{}", colorize!(b->"Restoring", Fgb->&item.stored, b->"to", Fgb->&dest));
+
+            if self.explain {
+                self.record(Action::Move { from: item.stored.clone(), to: dest, reason: format!("restore from {}", source.name()) });
+                continue
+            }
+
+            // Its own times and owner, the other tool kept nothing else. The directory it is
+            // in now is the other trash, not one to recreate the original's parent like.
+            let meta = ItemMeta { parent: None, ..ItemMeta::read(&item.stored) };
+            let res = move_back(&item.stored, &dest, &meta, &self.moves, &mut |_| {}).map_err(TrashError::from);
+
+            match res.and_then(|_| source.forget(dir, &item)) {
+                Ok(_) if dest != item.original => {
+                    summary.restored += 1;
+                    summary.renamed.push((item.original, dest));
+                },
+                Ok(_) => summary.restored += 1,
+                Err(e) => summary.failed.push((item.original, e))
+            }
+        }
+
+        Ok(summary)
+    }
+
+    fn migrate_into(&mut self, source: Source, dir: &Path, found: Vec<migrate::Found>, hist_item: &mut HistoryPairs) -> TrashResult<()> {
+        for item in found {
+            if item.original.file_name().is_none() {
+                warn!("{}", colorize!(Fyb->"Skipping", b->&item.stored, b->"- it has no original file name"));
+                continue
+            }
+
+            let taken = |p: &Path| exists(p) || self.plan.claims(p);
+            let (new_path, _) = self.backend.place(&item.original, &taken);
+
+            info!("{}", colorize!(b->"Moving", Fgb->&item.stored, b->"to", Fgb->&new_path));
+
+            if self.explain {
+                self.record(Action::Move { from: item.stored, to: new_path, reason: format!("migrate from {}", source.name()) });
+                continue
+            }
+
+            let mut meta = ItemMeta::read(&item.stored);
+            meta.id = self.new_id(hist_item);
+            meta.parent = item.original.parent().and_then(DirMeta::read);
+            meta.trashed_at = item.trashed_at;
+            // Whoever trashed it with the other tool isn't known
+            meta.user = None;
+
+            let new_path = self.backend.move_to_trash(&item.stored, &new_path, &meta, &self.moves, &mut |_| {})?;
+            hist_item.push(HistoryPair(item.original.clone(), new_path, meta));
+
+            source.forget(dir, &item)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn export_state(&self) -> State {
+        State {
+            version: state::VERSION,
+            trash_dir: self.trash_path.clone(),
+            operations: self.hist
+                .iter()
+                .map(|pairs| state::Operation {
+                    items: pairs
+                        .iter()
+                        .map(|HistoryPair(old, new, meta)| state::Item { original: old.clone(), trashed: new.clone(), meta: meta.clone() })
+                        .collect()
+                })
+                .collect()
+        }
+    }
+
+    /// Adds every operation in `state` after the ones already in history. Items are taken as they
+    /// are, those no longer in the trash are only warned about.
+    pub fn import_state(&mut self, state: State, rewrites: &[Rewrite]) -> TrashResult<()> {
+        let mut items = 0;
+
+        for op in state.operations {
+            let mut pairs: HistoryPairs = vec![];
+
+            for item in op.items {
+                let (old, new) = (Rewrite::apply(rewrites, &item.original), Rewrite::apply(rewrites, &item.trashed));
+                let mut meta = item.meta;
+
+                if new.symlink_metadata().is_err() {
+                    warn!("{}", colorize!(Fyb->"Importing", b->&old, b->"though", b->&new, b->"doesn't exist, it can't be restored"));
+                }
+
+                if meta.id.is_empty() || self.find_by_id(&meta.id).is_some() || pairs.iter().any(|p| p.2.id == meta.id) {
+                    meta.id = self.new_id(&pairs);
+                }
+
+                pairs.push(HistoryPair(old, new, meta));
+            }
+
+            items += pairs.len();
+
+            if !pairs.is_empty() {
+                self.hist.push(pairs);
+            }
+        }
+
+        info!("{}", colorize!(b->"Imported", b->items, b->"item(s)"));
+
+        Ok(())
+    }
+
+    /// Drops history items for something that has been deleted from the trash
+    fn forget(&mut self, trashed: &Path) {
+        self.on_disk = None;
+
+        for pairs in self.hist.iter_mut() {
+            pairs.retain(|p| !p.1.starts_with(trashed));
+        }
+
+        self.hist.retain(|pairs| !pairs.is_empty());
+    }
+
+    /// Finds the most recently trashed item whose original file name is `name`,
+    /// returning its (operation, item) index in the history.
+    pub fn find_by_name(&self, name: &str) -> Option<(usize, usize)> {
+        self.hist
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(i, pairs)| {
+                pairs
+                    .iter()
+                    .rposition(|p| p.0.file_name().is_some_and(|f| f == name))
+                    .map(|j| (i, j))
+            })
+    }
+
+    /// Finds the most recently trashed item that came from, or now lives at, `path`
+    pub fn find_by_path(&self, path: &Path) -> Option<(usize, usize)> {
+        let path = std::path::absolute(path).ok()?;
+
+        self.hist
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(i, pairs)| {
+                pairs
+                    .iter()
+                    .rposition(|p| p.0 == path || p.1 == path)
+                    .map(|j| (i, j))
+            })
+    }
+
+    pub fn original_path(&self, (op, item): (usize, usize)) -> &Path {
+        &self.hist[op][item].0
+    }
+
+    /// Whether the original path already holds exactly what's in the trash, in which
+    /// case restoring is pointless (unless we've been told to restore regardless)
+    pub fn is_identical(&self, old: &Path, new: &Path) -> bool {
+        self.skip_identical && same_content(old, new)
+    }
+
+    /// Works out where an item should go back to under the conflict policy,
+    /// or None if it has to stay in the trash.
+    pub fn destination(&self, old: &Path) -> Option<PathBuf> {
+        if !exists(old) {
+            return Some(old.to_path_buf())
+        }
+
+        let renamed = restored_name(old);
+
+        debug!("{}", colorize!(Fgb->old, b->"already exists, going by --on-conflict", b->format!("{:?}", self.conflict).to_lowercase()));
+
+        match self.conflict {
+            Conflict::Skip => None,
+            Conflict::Rename => Some(renamed),
+            Conflict::Prompt => {
+                let question = format!("{} already exists. Restore as {} instead?", old.display(), renamed.display());
+                (self.prompt)(&question).then_some(renamed)
+            }
+        }
+    }
+
+    /// Moves a single history item back to `dest` and drops it from history.
+    pub fn restore_item(&mut self, (op, item): (usize, usize), dest: &Path) -> TrashResult<()> {
+        let HistoryPair(_, new, meta) = &self.hist[op][item];
+
+        info!("{}", colorize!(b->"Moving", Fgb->new, b->"to", Fgb->dest));
+
+        if self.explain {
+            let new = new.clone();
+            self.plan_move(&new, dest, (op, item), false);
+            return Ok(())
+        }
+
+        self.backend.restore(new, dest, meta, &self.moves, &mut |n| self.progress.copied(n))?;
+
+        self.hist[op].remove(item);
+        self.on_disk = None;
+
+        if self.hist[op].is_empty() {
+            self.hist.remove(op);
+        }
+
+        Ok(())
+    }
+
+    /// Every item trashed from `path`, oldest first
+    pub fn versions(&self, path: &Path) -> Vec<(usize, usize)> {
+        let Ok(path) = std::path::absolute(path) else {
+            return vec![]
+        };
+
+        self.hist
+            .iter()
+            .enumerate()
+            .flat_map(|(i, pairs)| pairs.iter().enumerate().map(move |(j, p)| ((i, j), p)))
+            .filter(|(_, p)| p.0 == path)
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    /// Every time `query` was trashed, as `(op, item, where it is now)`. A path also matches
+    /// when it was inside a trashed directory, a bare file name matches any original with that name.
+    pub fn which(&self, query: &str) -> Vec<(usize, usize, PathBuf)> {
+        let by_name = !query.contains(std::path::is_separator);
+        let Ok(path) = std::path::absolute(query) else {
+            return vec![]
+        };
+
+        let mut found = vec![];
+
+        for (i, pairs) in self.hist.iter().enumerate() {
+            for (j, HistoryPair(old, new, _)) in pairs.iter().enumerate() {
+                if by_name && old.file_name() == Some(std::ffi::OsStr::new(query)) {
+                    found.push((i, j, new.clone()));
+                } else if let Ok(rest) = path.strip_prefix(old) {
+                    found.push((i, j, new.join(rest)));
+                }
+            }
+        }
+
+        found
+    }
+
+    /// Copies a single history item back to `dest`, leaving it in the trash and in history
+    pub fn copy_item(&mut self, (op, item): (usize, usize), dest: &Path) -> TrashResult<()> {
+        let HistoryPair(_, new, meta) = &self.hist[op][item];
+
+        info!("{}", colorize!(b->"Copying", Fgb->new, b->"to", Fgb->dest));
+
+        if self.explain {
+            let new = new.clone();
+            self.plan_move(&new, dest, (op, item), true);
+            return Ok(())
+        }
+
+        Ok(copy_back(new, dest, meta, &self.moves)?)
+    }
+
+    /// Restores the newest version of everything in the trash. Older versions of the same
+    /// paths stay, they would only conflict with the newer ones. Items that can't be
+    /// restored stay in history and are listed in the returned summary.
+    pub fn restore_all(&mut self) -> RestoreSummary {
+        let all = self.select(None, None);
+        let newest = self.newest(all.clone());
+        let older = all.len() - newest.len();
+
+        RestoreSummary { older, ..self.restore_items(newest) }
+    }
+
+    /// Restores the newest version of each original path matching `pattern`, older versions stay
+    pub fn restore_matching(&mut self, pattern: &Pattern) -> RestoreSummary {
+        self.restore_items(self.newest(self.matching(pattern)))
+    }
+
+    /// The items of `items` that nothing later in history was trashed from the same path as
+    pub fn newest(&self, items: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+        let mut seen = HashSet::new();
+        let mut newest: Vec<_> = items.into_iter().rev().filter(|&idx| seen.insert(self.original_path(idx))).collect();
+
+        newest.reverse();
+        newest
+    }
+
+    /// Restores each item on its own, skipping and reporting whatever can't go back
+    fn restore_items(&mut self, mut items: Vec<(usize, usize)>) -> RestoreSummary {
+        let mut summary = RestoreSummary::default();
+
+        items.sort_unstable();
+        self.progress.start(items.len(), items.iter().map(|&(op, item)| self.hist[op][item].2.size).sum());
+
+        // Walking backwards keeps the remaining indexes valid as items are removed
+        for (op, item) in items.into_iter().rev() {
+            let HistoryPair(old, new, meta) = &self.hist[op][item];
+
+            self.progress.item(new, meta.size);
+
+            if !exists(new) {
+                summary.missing.push(old.clone());
+                self.record(Action::Skip { path: old.clone(), reason: SKIP_MISSING.to_string() });
+                continue
+            }
+
+            if self.is_identical(old, new) {
+                summary.identical.push(old.clone());
+                self.record(Action::Skip { path: old.clone(), reason: SKIP_IDENTICAL.to_string() });
+                continue
+            }
+
+            let old = old.clone();
+
+            let dest = match self.destination(&old) {
+                Some(dest) => dest,
+                None => {
+                    self.record(Action::Skip { path: old.clone(), reason: SKIP_CONFLICT.to_string() });
+                    summary.conflicts.push(old);
+                    continue
+                }
+            };
+
+            match self.restore_item((op, item), &dest) {
+                Ok(_) if dest != old => {
+                    summary.restored += 1;
+                    summary.renamed.push((old, dest));
+                },
+                Ok(_) => summary.restored += 1,
+                Err(e) => summary.failed.push((old, e))
+            }
+        }
+
+        self.progress.finish();
+
+        summary
+    }
+
+    /// Restores the last `count` operations, newest first. An operation is only
+    /// removed from history if every one of its items made it back, otherwise
+    /// whatever was already moved is put back into the trash.
+    pub fn restore_last(&mut self, count: usize) -> RestoreSummary {
+        let mut summary = RestoreSummary::default();
+        let stop = self.hist.len().saturating_sub(count);
+
+        let ops = &self.hist[stop..];
+        self.progress.start(ops.iter().map(Vec::len).sum(), ops.iter().flatten().map(|p| p.2.size).sum());
+
+        for op in (stop..self.hist.len()).rev() {
+            self.restore_op(op, &mut summary);
+        }
+
+        self.progress.finish();
+
+        summary
+    }
+
+    /// Restores operation `entry` (counting from 1, as in history) completely or not at all,
+    /// wherever it is in history
+    pub fn restore_entry(&mut self, entry: usize) -> TrashResult<RestoreSummary> {
+        let op = match entry.checked_sub(1).filter(|&op| op < self.hist.len()) {
+            Some(op) => op,
+            None => return Err(TrashError(format!("No operation #{} in history", entry)))
+        };
+
+        let mut summary = RestoreSummary::default();
+
+        self.progress.start(self.hist[op].len(), self.hist[op].iter().map(|p| p.2.size).sum());
+        self.restore_op(op, &mut summary);
+        self.progress.finish();
+
+        Ok(summary)
+    }
+
+    /// Moves every item of `op` back, or none of them: any conflict, missing item or failed
+    /// move leaves the whole operation in the trash and in history. An item whose original
+    /// path already has identical content counts as restored, and its copy in the trash is purged.
+    fn restore_op(&mut self, op: usize, summary: &mut RestoreSummary) {
+        let pairs = &self.hist[op];
+        let skipped = summary.conflicts.len() + summary.missing.len();
+        let mut dests: Vec<(usize, PathBuf)> = Vec::with_capacity(pairs.len());
+        let mut identical = vec![];
+        let mut skips = vec![];
+
+        for (i, HistoryPair(old, new, _)) in pairs.iter().enumerate() {
+            if !exists(new) {
+                summary.missing.push(old.clone());
+                skips.push((old.clone(), SKIP_MISSING));
+                continue
+            }
+
+            if self.is_identical(old, new) {
+                summary.identical.push(old.clone());
+                identical.push(i);
+                continue
+            }
+
+            match self.destination(old) {
+                Some(dest) => dests.push((i, dest)),
+                None => {
+                    summary.conflicts.push(old.clone());
+                    skips.push((old.clone(), SKIP_CONFLICT));
+                }
+            }
+        }
+
+        if summary.conflicts.len() + summary.missing.len() > skipped {
+            summary.skipped_ops.push(op + 1);
+
+            // The whole operation stays, including the items that could have gone back
+            let others = self.hist[op].iter().filter(|p| skips.iter().all(|s| s.0 != p.0)).map(|p| p.0.clone()).collect::<Vec<_>>();
+            let reason = format!("other items of operation #{} can't be restored", op + 1);
+
+            for (path, reason) in skips.into_iter().map(|(p, r)| (p, r.to_string())).chain(others.into_iter().map(|p| (p, reason.clone()))) {
+                self.record(Action::Skip { path, reason });
+            }
+
+            return
+        }
+
+        let mut moved: Vec<(usize, &Path)> = Vec::with_capacity(dests.len());
+        let mut failure = None;
+
+        for (i, dest) in dests.iter() {
+            let pair = &pairs[*i];
+
+            info!("{}", colorize!(b->"Moving", Fgb->&pair.1, b->"to", Fgb->dest));
+
+            if self.explain {
+                let why = if pair.0 == *dest { "restore".to_string() } else { format!("restore, {}", RENAMED) };
+                self.plan.push(Action::Move { from: pair.1.clone(), to: dest.clone(), reason: why });
+                continue
+            }
+
+            self.progress.item(&pair.1, pair.2.size);
+
+            match self.backend.restore(&pair.1, dest, &pair.2, &self.moves, &mut |n| self.progress.copied(n)) {
+                Ok(_) => moved.push((*i, dest)),
+                Err(e) => {
+                    failure = Some((pair.0.clone(), e));
+                    break
+                }
+            }
+        }
+
+        if let Some(failed) = failure {
+            let mut relocated = vec![];
+
+            for (i, dest) in moved {
+                let pair = &pairs[i];
+
+                match self.backend.move_to_trash(dest, &pair.1, &pair.2, &self.moves, &mut |_| {}) {
+                    // The backend chose another name, so history has to follow it there
+                    Ok(stored) if stored != pair.1 => {
+                        warn!("{}", colorize!(Fyb->"Rolled back", b->dest, Fyb->"to", b->&stored, Fyb->"instead of", b->&pair.1));
+                        relocated.push((i, stored));
+                    },
+                    Ok(_) => {},
+                    Err(e) => error!("{}", colorize!(Frb->"trash error:", b->"could not roll back", b->dest, b->e.0.as_str()))
+                }
+            }
+
+            for (i, stored) in relocated {
+                self.hist[op][i].1 = stored;
+                self.on_disk = None;
+            }
+
+            summary.failed.push(failed);
+            summary.skipped_ops.push(op + 1);
+            return
+        }
+
+        summary.restored += dests.len();
+        summary.renamed.extend(
+            dests
+                .into_iter()
+                .filter(|(i, dest)| pairs[*i].0 != *dest)
+                .map(|(i, dest)| (pairs[i].0.clone(), dest))
+        );
+
+        // Already back as far as anyone can tell, the copy in the trash only stays if it can't be purged
+        let mut kept = vec![];
+
+        for i in identical {
+            let pair = self.hist[op][i].clone();
+
+            if self.explain {
+                self.record(Action::Delete { path: pair.1, reason: SKIP_IDENTICAL.to_string() });
+                continue
+            }
+
+            if let Err(e) = self.backend.purge(&pair.1) {
+                warn!("{}", colorize!(Fyb->"Could not purge", b->&pair.1, b->"-", b->e));
+                kept.push(pair);
+            }
+        }
+
+        if !self.explain {
+            self.hist.remove(op);
+            self.on_disk = None;
+
+            if !kept.is_empty() {
+                self.hist.insert(op, kept);
+            }
+        }
+    }
+
+    /// An ID not used by anything in history or in the operation being built
+    fn new_id(&self, pending: &HistoryPairs) -> String {
+        loop {
+            let id = random_id();
+
+            if self.find_by_id(&id).is_none() && pending.iter().all(|p| p.2.id != id) {
+                return id
+            }
+        }
+    }
+
+    pub fn find_by_id(&self, id: &str) -> Option<(usize, usize)> {
+        self.hist
+            .iter()
+            .enumerate()
+            .find_map(|(i, pairs)| pairs.iter().position(|p| p.2.id == id).map(|j| (i, j)))
+    }
+
+    /// Turns an item reference into (operation, item) indexes, failing if nothing matches
+    pub fn resolve(&self, item: &ItemRef) -> TrashResult<Vec<(usize, usize)>> {
+        match item {
+            ItemRef::Id(id) => match self.find_by_id(id) {
+                Some(idx) => Ok(vec![idx]),
+                None => Err(TrashError(format!("No item with ID {} in history", id)))
+            },
+            ItemRef::Index(idx) => self.resolve_index(*idx)
+        }
+    }
+
+    pub fn resolve_index(&self, idx: HistIndex) -> TrashResult<Vec<(usize, usize)>> {
+        let op = idx.op - 1;
+
+        let pairs = match self.hist.get(op) {
+            Some(pairs) => pairs,
+            None => return Err(TrashError(format!("No operation #{} in history", idx.op)))
+        };
+
+        match idx.item {
+            Some(item) if item <= pairs.len() => Ok(vec![(op, item - 1)]),
+            Some(item) => Err(TrashError(format!("Operation #{} has no item {}", idx.op, item))),
+            None => Ok((0..pairs.len()).map(|item| (op, item)).collect())
+        }
+    }
+
+    /// Every item whose original path matches `pattern`. Patterns without a slash
+    /// are matched against the file name only.
+    pub fn matching(&self, pattern: &Pattern) -> Vec<(usize, usize)> {
+        self.hist
+            .iter()
+            .enumerate()
+            .flat_map(|(i, pairs)| pairs.iter().enumerate().map(move |(j, p)| (i, j, p)))
+            .filter(|(_, _, p)| matches_original(pattern, &p.0))
+            .map(|(i, j, _)| (i, j))
+            .collect()
+    }
+
+    pub fn trashed_path(&self, (op, item): (usize, usize)) -> &Path {
+        &self.hist[op][item].1
+    }
+
+    /// Deletes items from the trash for good and drops them from history
+    pub fn purge(&mut self, items: Vec<(usize, usize)>) -> TrashResult<()> {
+        let (count, freed) = self.purge_items(items)?;
+
+        match &self.webhook {
+            Some(hook) if !self.explain && freed > hook.purge_over => hook.notify(&Event::Purged { items: count, bytes_freed: freed }),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// `purge` without telling the webhook, returning how many items went and the bytes freed
+    fn purge_items(&mut self, mut items: Vec<(usize, usize)>) -> TrashResult<(usize, u64)> {
+        items.sort_unstable();
+        items.dedup();
+
+        let (count, mut freed) = (items.len(), 0);
+
+        // Back to front so the indexes still to come stay valid
+        for (op, item) in items.into_iter().rev() {
+            let HistoryPair(_, path, meta) = &self.hist[op][item];
+
+            info!("{}", colorize!(Frb->"Permanently deleting", Fgb->path));
+
+            if self.explain {
+                self.plan.push(Action::Delete { path: path.clone(), reason: format!("purge, trashed from {}", self.hist[op][item].0.display()) });
+                continue
+            }
+
+            if path.symlink_metadata().is_ok() {
+                freed += meta.size;
+            }
+
+            self.backend.purge(path)?;
+            self.hist[op].remove(item);
+            self.on_disk = None;
+        }
+
+        self.hist.retain(|pairs| !pairs.is_empty());
+
+        Ok((count, freed))
+    }
+
+    pub fn gauges(&self) -> metrics::Gauges {
+        let now = SystemTime::now();
+        let items = || self.hist.iter().flatten();
+
+        metrics::Gauges {
+            items: self.item_count(),
+            bytes: items().map(|pair| pair.2.size).sum(),
+            oldest_age_secs: items()
+                .filter_map(|pair| now.duration_since(pair.2.trashed_at?).ok())
+                .max()
+                .map(|age| age.as_secs())
+        }
+    }
+
+    /// Original paths of items in history that are gone from the trash, so can't be restored
+    pub fn unrestorable(&self) -> Vec<&Path> {
+        self.hist
+            .iter()
+            .flatten()
+            .filter(|pair| pair.1.symlink_metadata().is_err())
+            .map(|pair| pair.0.as_path())
+            .collect()
+    }
+
+    pub fn item_count(&self) -> usize {
+        self.hist.iter().map(|pairs| pairs.len()).sum()
+    }
+
+    /// Items and bytes per top-level origin directory, biggest first
+    pub fn usage_by_origin(&self) -> Vec<(PathBuf, usize, u64)> {
+        let mut usage: Vec<(PathBuf, usize, u64)> = vec![];
+
+        // Sorted, so any directory comes straight before the ones inside it
+        for (dir, pairs) in self.by_dir() {
+            let bytes = pairs.iter().map(|p| p.2.size).sum();
+
+            match usage.last_mut() {
+                Some((top, count, size)) if dir.starts_with(&*top) => {
+                    *count += pairs.len();
+                    *size += bytes;
+                },
+                _ => usage.push((dir, pairs.len(), bytes))
+            }
+        }
+
+        usage.sort_by_key(|u| std::cmp::Reverse(u.2));
+        usage
+    }
+
+    /// Every item keyed by the parent of its original path, oldest first within each directory
+    pub fn by_dir(&self) -> BTreeMap<PathBuf, Vec<&HistoryPair>> {
+        let mut groups: BTreeMap<PathBuf, Vec<&HistoryPair>> = BTreeMap::new();
+
+        for pair in self.hist.iter().flatten() {
+            let dir = pair.0.parent().unwrap_or(Path::new("/")).to_path_buf();
+            groups.entry(dir).or_default().push(pair);
+        }
+
+        groups
+    }
+
+    /// Items matching `pattern` (if any) that were trashed more than `older_than` ago (if given),
+    /// oldest first. Items from before trash times were recorded never count as older.
+    pub fn select(&self, pattern: Option<&Pattern>, older_than: Option<Duration>) -> Vec<(usize, usize)> {
+        let now = SystemTime::now();
+
+        let items = match pattern {
+            Some(pattern) => self.matching(pattern),
+            None => self.hist
+                .iter()
+                .enumerate()
+                .flat_map(|(i, pairs)| (0..pairs.len()).map(move |j| (i, j)))
+                .collect()
+        };
+
+        items
+            .into_iter()
+            .filter(|&(i, j)| match (older_than, self.hist[i][j].2.trashed_at) {
+                (None, _) => true,
+                (Some(age), Some(at)) => now.duration_since(at).is_ok_and(|d| d > age),
+                (Some(_), None) => false
+            })
+            .collect()
+    }
+
+    /// Saves history. Operations added since the last write are appended to the history file
+    /// one per line; after anything was undone, restored or purged the file is compacted. The
+    /// journal is emptied once everything in it is in history.
+    pub fn write(&mut self) -> TrashResult<()> {
+        match self.on_disk {
+            Some(saved) if saved <= self.hist.len() => history::append(&self.hist_path, &self.hist[saved..], self.sync)?,
+            _ => history::rewrite(&self.hist_path, &self.hist, self.sync)?
+        }
+
+        self.on_disk = Some(self.hist.len());
+        self.journal.clear()
+    }
+
+    /// Adds to the plan, only while explaining
+    pub fn record(&mut self, action: Action) {
+        if self.explain {
+            self.plan.push(action);
+        }
+    }
+
+    pub fn plan(&self) -> &Plan {
+        &self.plan
+    }
+
+    /// Hands over what explain mode planned, leaving an empty plan behind
+    pub fn take_plan(&mut self) -> Plan {
+        std::mem::take(&mut self.plan)
+    }
+
+    /// Plans what restoring `new` to `old` would do under the conflict policy, without asking anything
+    fn plan_restore(&mut self, old: &Path, new: &Path, why: &str) {
+        if !exists(old) && !self.plan.claims(old) {
+            self.plan_parent(old);
+            self.record(Action::Move { from: new.to_path_buf(), to: old.to_path_buf(), reason: why.to_string() });
+            return
+        }
+
+        let dest = restored_name(old);
+
+        match self.conflict {
+            Conflict::Skip => self.record(Action::Skip { path: old.to_path_buf(), reason: SKIP_CONFLICT.to_string() }),
+            Conflict::Rename | Conflict::Prompt => {
+                let ask = if self.conflict == Conflict::Prompt { ", if confirmed" } else { "" };
+
+                self.plan_parent(&dest);
+                self.record(Action::Move { from: new.to_path_buf(), to: dest, reason: format!("{}, {}{}", why, RENAMED, ask) });
+            }
+        }
+    }
+
+    /// Plans taking a history item out of the trash to an already decided `dest`
+    fn plan_move(&mut self, new: &Path, dest: &Path, idx: (usize, usize), copy: bool) {
+        let why = if copy { "restore --keep" } else { "restore" };
+        let reason = if self.original_path(idx) == dest { why.to_string() } else { format!("{}, {}", why, RENAMED) };
+        let (from, to) = (new.to_path_buf(), dest.to_path_buf());
+
+        self.plan_parent(dest);
+        self.record(if copy { Action::Copy { from, to, reason } } else { Action::Move { from, to, reason } });
+    }
+
+    /// Directories `recreate_parent` would have to make for `dest`
+    fn plan_parent(&mut self, dest: &Path) {
+        if let Some(parent) = dest.parent().filter(|p| !p.exists() && !self.plan.claims(p)) {
+            self.record(Action::CreateDir { path: parent.to_path_buf(), reason: format!("it no longer exists, {} goes in it", dest.display()) });
+        }
+    }
+
+    pub fn toggle_explain(&mut self) {
+        self.explain = true;
+    }
+
+    pub fn set_conflict(&mut self, conflict: Conflict) {
+        self.conflict = conflict;
+    }
+
+    pub fn set_in_trash(&mut self, in_trash: InTrash) {
+        self.in_trash = in_trash;
+    }
+
+    pub fn set_confirm_bulk(&mut self, confirm_bulk: bool) {
+        self.confirm_bulk = confirm_bulk;
+    }
+
+    pub fn set_sync(&mut self, sync: bool) {
+        self.sync = sync;
+        self.journal.set_sync(sync);
+    }
+
+    /// Targets and directory contents in name order, so logs, history and plans come out the
+    /// same on every run
+    pub fn set_sorted(&mut self, sorted: bool) {
+        self.moves.sorted = sorted;
+    }
+
+    /// Sets the size of the buffer files are copied through between filesystems
+    pub fn set_buffer_size(&mut self, size: usize) {
+        self.moves.buffer_size = size.max(1);
+    }
+
+    /// How many more times a move is tried when a file is briefly held by something else
+    pub fn set_retries(&mut self, retries: usize) {
+        self.moves.retries = retries;
+    }
+
+    /// What to do with FIFOs, sockets and devices that have to be copied to another filesystem
+    pub fn set_special_files(&mut self, special: move_files::Special) {
+        self.moves.special = special;
+    }
+
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    pub fn set_follow_symlinks(&mut self, follow: bool) {
+        self.follow_symlinks = follow;
+    }
+
+    pub fn set_skip_identical(&mut self, skip: bool) {
+        self.skip_identical = skip;
+    }
+
+    pub fn set_progress(&mut self, format: Option<ProgressFormat>) {
+        self.progress = Progress::new(format);
+    }
+
+    pub fn set_ignore(&mut self, ignore: Ignore) {
+        self.ignore = ignore;
+    }
+
+    pub fn set_webhook(&mut self, webhook: Option<Webhook>) {
+        self.webhook = webhook;
+    }
+
+    /// Keeps newly trashed items somewhere other than `trash_path`, like the freedesktop trash.
+    /// Items already in history are handed to it as well when restored or purged.
+    pub fn set_backend(&mut self, backend: Box<dyn TrashBackend>) {
+        self.backend.set_home(backend);
+    }
+
+    pub fn set_xdev(&mut self, xdev: Xdev) {
+        self.xdev = xdev;
+        self.backend.set_enabled(xdev == Xdev::LocalTrash);
+    }
+
+    pub fn set_prompt(&mut self, prompt: fn(&str) -> bool) {
+        self.prompt = prompt;
+    }
+
+    pub fn set_max_size(&mut self, max_size: Option<u64>) {
+        self.max_size = max_size;
+    }
+
+}
+
+/// Where config.toml and, unless it says otherwise, the history live. Everything is under
+/// `root` when there is one, so the real trash is never touched.
+pub fn config_dir(root: Option<&Path>) -> PathBuf {
+    match root {
+        Some(root) => root.join("config"),
+        None => PathBuf::from_iter([dirs::home_dir().unwrap(), PathBuf::from(".config/trash/")])
+    }
+}
+
+pub fn history_path(root: Option<&Path>) -> PathBuf {
+    config_dir(root).join("trash-history.json")
+}
+
+/// Settings for the trash under `root`, or the usual one
+pub fn load_config(root: Option<&Path>) -> TrashResult<Config> {
+    Config::load_layers(&config::layers(&config_dir(root), root.is_some()))
+}
+
+/// Where this run keeps the trash and its history, from `--trash-dir` and `--history` or
+/// `TRASH_DIR` and `TRASH_HISTORY`
+#[derive(Debug, Default)]
+pub struct Overrides {
+    pub trash_dir: Option<PathBuf>,
+    pub history: Option<PathBuf>
+}
+
+/// Overrides win over config, which wins over the defaults
+pub fn resolve_paths(root: Option<&Path>, config: &Config, overrides: &Overrides) -> TrashResult<(PathBuf, PathBuf)> {
+    let hist_path = overrides.history.clone()
+        .or_else(|| config.history.clone())
+        .unwrap_or_else(|| history_path(root));
+    let hist_dir = hist_path.parent().unwrap_or(Path::new("."));
+
+    if !hist_dir.try_exists()? {
+        secure::create_private_dir(hist_dir)?;
+    }
+
+    if !hist_path.try_exists().unwrap() {
+        secure::create_private_file(&hist_path)?;
+    }
+
+    let (mut trash_dir, in_temp) = match (overrides.trash_dir.clone().or_else(|| config.trash_dir.clone()), root) {
+        (Some(dir), _) => (dir, false),
+        (None, Some(root)) => (root.join("trash"), false),
+        (None, None) => (env::temp_dir().join("trash/"), true)
+    };
+
+    // Keeps the trailing slash the default path has always had
+    trash_dir.push("");
+
+    if !trash_dir.try_exists()? {
+        secure::create_private_dir(&trash_dir)?;
+
+        // Most likely meaning the computer has restarted and /tmp has been cleared, taking
+        // everything history lists with it. A trash dir set anywhere else is just new, and
+        // the history may well be for items kept somewhere else.
+        if in_temp {
+            secure::create_private_file(&hist_path)?;
+        }
+    }
+
+    Ok((hist_path, trash_dir))
+}
+
+impl ItemMeta {
+    /// Captures everything worth keeping about `path` before it's moved into the trash.
+    fn read(path: &Path) -> Self {
+        let meta = path.symlink_metadata().ok();
+
+        #[cfg(unix)]
+        let (uid, gid) = {
+            use std::os::unix::fs::MetadataExt;
+            (meta.as_ref().map(|m| m.uid()), meta.as_ref().map(|m| m.gid()))
+        };
+
+        #[cfg(not(unix))]
+        let (uid, gid) = (None, None);
+
+        Self {
+            // Handed out by the Trash, which knows which IDs are taken
+            id: String::new(),
+            size: disk_size(path),
+            parent: path.parent().and_then(DirMeta::read),
+            mtime: meta.as_ref().and_then(|m| m.modified().ok()),
+            atime: meta.as_ref().and_then(|m| m.accessed().ok()),
+            uid,
+            gid,
+            trashed_at: Some(SystemTime::now()),
+            user: current_user(),
+            link: meta.as_ref().filter(|m| m.is_symlink()).and_then(|_| fs::read_link(path).ok())
+        }
+    }
+
+    /// Hands the item back to its original owner. Only root can give files away,
+    /// so for everyone else this is a no-op unless something changed the owner.
+    #[cfg(unix)]
+    fn apply_owner(&self, path: &Path) -> io::Result<()> {
+        use std::os::unix::fs::{lchown, MetadataExt};
+
+        let meta = path.symlink_metadata()?;
+
+        if self.uid.is_some_and(|uid| uid != meta.uid()) || self.gid.is_some_and(|gid| gid != meta.gid()) {
+            lchown(path, self.uid, self.gid)?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn apply_owner(&self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Puts back the recorded timestamps. Symlinks are left alone, as setting
+    /// times on them would follow the link.
+    fn apply_times(&self, path: &Path) -> io::Result<()> {
+        if (self.mtime.is_none() && self.atime.is_none()) || path.is_symlink() {
+            return Ok(())
+        }
+
+        let mut times = FileTimes::new();
+
+        if let Some(mtime) = self.mtime {
+            times = times.set_modified(mtime);
+        }
+
+        if let Some(atime) = self.atime {
+            times = times.set_accessed(atime);
+        }
+
+        File::open(path)?.set_times(times)
+    }
+}
+
+impl DirMeta {
+    #[cfg(unix)]
+    fn read(path: &Path) -> Option<Self> {
+        use std::os::unix::fs::MetadataExt;
+
+        let meta = fs::metadata(path).ok()?;
+
+        Some(Self {
+            mode: meta.mode() & 0o7777,
+            uid: meta.uid(),
+            gid: meta.gid()
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn read(_path: &Path) -> Option<Self> {
+        None
+    }
+
+    #[cfg(unix)]
+    fn apply(&self, path: &Path) -> io::Result<()> {
+        use std::os::unix::fs::{chown, PermissionsExt};
+
+        fs::set_permissions(path, fs::Permissions::from_mode(self.mode))?;
+
+        // Handing a directory to another user needs privileges we may not have
+        if let Err(e) = chown(path, Some(self.uid), Some(self.gid)) {
+            debug!("{}", colorize!(Fyb->"Could not restore ownership of", b->path, e));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn apply(&self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Moves a trashed item to `dest`, recreating its parent directory first if it has
+/// since been removed.
+fn move_back(new: &Path, dest: &Path, meta: &ItemMeta, opts: &MoveOptions, on_copied: &mut dyn FnMut(u64)) -> io::Result<()> {
+    recreate_parent(dest, meta)?;
+    rename_with(new, dest, opts, on_copied)?;
+    apply_meta(dest, meta);
+
+    Ok(())
+}
+
+/// Same as `move_back`, except the trashed item stays where it is
+fn copy_back(new: &Path, dest: &Path, meta: &ItemMeta, opts: &MoveOptions) -> io::Result<()> {
+    recreate_parent(dest, meta)?;
+    move_files::copy(new, dest, opts)?;
+    apply_meta(dest, meta);
+
+    Ok(())
+}
+
+fn recreate_parent(dest: &Path, meta: &ItemMeta) -> io::Result<()> {
+    if let Some(parent) = dest.parent().filter(|p| !p.exists()) {
+        info!("{}", colorize!(b->"Recreating", Fgb->parent));
+        fs::create_dir_all(parent)?;
+
+        if let Some(dir) = &meta.parent {
+            dir.apply(parent)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn apply_meta(dest: &Path, meta: &ItemMeta) {
+    if let Err(e) = meta.apply_owner(dest) {
+        debug!("{}", colorize!(Fyb->"Could not restore ownership of", b->dest, e));
+    }
+
+    if let Err(e) = meta.apply_times(dest) {
+        debug!("{}", colorize!(Fyb->"Could not restore timestamps of", b->dest, e));
+    }
+}
+
+/// Whether stdout is a terminal known to understand OSC 8 hyperlinks. FORCE_HYPERLINK=1
+/// or 0 overrides the guess either way.
+pub fn hyperlinks_enabled() -> bool {
+    static ENABLED: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+
+    *ENABLED.get_or_init(|| {
+        if let Ok(force) = env::var("FORCE_HYPERLINK") {
+            return force != "0"
+        }
+
+        if !io::stdout().is_terminal() {
+            return false
+        }
+
+        let var = |name: &str| env::var(name).unwrap_or_default();
+
+        matches!(var("TERM_PROGRAM").as_str(), "iTerm.app" | "WezTerm" | "vscode" | "ghostty")
+            || var("VTE_VERSION").parse::<u32>().is_ok_and(|v| v >= 5000)
+            || ["KITTY_WINDOW_ID", "WT_SESSION", "KONSOLE_VERSION"].iter().any(|v| env::var(v).is_ok())
+            || ["kitty", "foot", "alacritty", "wezterm"].iter().any(|t| var("TERM").contains(t))
+    })
+}
+
+/// The path as text, wrapped in an OSC 8 hyperlink to its containing directory when
+/// the terminal supports it, so clicking it opens the folder.
+pub fn link(path: &Path) -> String {
+    if !hyperlinks_enabled() {
+        return path.display().to_string()
+    }
+
+    let dir = path.parent().unwrap_or(path);
+
+    format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", file_url(dir), path.display())
+}
+
+/// `file://` URL for an absolute path, percent-encoding anything outside the unreserved set
+pub fn file_url(path: &Path) -> String {
+    let mut url = String::from("file://");
+
+    for &byte in path.as_os_str().as_encoded_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => url.push(byte as char),
+            _ => url.push_str(&format!("%{:02X}", byte))
+        }
+    }
+
+    url
+}
+
+/// Parses ages like `90d`, `12h`, `30m`, `45s` or `2w`
+pub fn parse_age(age: &str) -> Result<Duration, String> {
+    let err = || format!("{} isn't an age like 30d, 12h, 2w", age);
+
+    let unit = age.chars().last().ok_or_else(err)?;
+    let count: u64 = age[..age.len() - unit.len_utf8()].parse().map_err(|_| err())?;
+
+    let secs = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return Err(err())
+    };
+
+    count.checked_mul(secs).map(Duration::from_secs).ok_or_else(err)
+}
+
+/// Parses sizes like `4096`, `512K`, `4M` or `1G` (powers of 1024)
+pub fn parse_size(size: &str) -> Result<usize, String> {
+    let err = || format!("{} isn't a size like 512K, 4M, 1G", size);
+
+    let digits = size.find(|c: char| !c.is_ascii_digit()).unwrap_or(size.len());
+    let count: usize = size[..digits].parse().map_err(|_| err())?;
+
+    let unit = match size[digits..].to_uppercase().trim_end_matches("IB").trim_end_matches('B') {
+        "" => 1,
+        "K" => 1024,
+        "M" => 1024 * 1024,
+        "G" => 1024 * 1024 * 1024,
+        _ => return Err(err())
+    };
+
+    match count.checked_mul(unit) {
+        Some(0) | None => Err(err()),
+        Some(bytes) => Ok(bytes)
+    }
+}
+
+/// Whether both are regular files with the same contents
+fn same_content(a: &Path, b: &Path) -> bool {
+    match (a.symlink_metadata(), b.symlink_metadata()) {
+        (Ok(ma), Ok(mb)) if ma.is_file() && mb.is_file() && ma.len() == mb.len() => {
+            matches!((hash_file(a), hash_file(b)), (Ok(ha), Ok(hb)) if ha == hb)
+        },
+        _ => false
+    }
+}
+
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    use sha2::{Digest, Sha256};
+
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn current_user() -> Option<String> {
+    ["USER", "LOGNAME", "USERNAME"]
+        .iter()
+        .find_map(|var| env::var(var).ok())
+        .filter(|user| !user.is_empty())
+}
+
+/// Flushes an item that has just been moved in to disk, along with the directories it left
+/// and landed in
+fn sync_moved(old_path: &Path, stored: &Path) -> TrashResult<()> {
+    move_files::sync_tree(stored)?;
+
+    for dir in [old_path.parent(), stored.parent()].into_iter().flatten() {
+        move_files::sync_dir(dir)?;
+    }
+
+    Ok(())
+}
+
+/// Gives every item loaded from an older history file an ID
+fn assign_ids(hist: &mut History) -> bool {
+    let mut seen: std::collections::HashSet<String> = hist
+        .iter()
+        .flatten()
+        .map(|p| p.2.id.clone())
+        .filter(|id| !id.is_empty())
+        .collect();
+
+    let mut assigned = false;
+
+    for pair in hist.iter_mut().flatten().filter(|p| p.2.id.is_empty()) {
+        let mut id = random_id();
+
+        while seen.contains(&id) {
+            id = random_id();
+        }
+
+        seen.insert(id.clone());
+        pair.2.id = id;
+        assigned = true;
+    }
+
+    assigned
+}
+
+/// Six random lowercase letters. Letters only, so an ID can never be mistaken for a
+/// history position like `3` or `3:2`.
+fn random_id() -> String {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_nanos());
+
+    let mut n = hasher.finish();
+
+    (0..6)
+        .map(|_| {
+            let c = (b'a' + (n % 26) as u8) as char;
+            n /= 26;
+            c
+        })
+        .collect()
+}
+
+/// More items than this at once are asked about with -I
+pub const BULK_ITEMS: usize = 3;
+
+pub const SKIP_MISSING: &str = "no longer in the trash";
+pub const SKIP_IDENTICAL: &str = "the original path already has identical content";
+pub const SKIP_CONFLICT: &str = "something already exists at the original path";
+pub const RENAMED: &str = "renamed because something already exists at the original path";
+
+/// `<path>.restored`, or `<path>.restored.N` if that's taken too
+fn restored_name(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".restored");
+
+    let mut renamed = PathBuf::from(&name);
+    let mut count = 1;
+
+    while exists(&renamed) {
+        let mut numbered = name.clone();
+        numbered.push(format!(".{}", count));
+        renamed = PathBuf::from(numbered);
+        count += 1;
+    }
+
+    renamed
+}
+
+/// `<path>.~N~` with the first N that isn't taken, like `cp --backup=numbered`. The name is
+/// kept whole, so `report.pdf` doesn't turn into `report.1` and run into an actual `report.1`.
+fn new_item_name(path: &Path, taken: impl Fn(&Path) -> bool) -> PathBuf {
+    (1..)
+        .map(|count| {
+            let mut name = path.as_os_str().to_owned();
+            name.push(format!(".~{}~", count));
+            PathBuf::from(name)
+        })
+        .find(|renamed| !taken(renamed))
+        .expect("Ran out of numbers")
+}
+
+fn delete_permanently(path: &Path) -> io::Result<()> {
+    let path = &*move_files::long_path(path);
+
+    if path.symlink_metadata()?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Fails if `dir`'s filesystem doesn't have room for `bytes` more data in `files` more
+/// files, so a big move across filesystems stops before it starts rather than half way
+#[cfg(unix)]
+fn check_space(dir: &Path, bytes: u64, files: u64) -> TrashResult<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    if files == 0 {
+        return Ok(())
+    }
+
+    let path = std::ffi::CString::new(dir.as_os_str().as_bytes()).map_err(|e| TrashError(e.to_string()))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+
+    // SAFETY: path is a valid C string and statvfs only writes into the struct we hand it
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        // Can't tell, so let the move find out the hard way
+        return Ok(())
+    }
+
+    let free_bytes = stat.f_bavail as u64 * stat.f_frsize as u64;
+
+    if bytes > free_bytes {
+        return Err(TrashError(format!(
+            "Not enough space for the trash at {}: {} to copy, {} free",
+            dir.display(), human_size(bytes), human_size(free_bytes)
+        )))
+    }
+
+    // Some filesystems (e.g. btrfs) don't have a fixed number of inodes and report none
+    if stat.f_files > 0 && files > stat.f_favail as u64 {
+        return Err(TrashError(format!(
+            "Not enough inodes for the trash at {}: {} files to copy, {} free",
+            dir.display(), files, stat.f_favail
+        )))
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_space(_dir: &Path, _bytes: u64, _files: u64) -> TrashResult<()> {
+    Ok(())
+}
+
+/// Moves back what a failed move got into the trash, so a directory is either all in the trash
+/// or all where it was. If that fails too, the journal still has it for the next run.
+fn put_back_partial(old_path: &Path, new_path: &Path, opts: &MoveOptions) {
+    match journal::put_back(new_path, old_path, opts) {
+        Ok(()) => warn!("{}", colorize!(Fyb->"Rolled back", b->old_path, b->"- it could only be moved part way into the trash")),
+        Err(e) => error!("{}", colorize!(Frb->"Could not roll back", b->old_path, b->"-", b->e))
+    }
+}
+
+/// `path` made absolute with every directory above it resolved, but not the last part, so a
+/// symlink stays the link rather than becoming what it points to
+fn absolute_link(path: &Path) -> io::Result<PathBuf> {
+    let path = std::path::absolute(path)?;
+
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => Ok(move_files::canonicalize(parent)?.join(name)),
+        _ => move_files::canonicalize(&path)
+    }
+}
+
+/// Whether an original path matches a glob, by file name only if the glob has no slashes
+fn matches_original(pattern: &Pattern, original: &Path) -> bool {
+    match (pattern.as_str().contains('/'), original.file_name()) {
+        (false, Some(name)) => pattern.matches_path(Path::new(name)),
+        (false, None) => false,
+        (true, _) => pattern.matches_path(original)
+    }
+}
+
+/// Whether anything is at `path`, a symlink pointing nowhere included
+fn exists(path: &Path) -> bool {
+    path.symlink_metadata().is_ok()
+}
+
+/// Whether `path` is a directory itself, not a symlink to one
+fn is_dir(path: &Path) -> bool {
+    path.symlink_metadata().is_ok_and(|m| m.is_dir())
+}
+
+/// Identifies a file independently of the path used to reach it
+#[cfg(unix)]
+fn file_id(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    path.metadata().ok().map(|m| (m.dev(), m.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(path: &Path) -> Option<(u64, u64)> {
+    use std::hash::{Hash, Hasher};
+    use std::collections::hash_map::DefaultHasher;
+
+    let mut hasher = DefaultHasher::new();
+    path.canonicalize().ok()?.hash(&mut hasher);
+
+    Some((0, hasher.finish()))
+}
+
+/// `file_id` from metadata that's already been read
+#[cfg(unix)]
+fn meta_id(meta: &fs::Metadata, _path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn meta_id(_meta: &fs::Metadata, path: &Path) -> Option<(u64, u64)> {
+    file_id(path)
+}
+
+/// Total size in bytes of a file, or of everything below a directory. Symlinks are not followed.
+fn disk_size(path: &Path) -> u64 {
+    disk_usage(path).0
+}
+
+/// Total size in bytes and number of files and directories, `path` included.
+/// Only sizing reads directories on several threads: huge trees take longer to list than to
+/// rename, and a move on one filesystem is a single rename that never walks the tree at all.
+fn disk_usage(path: &Path) -> (u64, u64) {
+    let meta = match path.symlink_metadata() {
+        Ok(m) => m,
+        Err(_) => return (0, 0)
+    };
+
+    if !meta.is_dir() {
+        return (meta.len(), 1)
+    }
+
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get().min(MAX_WALK_THREADS));
+
+    let queue = Mutex::new(Walk {
+        dirs: vec![move_files::long_path(path).into_owned()],
+        busy: 0,
+        seen: file_id(path).into_iter().collect()
+    });
+    let ready = Condvar::new();
+    let total = AtomicU64::new(0);
+    let entries = AtomicU64::new(1);
+
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| walk_sizes(&queue, &ready, &total, &entries));
+        }
+    });
+
+    (total.into_inner(), entries.into_inner())
+}
+
+const MAX_WALK_THREADS: usize = 8;
+
+struct Walk {
+    /// Directories still to be read
+    dirs: Vec<PathBuf>,
+    /// How many are being read right now
+    busy: usize,
+    /// Device and inode of every directory queued so far. A directory bind mounted in two places
+    /// would otherwise be counted twice, and one mounted inside itself walked forever.
+    seen: HashSet<(u64, u64)>
+}
+
+/// One worker of `disk_size`. Takes directories off the queue until it's empty and
+/// nobody is busy reading one, which would add more.
+fn walk_sizes(queue: &Mutex<Walk>, ready: &Condvar, total: &AtomicU64, entries: &AtomicU64) {
+    loop {
+        let dir = {
+            let mut state = queue.lock().unwrap();
+
+            loop {
+                if let Some(dir) = state.dirs.pop() {
+                    state.busy += 1;
+                    break dir
+                }
+
+                if state.busy == 0 {
+                    ready.notify_all();
+                    return
+                }
+
+                state = ready.wait(state).unwrap();
+            }
+        };
+
+        let mut subdirs = vec![];
+        let (mut bytes, mut count) = (0, 0);
+
+        for entry in fs::read_dir(&dir).into_iter().flatten().filter_map(|e| e.ok()) {
+            match entry.metadata() {
+                Ok(m) if m.is_dir() => subdirs.push((entry.path(), meta_id(&m, &entry.path()))),
+                Ok(m) => bytes += m.len(),
+                Err(_) => continue
+            }
+
+            count += 1;
+        }
+
+        total.fetch_add(bytes, Ordering::Relaxed);
+        entries.fetch_add(count, Ordering::Relaxed);
+
+        let mut state = queue.lock().unwrap();
+
+        for (subdir, id) in subdirs {
+            match id {
+                Some(id) if !state.seen.insert(id) => {
+                    warn!("{}", colorize!(Fyb->"Not counting", b->&subdir, b->"again - it is a directory already counted under another path"));
+                },
+                _ => state.dirs.push(subdir)
+            }
+        }
+
+        state.busy -= 1;
+        ready.notify_all();
+    }
+}
+
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+impl FromStr for HistIndex {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |n: &str| match n.parse::<usize>() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(format!("expected N or N:M with positive numbers, got {}", s))
+        };
+
+        match s.split_once(':') {
+            Some((op, item)) => Ok(Self { op: parse(op)?, item: Some(parse(item)?) }),
+            None => Ok(Self { op: parse(s)?, item: None })
+        }
+    }
+}
+
+impl FromStr for ItemRef {
+    type Err = String;
+
+    // IDs are letters only, so anything starting with a digit has to be a position
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with(|c: char| c.is_ascii_digit()) {
+            return s.parse().map(Self::Index)
+        }
+
+        if !s.is_empty() && s.chars().all(|c| c.is_ascii_lowercase()) {
+            Ok(Self::Id(s.to_string()))
+        } else {
+            Err(format!("{} is neither an item ID nor a history position", s))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::create_dir;
+    use std::io::Write;
+
+    fn trash_dir() -> (tempfile::TempDir, PathBuf) {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut tmp_path = tmp_dir.path().to_owned();
+
+        tmp_path.push("trash-history.json");
+
+        let hist_path = tmp_path.clone();
+
+        File::create(&tmp_path).unwrap();
+        tmp_path.pop();
+
+        tmp_path.push("test_dir");
+        create_dir(&tmp_path).unwrap();
+
+        for i in 0..3 {
+            tmp_path.push(format!("test{}.txt", i));
+            File::create(&tmp_path).unwrap();
+            tmp_path.pop();
+        }
+
+        tmp_path.pop();
+        tmp_path.push("trash_dir");
+        create_dir(&tmp_path).unwrap();
+    
+
+        (tmp_dir, hist_path)
+    }
+
+    #[test]
+    fn test_trash() {
+    
+        let (tmp_dir, hist_path) = trash_dir();
+        let mut trash_dir = tmp_dir.path().to_owned();
+        let mut test_dir = tmp_dir.path().to_owned();
+
+        trash_dir.push("trash_dir");
+        test_dir.push("test_dir");
+
+        let files: Vec<String> = test_dir
+            .read_dir()
+            .unwrap()
+            .map(|f|{
+                f.unwrap()
+                    .path()
+                    .to_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+
+        let mut trash = Trash::new(hist_path.to_owned(), trash_dir.to_owned()).unwrap();
+
+        trash.remove(files.clone()).unwrap();
+
+        for file in files {
+            test_dir.push(file);
+            assert!(!test_dir.exists());
+            test_dir.pop();
+        }
+    }
+
+    #[test]
+    fn test_glob() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let mut trash_dir = tmp_dir.path().to_owned();
+        let mut test_dir = tmp_dir.path().to_owned();
+
+        trash_dir.push("trash_dir");
+
+        let mut trash = Trash::new(hist_path.to_owned(), trash_dir.to_owned()).unwrap();
+
+        trash.remove(vec![test_dir.join("test_dir/*").to_string_lossy().to_string()]).unwrap();
+
+        test_dir.push("test_dir");
+
+        for i in 0..3 {
+            test_dir.push(format!("test{}.txt", i));
+            assert!(!test_dir.exists());
+            test_dir.pop();
+        }
+
+        assert!(trash.remove(vec!["["]).is_err());
+    }
+
+    #[test]
+    fn test_trash_explain() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let trash_dir = tmp_dir.path().join("trash_dir");
+        let target = tmp_dir.path().join("test_dir/test2.txt");
+
+        let mut trash = Trash::new(hist_path.clone(), trash_dir.clone()).unwrap();
+        trash.toggle_explain();
+
+        let mv_file = vec![target.to_string_lossy().to_string()];
+
+        trash.remove(mv_file).unwrap();
+
+        assert!(target.exists());
+        assert_eq!(trash.plan().actions, vec![Action::Move { from: target.clone(), to: trash_dir.join("test2.txt"), reason: "trash".to_string() }]);
+
+        // A second item with the same name collides with the first one's planned spot
+        let other = tmp_dir.path().join("other");
+        fs::create_dir(&other).unwrap();
+        fs::write(other.join("test2.txt"), "").unwrap();
+
+        trash.remove(vec![other.join("test2.txt").to_string_lossy().to_string()]).unwrap();
+
+        assert!(matches!(&trash.plan().actions[1], Action::Move { to, .. } if *to == trash_dir.join("test2.txt.~1~")));
+    }
+
+    #[test]
+    fn test_plan_apply() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let trash_dir = tmp_dir.path().join("trash_dir");
+        let test_dir = tmp_dir.path().join("test_dir");
+        let targets = vec![test_dir.join("test0.txt").to_string_lossy().to_string(), test_dir.join("test1.txt").to_string_lossy().to_string()];
+
+        let mut planner = Trash::new(hist_path.clone(), trash_dir.clone()).unwrap();
+        planner.toggle_explain();
+        planner.remove(targets).unwrap();
+
+        let mut plan = std::mem::take(&mut planner.plan);
+        plan.trash = Some(trash_dir.clone());
+        plan.snapshot();
+
+        let mut trash = Trash::new(hist_path.clone(), trash_dir.clone()).unwrap();
+
+        // Something changed since, so nothing moves at all
+        fs::write(test_dir.join("test1.txt"), "changed").unwrap();
+
+        assert!(trash.apply(&plan).is_err());
+        assert!(test_dir.join("test0.txt").exists());
+
+        plan.snapshot();
+        trash.apply(&plan).unwrap();
+
+        assert!(!test_dir.join("test0.txt").exists());
+        assert!(trash_dir.join("test1.txt").exists());
+        assert_eq!(trash.hist.len(), 1);
+        assert_eq!(trash.hist[0].len(), 2);
+    }
+
+    #[test]
+    fn test_sorted() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let test_dir = tmp_dir.path().join("test_dir");
+        let targets = vec![test_dir.join("test1.txt").to_string_lossy().to_string(), test_dir.join("test0.txt").to_string_lossy().to_string()];
+
+        let mut trash = Trash::new(hist_path, tmp_dir.path().join("trash_dir")).unwrap();
+        trash.set_sorted(true);
+        trash.remove(targets).unwrap();
+
+        let names: Vec<_> = trash.hist[0].iter().map(|p| p.0.file_name().unwrap()).collect();
+
+        assert_eq!(names, ["test0.txt", "test1.txt"]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_partial_and_strict() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let test_dir = tmp_dir.path().join("test_dir");
+        let fd = FreeDesktop::new(tmp_dir.path().join("Trash"));
+        let targets: Vec<String> = (0..3).map(|i| test_dir.join(format!("test{}.txt", i)).to_string_lossy().to_string()).collect();
+
+        // A dangling trashinfo link looks free but can't be created, so test1.txt can't be trashed
+        fs::create_dir_all(fd.dir().join("info")).unwrap();
+        std::os::unix::fs::symlink("/nowhere", fd.dir().join("info/test1.txt.trashinfo")).unwrap();
+
+        let mut trash = Trash::new(hist_path.clone(), tmp_dir.path().join("trash_dir")).unwrap();
+        trash.set_backend(Box::new(fd.clone()));
+        trash.set_sorted(true);
+        trash.set_strict(true);
+
+        assert!(trash.remove(targets.clone()).is_err());
+        assert!(trash.hist.is_empty());
+        assert!(test_dir.join("test0.txt").exists());
+        assert!(!fd.info_path(&fd.dir().join("files/test0.txt")).exists());
+
+        trash.set_strict(false);
+
+        assert!(trash.remove(targets).is_err());
+        assert_eq!(trash.hist[0].len(), 2);
+        assert_eq!(trash.failures().len(), 1);
+        assert_eq!(trash.failures()[0].0, test_dir.join("test1.txt"));
+        assert_eq!(trash.trashed(), 2);
+
+        // The next remove starts over rather than failing with the last one's failures
+        fs::write(test_dir.join("test3.txt"), "3").unwrap();
+        trash.remove(vec![test_dir.join("test3.txt").to_string_lossy().to_string()]).unwrap();
+
+        assert!(trash.failures().is_empty());
+        assert_eq!(trash.trashed(), 1);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_remove_symlink() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let test_dir = tmp_dir.path().join("test_dir");
+        let link = test_dir.join("link");
+        let arg = |p: &Path| vec![p.to_string_lossy().to_string()];
+
+        std::os::unix::fs::symlink("test0.txt", &link).unwrap();
+
+        let mut trash = Trash::new(hist_path, tmp_dir.path().join("trash_dir")).unwrap();
+        trash.remove(arg(&link)).unwrap();
+
+        let HistoryPair(old, new, meta) = &trash.hist[0][0];
+
+        assert_eq!(old, &link);
+        assert!(new.is_symlink());
+        assert_eq!(meta.link.as_deref(), Some(Path::new("test0.txt")));
+        assert!(test_dir.join("test0.txt").exists());
+
+        // Followed, it's the file that goes
+        std::os::unix::fs::symlink("test1.txt", &link).unwrap();
+        trash.set_follow_symlinks(true);
+        trash.remove(arg(&link)).unwrap();
+
+        assert_eq!(trash.hist[1][0].0, test_dir.join("test1.txt"));
+        assert!(trash.hist[1][0].2.link.is_none());
+        assert!(link.is_symlink() && !link.exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_remove_broken_symlink() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let link = tmp_dir.path().join("test_dir/dangling");
+        let arg = vec![link.to_string_lossy().to_string()];
+
+        let mut trash = Trash::new(hist_path, tmp_dir.path().join("trash_dir")).unwrap();
+
+        // Even followed there's nothing else it could mean
+        std::os::unix::fs::symlink("missing", &link).unwrap();
+        trash.set_follow_symlinks(true);
+        trash.remove(arg.clone()).unwrap();
+
+        // The one already in the trash isn't taken for a free name
+        std::os::unix::fs::symlink("missing_too", &link).unwrap();
+        trash.remove(arg).unwrap();
+
+        let (first, second) = (trash.hist[0][0].1.clone(), trash.hist[1][0].1.clone());
+
+        assert_ne!(first, second);
+        assert_eq!(fs::read_link(&first).unwrap(), Path::new("missing"));
+
+        trash.restore_last(1);
+
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new("missing_too"));
+        assert!(second.symlink_metadata().is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_xdev_skip() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let target = tmp_dir.path().join("test_dir/test0.txt");
+
+        // Needs a trash on another filesystem, a tmpfs where there is one
+        let Ok(other) = tempfile::tempdir_in("/dev/shm") else { return };
+
+        if file_id(other.path()).map(|id| id.0) == file_id(&target).map(|id| id.0) {
+            return
+        }
+
+        create_dir(other.path().join("trash_dir")).unwrap();
+
+        let mut trash = Trash::new(hist_path, other.path().join("trash_dir")).unwrap();
+        trash.set_xdev(Xdev::Skip);
+        trash.remove(vec![target.to_string_lossy().to_string()]).unwrap();
+
+        assert!(target.exists());
+        assert!(trash.hist.is_empty());
+
+        trash.set_xdev(Xdev::Copy);
+        trash.remove(vec![target.to_string_lossy().to_string()]).unwrap();
+
+        assert!(!target.exists());
+        assert_eq!(fs::read_to_string(&trash.hist[0][0].1).unwrap(), "");
+    }
+
+    /// Gets one file of a directory into the trash and then fails, like a copy across
+    /// filesystems that runs into a full disk
+    #[derive(Debug)]
+    struct HalfWay;
+
+    impl TrashBackend for HalfWay {
+        fn place(&self, original: &Path, _taken: &dyn Fn(&Path) -> bool) -> (PathBuf, String) {
+            (original.with_file_name("trashed"), "trash".to_string())
+        }
+
+        fn move_to_trash(&self, original: &Path, stored: &Path, _meta: &ItemMeta, _opts: &MoveOptions, _on_copied: &mut dyn FnMut(u64)) -> TrashResult<PathBuf> {
+            create_dir(stored)?;
+            fs::rename(original.join("test0.txt"), stored.join("test0.txt"))?;
+
+            Err(TrashError::new("No space left on device"))
+        }
+
+        fn restore(&self, _stored: &Path, _dest: &Path, _meta: &ItemMeta, _opts: &MoveOptions, _on_copied: &mut dyn FnMut(u64)) -> TrashResult<()> {
+            Ok(())
+        }
+
+        fn purge(&self, _stored: &Path) -> TrashResult<()> {
+            Ok(())
+        }
+
+        fn list(&self) -> TrashResult<Vec<PathBuf>> {
+            Ok(vec![])
+        }
+    }
+
+    #[test]
+    fn test_failed_move_is_rolled_back() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let test_dir = tmp_dir.path().join("test_dir");
+
+        let mut trash = Trash::new(hist_path, tmp_dir.path().join("trash_dir")).unwrap();
+        trash.set_backend(Box::new(HalfWay));
+
+        assert!(trash.remove(vec![test_dir.to_string_lossy().to_string()]).is_err());
+        assert_eq!(trash.failures().len(), 1);
+        assert!(trash.hist.is_empty());
+
+        assert!(!tmp_dir.path().join("trashed").exists());
+        assert!((0..3).all(|i| test_dir.join(format!("test{}.txt", i)).exists()));
+    }
+
+    /// Keeps items under a name of its own, like Finder, and can't put back test1.txt
+    #[derive(Debug)]
+    struct Renaming(PathBuf);
+
+    impl TrashBackend for Renaming {
+        fn place(&self, original: &Path, _taken: &dyn Fn(&Path) -> bool) -> (PathBuf, String) {
+            (self.0.join(original.file_name().unwrap()), "trash".to_string())
+        }
+
+        fn move_to_trash(&self, original: &Path, stored: &Path, _meta: &ItemMeta, _opts: &MoveOptions, _on_copied: &mut dyn FnMut(u64)) -> TrashResult<PathBuf> {
+            let mut name = stored.as_os_str().to_os_string();
+            name.push(" 2");
+
+            fs::rename(original, &name)?;
+
+            Ok(PathBuf::from(name))
+        }
+
+        fn restore(&self, stored: &Path, dest: &Path, _meta: &ItemMeta, _opts: &MoveOptions, _on_copied: &mut dyn FnMut(u64)) -> TrashResult<()> {
+            if dest.ends_with("test1.txt") {
+                return Err(TrashError::new("Permission denied"))
+            }
+
+            Ok(fs::rename(stored, dest)?)
+        }
+
+        fn purge(&self, stored: &Path) -> TrashResult<()> {
+            Ok(fs::remove_file(stored)?)
+        }
+
+        fn list(&self) -> TrashResult<Vec<PathBuf>> {
+            backend::list_dir(&self.0)
+        }
+    }
+
+    #[test]
+    fn test_roll_back_follows_backend() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let test_dir = tmp_dir.path().join("test_dir");
+        let trash_dir = tmp_dir.path().join("trash_dir");
+
+        let mut trash = Trash::new(hist_path, trash_dir.clone()).unwrap();
+        trash.set_backend(Box::new(Renaming(trash_dir.clone())));
+        trash.set_sorted(true);
+        trash.remove(vec![test_dir.join("test[01].txt").to_string_lossy().to_string()]).unwrap();
+
+        // test0.txt goes back, then test1.txt can't and test0.txt is rolled back under yet another name
+        let summary = trash.restore_entry(1).unwrap();
+
+        assert_eq!(summary.failed.len(), 1);
+        assert!(!test_dir.join("test0.txt").exists());
+        assert_eq!(trash.hist[0][0].1, trash_dir.join("test0.txt 2 2"));
+        assert!(trash.hist[0][0].1.exists());
+    }
+
+    #[test]
+    fn test_export_import_state() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let trash_dir = tmp_dir.path().join("trash_dir");
+        let test_dir = tmp_dir.path().join("test_dir");
+        let file = tmp_dir.path().join("state.toml");
+
+        let mut trash = Trash::new(hist_path, trash_dir.clone()).unwrap();
+        trash.remove(vec![test_dir.join("test0.txt").to_string_lossy().to_string()]).unwrap();
+        trash.export_state().save(&file).unwrap();
+
+        let moved = tmp_dir.path().join("moved");
+        let new_hist = tmp_dir.path().join("new-history.json");
+        fs::write(&new_hist, "[]").unwrap();
+
+        let mut imported = Trash::new(new_hist, moved.clone()).unwrap();
+        let rewrite = format!("{}={}", trash_dir.display(), moved.display()).parse().unwrap();
+
+        imported.import_state(State::load(&file).unwrap(), &[rewrite]).unwrap();
+
+        let HistoryPair(old, new, meta) = &imported.hist[0][0];
+
+        assert!(old.ends_with("test_dir/test0.txt"));
+        assert_eq!(*new, moved.join("test0.txt"));
+        assert_eq!(meta.id, trash.hist[0][0].2.id);
+    }
+
+    #[test]
+    fn test_freedesktop() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let trash_dir = tmp_dir.path().join("trash_dir");
+        let test_dir = tmp_dir.path().join("test_dir");
+        let fd = FreeDesktop::new(tmp_dir.path().join("Trash"));
+        let stored = fd.dir().join("files/test0.txt");
+
+        let mut trash = Trash::new(hist_path, trash_dir).unwrap();
+        trash.set_backend(Box::new(fd.clone()));
+        trash.remove(vec![test_dir.join("test0.txt").to_string_lossy().to_string()]).unwrap();
+
+        assert_eq!(trash.hist[0][0].1, stored);
+        assert!(fd.info_path(&stored).exists());
+
+        trash.undo().unwrap();
+
+        assert!(test_dir.join("test0.txt").exists());
+        assert!(!fd.info_path(&stored).exists());
+    }
+
+    #[test]
+    fn test_migrate() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let trash_dir = tmp_dir.path().join("trash_dir");
+        let other = tmp_dir.path().join("Trash");
+        let original = tmp_dir.path().join("test_dir/test0.txt");
+
+        fs::create_dir_all(other.join("info")).unwrap();
+        fs::create_dir_all(other.join("files")).unwrap();
+        fs::write(other.join("files/test0.txt"), "old").unwrap();
+        fs::write(other.join("info/test0.txt.trashinfo"), format!("[Trash Info]\nPath={}\nDeletionDate=2024-05-02T03:00:00\n", original.display())).unwrap();
+
+        let mut trash = Trash::new(hist_path, trash_dir.clone()).unwrap();
+        trash.migrate(Source::TrashCli, &other).unwrap();
+
+        let HistoryPair(old, new, meta) = &trash.hist[0][0];
+
+        assert_eq!(*old, original);
+        assert_eq!(fs::read_to_string(new).unwrap(), "old");
+        assert!(meta.trashed_at.is_some());
+        assert!(!other.join("files/test0.txt").exists());
+        assert!(!other.join("info/test0.txt.trashinfo").exists());
+
+        trash.migrate(Source::TrashCli, &other).unwrap();
+        assert_eq!(trash.hist.len(), 1);
+    }
+
+    #[test]
+    fn test_restore_foreign() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let other = tmp_dir.path().join("Trash");
+        let original = tmp_dir.path().join("gone/notes.txt");
+        let info = |name: &str, path: &Path, date: &str| {
+            fs::write(other.join(format!("info/{}.trashinfo", name)), format!("[Trash Info]\nPath={}\nDeletionDate={}\n", path.display(), date)).unwrap();
+        };
+
+        fs::create_dir_all(other.join("info")).unwrap();
+        fs::create_dir_all(other.join("files")).unwrap();
+
+        for (name, text, date) in [("notes.txt", "old", "2024-05-01T03:00:00"), ("notes.txt.2", "new", "2024-05-02T03:00:00")] {
+            fs::write(other.join("files").join(name), text).unwrap();
+            info(name, &original, date);
+        }
+
+        fs::write(other.join("files/taken.txt"), "mine").unwrap();
+        info("taken.txt", &tmp_dir.path().join("test_dir/test0.txt"), "2024-05-02T03:00:00");
+
+        let mut trash = Trash::new(hist_path, tmp_dir.path().join("trash_dir")).unwrap();
+        let summary = trash.restore_foreign(Source::TrashCli, &other, &Pattern::new("*.txt").unwrap()).unwrap();
+
+        // The newest version goes back, parent and all, and trash-cli no longer lists it
+        assert_eq!(fs::read_to_string(&original).unwrap(), "new");
+        assert!(!other.join("info/notes.txt.2.trashinfo").exists());
+        assert!(other.join("files/notes.txt").exists() && other.join("info/notes.txt.trashinfo").exists());
+
+        assert_eq!((summary.restored, summary.conflicts), (1, vec![tmp_dir.path().join("test_dir/test0.txt")]));
+        assert!(other.join("info/taken.txt.trashinfo").exists());
+        assert!(trash.hist.is_empty());
+    }
+
+    #[test]
+    fn test_undo() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let mut trash_dir = tmp_dir.path().to_owned();
+        let mut test_dir = tmp_dir.path().to_owned();
+
+        trash_dir.push("trash_dir");
+        test_dir.push("test_dir");
+
+        let mut trash = Trash::new(hist_path.clone(), trash_dir.clone()).unwrap();
+
+        test_dir.push("test1.txt");
+
+        trash.remove(vec![test_dir.to_string_lossy().to_string()]).unwrap();
+
+        assert!(!test_dir.exists());
+
+        trash.undo().unwrap();
+
+        assert!(test_dir.exists())
+    }
+
+    #[test]
+    fn test_write_appends() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let test_dir = tmp_dir.path().join("test_dir");
+        // Counting the version line at the top
+        let lines = || fs::read_to_string(&hist_path).unwrap().lines().count();
+
+        let mut trash = Trash::new(hist_path.clone(), tmp_dir.path().join("trash_dir")).unwrap();
+
+        for i in 0..3 {
+            trash.remove(vec![test_dir.join(format!("test{}.txt", i)).to_string_lossy().to_string()]).unwrap();
+            trash.write().unwrap();
+        }
+
+        let before = fs::read_to_string(&hist_path).unwrap();
+        assert_eq!(lines(), 4);
+
+        // Undo compacts the file, leaving it with what's still in the trash
+        trash.undo().unwrap();
+        trash.write().unwrap();
+
+        assert_eq!(lines(), 3);
+        assert!(before.starts_with(&fs::read_to_string(&hist_path).unwrap()));
+        let ids = |hist: &History| hist.iter().flatten().map(|p| p.2.id.clone()).collect::<Vec<_>>();
+        let reopened = Trash::new(hist_path, tmp_dir.path().join("trash_dir")).unwrap();
+
+        assert_eq!(ids(reopened.history()), ids(trash.history()));
+    }
+
+    #[test]
+    fn test_recover() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let trash_dir = tmp_dir.path().join("trash_dir");
+        let test_dir = tmp_dir.path().join("test_dir");
+
+        // A run that died after moving test0.txt, and part way through moving test_dir
+        let mut trash = Trash::new(hist_path.clone(), trash_dir.clone()).unwrap();
+        let meta = |id: &str| ItemMeta { id: id.to_string(), ..Default::default() };
+
+        trash.move_in(&test_dir.join("test0.txt"), &trash_dir.join("test0.txt"), &meta("moved")).unwrap();
+        trash.journal.begin(&HistoryPair(test_dir.clone(), trash_dir.join("test_dir"), meta("halfway"))).unwrap();
+        fs::create_dir(trash_dir.join("test_dir")).unwrap();
+        fs::rename(test_dir.join("test1.txt"), trash_dir.join("test_dir/test1.txt")).unwrap();
+        drop(trash);
+
+        let mut trash = Trash::new(hist_path.clone(), trash_dir.clone()).unwrap();
+
+        assert_eq!(trash.recover().unwrap(), 1);
+        assert_eq!(trash.history()[0][0].1, trash_dir.join("test0.txt"));
+        assert!(test_dir.join("test1.txt").exists() && !trash_dir.join("test_dir").exists());
+
+        // Once history has it, the journal is done with
+        trash.write().unwrap();
+
+        assert_eq!(Trash::new(hist_path, trash_dir).unwrap().recover().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_recover_renamed() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let trash_dir = tmp_dir.path().join("trash_dir");
+        let original = tmp_dir.path().join("test_dir/test0.txt");
+
+        // A run that died once the backend had put test0.txt under a name of its own
+        let mut trash = Trash::new(hist_path.clone(), trash_dir.clone()).unwrap();
+        let meta = ItemMeta { id: "renamed".to_string(), ..ItemMeta::read(&original) };
+        let pair = HistoryPair(original.clone(), trash_dir.join("test0.txt"), meta);
+
+        trash.set_backend(Box::new(Renaming(trash_dir.clone())));
+        trash.journal.begin(&pair).unwrap();
+        trash.backend.move_to_trash(&pair.0, &pair.1, &pair.2, &MoveOptions::default(), &mut |_| {}).unwrap();
+        drop(trash);
+
+        let mut trash = Trash::new(hist_path, trash_dir.clone()).unwrap();
+        trash.set_backend(Box::new(Renaming(trash_dir.clone())));
+
+        assert_eq!(trash.recover().unwrap(), 1);
+        assert_eq!(trash.history()[0][0].1, trash_dir.join("test0.txt 2"));
+    }
+
+    #[test]
+    fn test_non_empty_directory_doesnt_fail() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let mut trash_dir = tmp_dir.path().to_owned();
+        let mut test_dir = tmp_dir.path().to_owned();
+
+        trash_dir.push("trash_dir");
+        test_dir.push("test_dir");
+
+        let non_empty_dir = test_dir.clone().join("non-empty");
+        create_dir(&non_empty_dir).unwrap();
+        
+        let mut non_empty_dir2 = trash_dir.clone().join("non-empty");
+        create_dir(&non_empty_dir2).unwrap();
+        
+        let mut f1 = File::create(non_empty_dir.join("test1.txt")).unwrap();
+        f1.write_all(b"stuff").unwrap();
+
+        let mut f2 = File::create(non_empty_dir2.join("test1.txt")).unwrap();
+        f2.write_all(b"stuff").unwrap();
+
+        let mut trash = Trash::new(hist_path.clone(), trash_dir.clone()).unwrap();
+
+        trash.remove(vec![non_empty_dir.to_string_lossy().to_string()]).unwrap();
+
+        assert!(!non_empty_dir.exists());
+
+        assert!(non_empty_dir2.exists());
+
+        non_empty_dir2.pop();
+
+        assert!(non_empty_dir2.join("non-empty.~1~").exists());
+    }
+
+    #[test]
+    fn test_sizes_recorded() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let trash_dir = tmp_dir.path().join("trash_dir");
+        let test_dir = tmp_dir.path().join("test_dir");
+
+        File::create(test_dir.join("test0.txt")).unwrap().write_all(b"12345").unwrap();
+        File::create(test_dir.join("test1.txt")).unwrap().write_all(b"123").unwrap();
+
+        let mut trash = Trash::new(hist_path.clone(), trash_dir.clone()).unwrap();
+
+        trash.remove(vec![test_dir.to_string_lossy().to_string()]).unwrap();
+
+        assert_eq!(trash.hist[0][0].2.size, 8);
+    }
+
+    #[test]
+    fn test_disk_size_wide_tree() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+
+        for i in 0..20 {
+            let dir = tmp_dir.path().join(format!("a{}/b/c", i));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("f"), vec![0; i]).unwrap();
+            fs::write(tmp_dir.path().join(format!("a{}/g", i)), "xy").unwrap();
+        }
+
+        assert_eq!(disk_size(tmp_dir.path()), (0..20).sum::<usize>() as u64 + 40);
+        // The root, then a, a/g, a/b, a/b/c and a/b/c/f twenty times over
+        assert_eq!(disk_usage(tmp_dir.path()).1, 101);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_disk_usage_loop() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let dir = tmp_dir.path().join("a/b");
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("f"), "xy").unwrap();
+        std::os::unix::fs::symlink(tmp_dir.path(), dir.join("up")).unwrap();
+
+        // The link back up is counted as itself and never walked into
+        assert_eq!(disk_usage(tmp_dir.path()).1, 5);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_walk_skips_seen() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let (a, b) = (tmp_dir.path().join("a"), tmp_dir.path().join("a/b"));
+
+        fs::create_dir_all(&b).unwrap();
+        fs::write(b.join("f"), "xy").unwrap();
+        fs::write(a.join("g"), "xyz").unwrap();
+
+        // b as if it had already been reached under another path, like a second bind mount of it
+        let queue = Mutex::new(Walk { dirs: vec![a], busy: 0, seen: file_id(&b).into_iter().collect() });
+        let (total, entries) = (AtomicU64::new(0), AtomicU64::new(0));
+
+        walk_sizes(&queue, &Condvar::new(), &total, &entries);
+
+        assert_eq!((total.into_inner(), entries.into_inner()), (3, 2));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_check_space() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+
+        assert!(check_space(tmp_dir.path(), 1024, 1).is_ok());
+        assert!(check_space(tmp_dir.path(), u64::MAX, 1).unwrap_err().0.starts_with("Not enough space"));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_check_space_where_backend_puts_it() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let target = tmp_dir.path().join("test_dir/test0.txt");
+
+        fs::write(&target, "data").unwrap();
+
+        // Nothing fits in /proc, whatever room there is where trash_path is
+        let mut trash = Trash::new(hist_path, tmp_dir.path().join("trash_dir")).unwrap();
+        trash.set_backend(Box::new(Renaming(PathBuf::from("/proc"))));
+
+        let err = trash.remove(vec![target.to_string_lossy().to_string()]).unwrap_err();
+
+        assert!(err.0.starts_with("Not enough space for the trash at /proc"));
+        assert!(target.exists());
+    }
+
+    #[test]
+    fn test_old_history_format() {
+        let hist: History = serde_json::from_str(r#"[[["/a/b.txt", "/tmp/trash/b.txt"]]]"#).unwrap();
+
+        assert_eq!(hist[0][0].2.size, 0);
+    }
+
+    #[test]
+    fn test_human_size() {
+        assert_eq!(human_size(512), "512 B");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        assert_eq!(human_size(5 * 1024 * 1024 * 1024), "5.0 GiB");
+    }
+
+    #[test]
+    fn test_restore_by_name() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let trash_dir = tmp_dir.path().join("trash_dir");
+        let test_dir = tmp_dir.path().join("test_dir");
+        let target = test_dir.join("test1.txt");
+
+        let mut trash = Trash::new(hist_path.clone(), trash_dir.clone()).unwrap();
+
+        trash.remove(vec![target.to_string_lossy().to_string()]).unwrap();
+        trash.remove(vec![test_dir.join("test2.txt").to_string_lossy().to_string()]).unwrap();
+
+        assert!(!target.exists());
+        assert!(trash.find_by_name("missing.txt").is_none());
+
+        let idx = trash.find_by_name("test1.txt").unwrap();
+
+        assert_eq!(idx, (0, 0));
+        assert_eq!(trash.original_path(idx), target);
+
+        trash.restore_item(idx, &target).unwrap();
+
+        assert!(target.exists());
+        assert_eq!(trash.hist.len(), 1);
+    }
+
+    #[test]
+    fn test_restore_keep() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let target = tmp_dir.path().join("test_dir/test1.txt");
+
+        let mut trash = Trash::new(hist_path, tmp_dir.path().join("trash_dir")).unwrap();
+
+        trash.remove(vec![target.to_string_lossy().to_string()]).unwrap();
+
+        let idx = trash.find_by_name("test1.txt").unwrap();
+        trash.copy_item(idx, &target).unwrap();
+
+        assert!(target.exists());
+        assert!(trash.trashed_path(idx).exists());
+        assert_eq!(trash.hist.len(), 1);
+    }
+
+    #[test]
+    fn test_versions() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let target = tmp_dir.path().join("test_dir/test1.txt");
+
+        let mut trash = Trash::new(hist_path, tmp_dir.path().join("trash_dir")).unwrap();
+
+        for content in ["one", "two"] {
+            fs::write(&target, content).unwrap();
+            trash.remove(vec![target.to_string_lossy().to_string()]).unwrap();
+        }
+
+        trash.remove(vec![tmp_dir.path().join("test_dir/test2.txt").to_string_lossy().to_string()]).unwrap();
+
+        let versions = trash.versions(&target);
+
+        assert_eq!(versions, vec![(0, 0), (1, 0)]);
+        assert_eq!(fs::read_to_string(trash.trashed_path(versions[1])).unwrap(), "two");
+    }
+
+    #[test]
+    fn test_which() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let dir = tmp_dir.path().join("test_dir");
+
+        let mut trash = Trash::new(hist_path, tmp_dir.path().join("trash_dir")).unwrap();
+
+        trash.remove(vec![dir.join("test1.txt").to_string_lossy().to_string()]).unwrap();
+        trash.remove(vec![dir.to_string_lossy().to_string()]).unwrap();
+
+        let found = trash.which("test2.txt");
+        assert!(found.is_empty());
+
+        // Inside a trashed directory still counts
+        let found = trash.which(&dir.join("test2.txt").to_string_lossy());
+        assert_eq!(found, vec![(1, 0, trash.trashed_path((1, 0)).join("test2.txt"))]);
+        assert!(found[0].2.exists());
+
+        assert_eq!(trash.which("test1.txt").len(), 1);
+        assert!(trash.which("nothing.txt").is_empty());
+    }
+
+    #[test]
+    fn test_restore_skips_identical() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let target = tmp_dir.path().join("test_dir/test1.txt");
+
+        let mut trash = Trash::new(hist_path, tmp_dir.path().join("trash_dir")).unwrap();
+
+        fs::write(&target, "same").unwrap();
+        trash.remove(vec![target.to_string_lossy().to_string()]).unwrap();
+        fs::write(&target, "same").unwrap();
+
+        let summary = trash.restore_all();
+
+        assert_eq!(summary.identical, vec![target.clone()]);
+        assert_eq!(summary.restored, 0);
+        assert_eq!(trash.hist.len(), 1);
+
+        trash.set_skip_identical(false);
+        trash.set_conflict(Conflict::Rename);
+
+        assert_eq!(trash.restore_all().restored, 1);
+    }
+
+    #[test]
+    fn test_restore_all() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let trash_dir = tmp_dir.path().join("trash_dir");
+        let test_dir = tmp_dir.path().join("test_dir");
+
+        let mut trash = Trash::new(hist_path.clone(), trash_dir.clone()).unwrap();
+
+        for i in 0..3 {
+            trash.remove(vec![test_dir.join(format!("test{}.txt", i)).to_string_lossy().to_string()]).unwrap();
+        }
+
+        // One conflict and one item that has disappeared from the trash
+        fs::write(test_dir.join("test0.txt"), "new").unwrap();
+        fs::remove_file(trash_dir.join("test1.txt")).unwrap();
+
+        // An older version of test2.txt stays behind rather than conflicting with the newer one
+        fs::write(test_dir.join("test2.txt"), "newer").unwrap();
+        trash.remove(vec![test_dir.join("test2.txt").to_string_lossy().to_string()]).unwrap();
+
+        let summary = trash.restore_all();
+
+        assert_eq!(summary.restored, 1);
+        assert_eq!(summary.older, 1);
+        assert_eq!(summary.conflicts, vec![test_dir.join("test0.txt")]);
+        assert_eq!(summary.missing, vec![test_dir.join("test1.txt")]);
+        assert_eq!(fs::read_to_string(test_dir.join("test2.txt")).unwrap(), "newer");
+        assert_eq!(trash.item_count(), 3);
+    }
+
+    #[test]
+    fn test_restore_last() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let trash_dir = tmp_dir.path().join("trash_dir");
+        let test_dir = tmp_dir.path().join("test_dir");
+
+        let mut trash = Trash::new(hist_path.clone(), trash_dir.clone()).unwrap();
+
+        for i in 0..3 {
+            trash.remove(vec![test_dir.join(format!("test{}.txt", i)).to_string_lossy().to_string()]).unwrap();
+        }
+
+        // The middle operation can't be restored, so it stays whole in the trash
+        fs::write(test_dir.join("test1.txt"), "new").unwrap();
+
+        let summary = trash.restore_last(2);
+
+        assert_eq!(summary.restored, 1);
+        assert_eq!(summary.skipped_ops, vec![2]);
+        assert!(test_dir.join("test2.txt").exists());
+        assert!(!test_dir.join("test0.txt").exists());
+        assert_eq!(trash.hist.len(), 2);
+    }
+
+    #[test]
+    fn test_restore_last_identical() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let test_dir = tmp_dir.path().join("test_dir");
+        let mut trash = Trash::new(hist_path, tmp_dir.path().join("trash_dir")).unwrap();
+
+        trash.remove(vec![test_dir.join("test*.txt").to_string_lossy().to_string()]).unwrap();
+
+        let copy = trash.hist[0].iter().find(|p| p.0.ends_with("test1.txt")).unwrap().1.clone();
+        fs::copy(&copy, test_dir.join("test1.txt")).unwrap();
+
+        // test1.txt is already back, which doesn't hold up the rest of the operation
+        let summary = trash.restore_last(1);
+
+        assert_eq!(summary.restored, 2);
+        assert_eq!(summary.identical, vec![test_dir.join("test1.txt")]);
+        assert!(summary.skipped_ops.is_empty());
+        assert!(test_dir.join("test0.txt").exists() && test_dir.join("test2.txt").exists());
+        assert!(!copy.exists());
+        assert!(trash.hist.is_empty());
+    }
+
+    #[test]
+    fn test_restore_matching() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let test_dir = tmp_dir.path().join("test_dir");
+        let target = test_dir.join("test0.txt");
+        let mut trash = Trash::new(hist_path, tmp_dir.path().join("trash_dir")).unwrap();
+
+        for content in ["old", "new"] {
+            fs::write(&target, content).unwrap();
+            trash.remove(vec![target.to_string_lossy().to_string()]).unwrap();
+        }
+
+        trash.remove(vec![test_dir.join("test1.txt").to_string_lossy().to_string()]).unwrap();
+
+        let pattern = Pattern::new(&format!("{}/*0.txt", test_dir.display())).unwrap();
+        let summary = trash.restore_matching(&pattern);
+
+        // Only the newest version comes back, the older one isn't reported as a conflict
+        assert_eq!(summary.restored, 1);
+        assert!(summary.conflicts.is_empty());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+        assert_eq!(trash.item_count(), 2);
+    }
+
+    #[test]
+    fn test_restore_entry() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let test_dir = tmp_dir.path().join("test_dir");
+        let mut trash = Trash::new(hist_path, tmp_dir.path().join("trash_dir")).unwrap();
+
+        trash.remove(vec![test_dir.join("test0.txt").to_string_lossy().to_string(), test_dir.join("test1.txt").to_string_lossy().to_string()]).unwrap();
+        trash.remove(vec![test_dir.join("test2.txt").to_string_lossy().to_string()]).unwrap();
+
+        // The older operation comes back whole, leaving the newer one alone
+        let summary = trash.restore_entry(1).unwrap();
+
+        assert_eq!(summary.restored, 2);
+        assert!(test_dir.join("test0.txt").exists() && test_dir.join("test1.txt").exists());
+        assert!(!test_dir.join("test2.txt").exists());
+        assert_eq!(trash.hist.len(), 1);
+
+        assert!(trash.restore_entry(0).is_err());
+        assert!(trash.restore_entry(2).is_err());
+    }
+
+    #[test]
+    fn test_undo_entry() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let test_dir = tmp_dir.path().join("test_dir");
+        let mut trash = Trash::new(hist_path, tmp_dir.path().join("trash_dir")).unwrap();
+
+        let remove = |trash: &mut Trash, path: &Path| trash.remove(vec![path.to_string_lossy().to_string()]).unwrap();
+
+        remove(&mut trash, &test_dir.join("test0.txt"));
+        remove(&mut trash, &test_dir.join("test1.txt"));
+
+        // test0.txt comes back and goes again, then the whole directory goes after it
+        fs::write(test_dir.join("test0.txt"), "new").unwrap();
+        remove(&mut trash, &test_dir.join("test0.txt"));
+        remove(&mut trash, &test_dir);
+
+        assert_eq!(trash.dependents(0).iter().map(|d| d.0).collect::<Vec<_>>(), vec![2, 3]);
+        assert!(trash.undo_entry(1).is_err());
+        assert_eq!(trash.hist.len(), 4);
+
+        // Nothing newer touched test1.txt except the directory holding it
+        assert!(trash.undo_entry(2).is_err());
+
+        trash.undo().unwrap();
+        trash.undo_entry(2).unwrap();
+
+        assert!(test_dir.join("test1.txt").exists());
+        assert_eq!(trash.hist.len(), 2);
+        assert!(trash.undo_entry(5).is_err());
+    }
+
+    #[test]
+    fn test_undo_conflict() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let trash_dir = tmp_dir.path().join("trash_dir");
+        let test_dir = tmp_dir.path().join("test_dir");
+        let target = test_dir.join("test1.txt");
+
+        let mut trash = Trash::new(hist_path.clone(), trash_dir.clone()).unwrap();
+
+        trash.remove(vec![target.to_string_lossy().to_string()]).unwrap();
+        File::create(&target).unwrap().write_all(b"new").unwrap();
+
+        // Skipping leaves the item in history for a later attempt
+        trash.undo().unwrap();
+        assert_eq!(trash.item_count(), 1);
+
+        trash.set_conflict(Conflict::Rename);
+        trash.undo().unwrap();
+
+        assert_eq!(trash.item_count(), 0);
+        assert_eq!(fs::read(&target).unwrap(), b"new");
+        assert!(test_dir.join("test1.txt.restored").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_undo_recreates_parent() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (tmp_dir, hist_path) = trash_dir();
+        let trash_dir = tmp_dir.path().join("trash_dir");
+        let parent = tmp_dir.path().join("test_dir/nested");
+        let target = parent.join("file.txt");
+
+        create_dir(&parent).unwrap();
+        File::create(&target).unwrap();
+        fs::set_permissions(&parent, fs::Permissions::from_mode(0o750)).unwrap();
+
+        let mut trash = Trash::new(hist_path.clone(), trash_dir.clone()).unwrap();
+
+        trash.remove(vec![target.to_string_lossy().to_string()]).unwrap();
+        fs::remove_dir(&parent).unwrap();
+
+        trash.undo().unwrap();
+
+        assert!(target.exists());
+        assert_eq!(fs::metadata(&parent).unwrap().permissions().mode() & 0o7777, 0o750);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_dir_renamed_whole() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let test_dir = tmp_dir.path().join("test_dir");
+        let before = file_id(&test_dir);
+
+        let mut trash = Trash::new(hist_path, tmp_dir.path().join("trash_dir")).unwrap();
+
+        trash.remove(vec![test_dir.to_string_lossy().to_string()]).unwrap();
+
+        // Same inode means the directory itself was moved rather than rebuilt file by file
+        assert_eq!(trash.hist[0].len(), 1);
+        assert_eq!(file_id(&trash.hist[0][0].1), before);
+    }
+
+    #[test]
+    fn test_empty_dirs_survive_undo() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let trash_dir = tmp_dir.path().join("trash_dir");
+        let test_dir = tmp_dir.path().join("test_dir");
+
+        fs::create_dir_all(test_dir.join("empty/nested_empty")).unwrap();
+
+        let mut trash = Trash::new(hist_path.clone(), trash_dir.clone()).unwrap();
+
+        trash.remove(vec![test_dir.to_string_lossy().to_string()]).unwrap();
+        assert!(!test_dir.exists());
+
+        trash.undo().unwrap();
+
+        assert!(test_dir.join("empty/nested_empty").is_dir());
+        assert!(test_dir.join("test0.txt").is_file());
+    }
+
+    #[test]
+    fn test_undo_restores_timestamps() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let trash_dir = tmp_dir.path().join("trash_dir");
+        let target = tmp_dir.path().join("test_dir/test0.txt");
+        let old_time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+
+        File::open(&target).unwrap().set_times(FileTimes::new().set_modified(old_time)).unwrap();
+
+        let mut trash = Trash::new(hist_path.clone(), trash_dir.clone()).unwrap();
+
+        trash.remove(vec![target.to_string_lossy().to_string()]).unwrap();
+
+        // Something touches the file while it sits in the trash
+        File::open(trash_dir.join("test0.txt")).unwrap().set_times(FileTimes::new().set_modified(SystemTime::now())).unwrap();
+
+        trash.undo().unwrap();
+
+        assert_eq!(fs::metadata(&target).unwrap().modified().unwrap(), old_time);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_undo_restores_owner() {
+        use std::os::unix::fs::{chown, MetadataExt};
+
+        let (tmp_dir, hist_path) = trash_dir();
+        let trash_dir = tmp_dir.path().join("trash_dir");
+        let target = tmp_dir.path().join("test_dir/test0.txt");
+
+        // Changing owners needs root, nothing to check otherwise
+        if chown(&target, Some(1234), Some(1234)).is_err() {
+            return
+        }
+
+        let mut trash = Trash::new(hist_path.clone(), trash_dir.clone()).unwrap();
+
+        trash.remove(vec![target.to_string_lossy().to_string()]).unwrap();
+        chown(trash_dir.join("test0.txt"), Some(0), Some(0)).unwrap();
+
+        trash.undo().unwrap();
+
+        let meta = fs::metadata(&target).unwrap();
+        assert_eq!((meta.uid(), meta.gid()), (1234, 1234));
+    }
+
+    #[test]
+    fn test_trash_is_protected() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let trash_dir = tmp_dir.path().join("trash_dir");
+
+        File::create(trash_dir.join("already_trashed.txt")).unwrap();
+
+        let mut trash = Trash::new(hist_path.clone(), trash_dir.clone()).unwrap();
+
+        trash.remove(vec![
+            trash_dir.join("already_trashed.txt").to_string_lossy().to_string(),
+            tmp_dir.path().to_string_lossy().to_string()
+        ]).unwrap();
+
+        assert!(trash_dir.join("already_trashed.txt").exists());
+        assert!(tmp_dir.path().join("test_dir").exists());
+        assert!(trash.hist.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_trash_is_protected_through_links() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let trash_dir = tmp_dir.path().join("trash_dir");
+        let trash_view = tmp_dir.path().join("test_dir/trash_view");
+
+        std::os::unix::fs::symlink(&trash_dir, &trash_view).unwrap();
+        File::create(trash_dir.join("already_trashed.txt")).unwrap();
+
+        let trash = Trash::new(hist_path.clone(), trash_dir.clone()).unwrap();
+
+        assert_eq!(trash.trash_relative(&trash_view.join("already_trashed.txt")), Some(trash_dir.join("already_trashed.txt")));
+        assert_eq!(trash.trash_relative(&tmp_dir.path().join("test_dir/test0.txt")), None);
+        assert!(trash.holds_trash(&trash_view));
+    }
+
+    #[test]
+    fn test_in_trash_policy() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let trash_dir = tmp_dir.path().join("trash_dir");
+        let test_dir = tmp_dir.path().join("test_dir");
+
+        let mut trash = Trash::new(hist_path.clone(), trash_dir.clone()).unwrap();
+
+        trash.remove(vec![test_dir.join("test0.txt").to_string_lossy().to_string()]).unwrap();
+
+        let trashed = vec![trash_dir.join("test0.txt").to_string_lossy().to_string()];
+
+        trash.set_in_trash(InTrash::Error);
+        assert!(trash.remove(trashed.clone()).is_err());
+
+        trash.set_in_trash(InTrash::Delete);
+        trash.remove(trashed).unwrap();
+
+        assert!(!trash_dir.join("test0.txt").exists());
+        assert!(trash.hist.is_empty());
+    }
+
+    #[test]
+    fn test_hist_index() {
+        assert_eq!("3".parse::<HistIndex>(), Ok(HistIndex { op: 3, item: None }));
+        assert_eq!("3:2".parse::<HistIndex>(), Ok(HistIndex { op: 3, item: Some(2) }));
+        assert!("0".parse::<HistIndex>().is_err());
+        assert!("3:".parse::<HistIndex>().is_err());
+    }
+
+    #[test]
+    fn test_purge() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let trash_dir = tmp_dir.path().join("trash_dir");
+        let test_dir = tmp_dir.path().join("test_dir");
+
+        let mut trash = Trash::new(hist_path.clone(), trash_dir.clone()).unwrap();
+
+        trash.remove(vec![test_dir.join("test*.txt").to_string_lossy().to_string()]).unwrap();
+        File::create(test_dir.join("keep.log")).unwrap();
+        trash.remove(vec![test_dir.join("keep.log").to_string_lossy().to_string()]).unwrap();
+
+        let mut items = trash.matching(&Pattern::new("test[01].txt").unwrap());
+        items.extend(trash.resolve(&"2".parse().unwrap()).unwrap());
+
+        assert_eq!(items.len(), 3);
+        assert!(trash.resolve(&"2:2".parse().unwrap()).is_err());
+
+        trash.purge(items).unwrap();
+
+        assert!(!trash_dir.join("test0.txt").exists());
+        assert!(!trash_dir.join("keep.log").exists());
+        assert!(trash_dir.join("test2.txt").exists());
+        assert_eq!(trash.item_count(), 1);
+    }
+
+    #[test]
+    fn test_item_ids() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let trash_dir = tmp_dir.path().join("trash_dir");
+        let test_dir = tmp_dir.path().join("test_dir");
+
+        fs::write(&hist_path, format!(r#"[[["{}", "{}"]]]"#, test_dir.join("old.txt").display(), trash_dir.join("old.txt").display())).unwrap();
+
+        let mut trash = Trash::new(hist_path.clone(), trash_dir.clone()).unwrap();
+
+        trash.remove(vec![test_dir.join("test*.txt").to_string_lossy().to_string()]).unwrap();
+
+        let ids: std::collections::HashSet<&str> = trash.hist.iter().flatten().map(|p| p.2.id.as_str()).collect();
+
+        assert_eq!(ids.len(), 4);
+        assert!(ids.iter().all(|id| id.len() == 6 && id.chars().all(|c| c.is_ascii_lowercase())));
+
+        let id = trash.hist[1][2].2.id.clone();
+
+        // Positions shift once earlier items go, IDs don't
+        trash.purge(vec![(0, 0)]).unwrap();
+
+        assert_eq!(trash.find_by_id(&id), Some((0, 2)));
+        assert_eq!(trash.resolve(&id.parse().unwrap()).unwrap(), vec![(0, 2)]);
+        assert_eq!("2:1".parse::<ItemRef>(), Ok(ItemRef::Index(HistIndex { op: 2, item: Some(1) })));
+        assert!("ab1".parse::<ItemRef>().is_err());
+    }
+
+    #[test]
+    fn test_find_by_path() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let trash_dir = tmp_dir.path().join("trash_dir");
+        let target = tmp_dir.path().join("test_dir/test0.txt");
+
+        let mut trash = Trash::new(hist_path.clone(), trash_dir.clone()).unwrap();
+
+        trash.remove(vec![target.to_string_lossy().to_string()]).unwrap();
+
+        assert_eq!(trash.find_by_path(&target), Some((0, 0)));
+        assert_eq!(trash.find_by_path(&trash_dir.join("test0.txt")), Some((0, 0)));
+        assert_eq!(trash.find_by_path(&trash_dir.join("test1.txt")), None);
+        assert!(trash.hist[0][0].2.trashed_at.is_some());
+    }
+
+    #[test]
+    fn test_hash_file() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("abc.txt");
+
+        fs::write(&path, b"abc").unwrap();
+
+        assert_eq!(hash_file(&path).unwrap(), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_restored_name_keeps_bytes() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join(OsStr::from_bytes(b"caf\xe9.txt"));
+        let restored = tmp_dir.path().join(OsStr::from_bytes(b"caf\xe9.txt.restored"));
+
+        assert_eq!(restored_name(&path), restored);
+
+        fs::write(&restored, "").unwrap();
+        let numbered = restored_name(&path);
+
+        // Still the same bytes, not a replacement character in their place
+        assert_eq!(numbered, tmp_dir.path().join(OsStr::from_bytes(b"caf\xe9.txt.restored.1")));
+
+        fs::write(&numbered, "").unwrap();
+        assert_eq!(restored_name(&path), tmp_dir.path().join(OsStr::from_bytes(b"caf\xe9.txt.restored.2")));
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("90d"), Ok(Duration::from_secs(90 * 24 * 60 * 60)));
+        assert_eq!(parse_age("2w"), Ok(Duration::from_secs(14 * 24 * 60 * 60)));
+        assert_eq!(parse_age("45s"), Ok(Duration::from_secs(45)));
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("10y").is_err());
+        assert!(parse_age("").is_err());
+        assert!(parse_age("99999999999999999w").is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("512K"), Ok(512 * 1024));
+        assert_eq!(parse_size("4MiB"), Ok(4 * 1024 * 1024));
+        assert_eq!(parse_size("1g"), Ok(1024 * 1024 * 1024));
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("3T").is_err());
+    }
+
+    #[test]
+    fn test_select_older_than() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let trash_dir = tmp_dir.path().join("trash_dir");
+        let test_dir = tmp_dir.path().join("test_dir");
+
+        let mut trash = Trash::new(hist_path.clone(), trash_dir.clone()).unwrap();
+
+        trash.remove(vec![test_dir.join("test*.txt").to_string_lossy().to_string()]).unwrap();
+        trash.hist[0][0].2.trashed_at = Some(SystemTime::now() - Duration::from_secs(100 * 24 * 60 * 60));
+        trash.hist[0][1].2.trashed_at = None;
+
+        assert_eq!(trash.select(None, Some(Duration::from_secs(90 * 24 * 60 * 60))), vec![(0, 0)]);
+        assert_eq!(trash.select(Some(&Pattern::new("test2.txt").unwrap()), None), vec![(0, 2)]);
+        assert_eq!(trash.select(None, None).len(), 3);
+    }
+
+    #[test]
+    fn test_evict() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let test_dir = tmp_dir.path().join("test_dir");
+        let mut trash = Trash::new(hist_path, tmp_dir.path().join("trash_dir")).unwrap();
+
+        for i in 0..3 {
+            fs::write(test_dir.join(format!("test{}.txt", i)), "x".repeat(100)).unwrap();
+            trash.remove(vec![test_dir.join(format!("test{}.txt", i)).to_string_lossy().to_string()]).unwrap();
+        }
+
+        let oldest = trash.trashed_path((0, 0)).to_path_buf();
+        let size = trash.hist[0][0].2.size;
+
+        // Room for three, so the oldest goes when the fourth comes in
+        trash.set_max_size(Some(size * 3));
+        fs::write(test_dir.join("test3.txt"), "x".repeat(100)).unwrap();
+        trash.remove(vec![test_dir.to_string_lossy().to_string()]).unwrap();
+
+        assert!(!oldest.exists());
+        assert_eq!(trash.hist.len(), 3);
+        assert_eq!(trash.hist[2][0].0, test_dir);
+    }
+
+    #[test]
+    fn test_file_url() {
+        assert_eq!(file_url(Path::new("/home/me/my docs/ü.txt")), "file:///home/me/my%20docs/%C3%BC.txt");
+    }
+
+    #[test]
+    fn test_by_dir() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let test_dir = tmp_dir.path().join("test_dir");
+        let mut trash = Trash::new(hist_path, tmp_dir.path().join("trash_dir")).unwrap();
+
+        trash.remove(vec![test_dir.join("test0.txt").display().to_string()]).unwrap();
+        trash.remove(vec![test_dir.join("test1.txt").display().to_string(), test_dir.display().to_string()]).unwrap();
+
+        let groups = trash.by_dir();
+
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[&test_dir].iter().map(|p| &p.0).collect::<Vec<_>>(), vec![&test_dir.join("test0.txt"), &test_dir.join("test1.txt")]);
+        assert_eq!(groups[tmp_dir.path()].len(), 1);
+    }
+
+    #[test]
+    fn test_usage_by_origin() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let test_dir = tmp_dir.path().join("test_dir");
+        let nested = test_dir.join("nested");
+
+        let elsewhere = tmp_dir.path().join("elsewhere");
+
+        fs::create_dir(&nested).unwrap();
+        fs::create_dir(&elsewhere).unwrap();
+        fs::write(nested.join("big.txt"), [0; 100]).unwrap();
+        fs::write(test_dir.join("test0.txt"), [0; 10]).unwrap();
+        fs::write(elsewhere.join("other.txt"), [0; 50]).unwrap();
+
+        let mut trash = Trash::new(hist_path, tmp_dir.path().join("trash_dir")).unwrap();
+
+        for path in [nested.join("big.txt"), test_dir.join("test0.txt"), elsewhere.join("other.txt")] {
+            trash.remove(vec![path.to_string_lossy().to_string()]).unwrap();
+        }
+
+        // nested counts towards test_dir
+        assert_eq!(trash.usage_by_origin(), vec![(test_dir, 2, 110), (elsewhere, 1, 50)]);
+    }
+
+    #[test]
+    fn test_unrestorable() {
+        let (tmp_dir, hist_path) = trash_dir();
+        let test_dir = tmp_dir.path().join("test_dir");
+        let mut trash = Trash::new(hist_path, tmp_dir.path().join("trash_dir")).unwrap();
+
+        for name in ["test0.txt", "test1.txt"] {
+            trash.remove(vec![test_dir.join(name).to_string_lossy().to_string()]).unwrap();
+        }
+
+        assert!(trash.unrestorable().is_empty());
+
+        fs::remove_file(&trash.hist[0][0].1).unwrap();
+
+        assert_eq!(trash.unrestorable(), vec![test_dir.join("test0.txt")]);
+    }
+
+    #[test]
+    fn test_resolve_paths_under_root() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path().join("root");
+
+        let (hist_path, trash_path) = resolve_paths(Some(&root), &Config::default(), &Overrides::default()).unwrap();
+
+        assert_eq!(hist_path, root.join("config/trash-history.json"));
+        assert!(fs::read(&hist_path).unwrap().is_empty());
+        assert!(trash_path.starts_with(&root) && trash_path.is_dir());
+    }
+
+    #[test]
+    fn test_new_trash_dir_keeps_history() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path().join("root");
+        let original = tmp_dir.path().join("a.txt");
+
+        fs::write(&original, "a").unwrap();
+
+        let mut trash = Trash::try_new(Some(&root), &Config::default(), &Overrides::default()).unwrap();
+        trash.remove(vec![original.clone()]).unwrap();
+        trash.write().unwrap();
+
+        // As TRASH_DIR pointing somewhere that doesn't exist yet does
+        let overrides = Overrides { trash_dir: Some(tmp_dir.path().join("ci/trash")), history: None };
+        let trash = Trash::try_new(Some(&root), &Config::default(), &overrides).unwrap();
+
+        assert!(trash.trash_path.is_dir());
+        assert_eq!(trash.history().len(), 1);
+        assert_eq!(trash.original_path((0, 0)), original);
+    }
+
+    #[test]
+    fn test_try_new_from_config() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path().join("root");
+
+        let config = Config {
+            trash_dir: Some(tmp_dir.path().join("project/.trash")),
+            history: Some(tmp_dir.path().join("project/history.json")),
+            confirm_bulk: true,
+            sync: true,
+            max_size: Some(1024),
+            ..Config::default()
+        };
+
+        let mut trash = Trash::try_new(Some(&root), &config, &Overrides::default()).unwrap();
+
+        assert_eq!(trash.hist_path, tmp_dir.path().join("project/history.json"));
+        assert!(trash.trash_path.starts_with(tmp_dir.path().join("project/.trash")));
+        assert!(trash.confirm_bulk && trash.sync);
+        assert_eq!(trash.max_size, Some(1024));
+
+        let original = tmp_dir.path().join("a.txt");
+        fs::write(&original, "a").unwrap();
+        trash.remove(vec![original.clone()]).unwrap();
+        trash.write().unwrap();
+
+        // A project trash given with --trash-dir shares the history, it doesn't start it over
+        let overrides = Overrides { trash_dir: Some(tmp_dir.path().join("proj-trash")), history: None };
+        let trash = Trash::try_new(Some(&root), &config, &overrides).unwrap();
+
+        assert!(trash.trash_path.starts_with(tmp_dir.path().join("proj-trash")));
+        assert_eq!(trash.history().len(), 1);
+        assert_eq!(trash.original_path((0, 0)), original);
+
+        // Overrides win over config
+        let overrides = Overrides { trash_dir: Some(tmp_dir.path().join("flag/trash")), history: Some(tmp_dir.path().join("flag/history.json")) };
+        let trash = Trash::try_new(Some(&root), &config, &overrides).unwrap();
+
+        assert_eq!(trash.hist_path, tmp_dir.path().join("flag/history.json"));
+        assert!(trash.trash_path.starts_with(tmp_dir.path().join("flag/trash")));
+    }
+}
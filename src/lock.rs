@@ -0,0 +1,71 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use log::warn;
+use serde::Deserialize;
+
+use crate::{TrashError, TrashResult};
+
+/// What to do when another run already holds the lock
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LockMode {
+    /// Wait for it to finish
+    #[default]
+    Wait,
+    /// Give up straight away
+    Fail
+}
+
+/// Exclusive lock held by every run that changes history, from reading it to writing it back.
+/// Released when dropped, or by the OS if the process dies.
+#[derive(Debug)]
+pub struct Lock {
+    _file: File
+}
+
+impl Lock {
+    pub fn acquire(path: &Path, mode: LockMode) -> TrashResult<Self> {
+        let file = OpenOptions::new().create(true).truncate(false).write(true).open(path)?;
+
+        match file.try_lock() {
+            Ok(()) => {},
+            Err(TryLockError::WouldBlock) if mode == LockMode::Wait => {
+                warn!("Waiting for another trash run holding {} to finish", path.display());
+                file.lock()?;
+            },
+            Err(TryLockError::WouldBlock) => {
+                return Err(TrashError(format!("Another trash run is holding {}, try again later", path.display())))
+            },
+            Err(TryLockError::Error(e)) => return Err(TrashError::from(e))
+        }
+
+        Ok(Self { _file: file })
+    }
+}
+
+/// Next to the history, so separate roots don't block each other
+pub fn lock_path(hist_path: &Path) -> PathBuf {
+    hist_path.with_file_name("trash.lock")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_lock() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("trash.lock");
+
+        let lock = Lock::acquire(&path, LockMode::Fail).unwrap();
+
+        assert!(Lock::acquire(&path, LockMode::Fail).is_err());
+
+        drop(lock);
+
+        assert!(Lock::acquire(&path, LockMode::Fail).is_ok());
+    }
+}
@@ -0,0 +1,88 @@
+use std::io::{self, Write};
+
+use clap::ValueEnum;
+use log::Record;
+use serde::{Deserialize, Serialize};
+
+/// How log messages are written to stderr
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Colored, for people at a terminal
+    #[default]
+    Pretty,
+    /// The same messages without any styling, for log files and dumb terminals
+    Plain,
+    /// One JSON object per line with the level, time and message
+    Json
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    level: &'a str,
+    time: String,
+    message: String
+}
+
+pub fn write(buf: &mut impl Write, record: &Record, format: LogFormat) -> io::Result<()> {
+    let message = record.args().to_string();
+
+    match format {
+        LogFormat::Pretty => writeln!(buf, "{}", message),
+        LogFormat::Plain => writeln!(buf, "{}", strip_styles(&message)),
+        LogFormat::Json => {
+            let line = Line {
+                level: &record.level().as_str().to_lowercase(),
+                time: jiff::Timestamp::now().to_string(),
+                message: strip_styles(&message)
+            };
+
+            writeln!(buf, "{}", serde_json::to_string(&line)?)
+        }
+    }
+}
+
+/// Drops ANSI escape sequences like the color codes `colorize!` adds
+fn strip_styles(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue
+        }
+
+        // CSI sequences run from `[` to a letter
+        if chars.next() == Some('[') {
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break
+                }
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formats() {
+        assert_eq!(strip_styles("\x1b[1;33mSkipping\x1b[0m \x1b[1mnotes.txt\x1b[0m"), "Skipping notes.txt");
+
+        let mut buf = vec![];
+        let args = format_args!("\x1b[1mMoving\x1b[0m a");
+        let record = Record::builder().args(args).level(log::Level::Info).build();
+
+        write(&mut buf, &record, LogFormat::Json).unwrap();
+
+        let line: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+
+        assert_eq!(line["level"], "info");
+        assert_eq!(line["message"], "Moving a");
+    }
+}
@@ -0,0 +1,111 @@
+use std::path::{Component, Path, PathBuf};
+
+use crate::backend::{self, TrashBackend};
+use crate::move_files::MoveOptions;
+use crate::{ItemMeta, TrashError, TrashResult};
+
+/// The macOS trash, with Finder doing the trashing so it remembers where items came from
+/// and Put Back works
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Finder;
+
+impl TrashBackend for Finder {
+    /// Only a guess for the log, Finder picks the name
+    fn place(&self, original: &Path, _taken: &dyn Fn(&Path) -> bool) -> (PathBuf, String) {
+        let name = original.file_name().unwrap_or_default();
+        let stored = trash_dir(original).map(|dir| dir.join(name)).unwrap_or_else(|| original.to_path_buf());
+
+        (stored, "the macOS trash, through Finder so Put Back works".to_string())
+    }
+
+    fn move_to_trash(&self, original: &Path, _stored: &Path, _meta: &ItemMeta, _opts: &MoveOptions, _on_copied: &mut dyn FnMut(u64)) -> TrashResult<PathBuf> {
+        put_in_trash(original)
+    }
+
+    fn restore(&self, stored: &Path, dest: &Path, meta: &ItemMeta, opts: &MoveOptions, on_copied: &mut dyn FnMut(u64)) -> TrashResult<()> {
+        Ok(crate::move_back(stored, dest, meta, opts, on_copied)?)
+    }
+
+    fn purge(&self, stored: &Path) -> TrashResult<()> {
+        if stored.symlink_metadata().is_ok() {
+            crate::delete_permanently(stored)?;
+        }
+
+        Ok(())
+    }
+
+    fn list(&self) -> TrashResult<Vec<PathBuf>> {
+        match dirs::home_dir() {
+            Some(home) => backend::list_dir(&home.join(".Trash")),
+            None => Ok(vec![])
+        }
+    }
+}
+
+/// The trash Finder would put `path` in: `.Trashes/<uid>` at the top of an external volume,
+/// `~/.Trash` for anything on the startup disk
+pub fn trash_dir(path: &Path) -> Option<PathBuf> {
+    let mut components = path.components();
+
+    match (components.next(), components.next(), components.next()) {
+        (Some(Component::RootDir), Some(Component::Normal(volumes)), Some(Component::Normal(name))) if volumes == "Volumes" => {
+            Some(Path::new("/Volumes").join(name).join(".Trashes").join(uid().to_string()))
+        },
+        _ => dirs::home_dir().map(|home| home.join(".Trash"))
+    }
+}
+
+#[cfg(unix)]
+fn uid() -> u32 {
+    unsafe { libc::getuid() }
+}
+
+#[cfg(not(unix))]
+fn uid() -> u32 {
+    0
+}
+
+/// Asks Finder to trash `path`, so it remembers where it came from and Put Back works, and
+/// returns where the item ended up. Finder picks the name, `a 2.txt` if `a.txt` is taken.
+#[cfg(target_os = "macos")]
+pub fn put_in_trash(path: &Path) -> TrashResult<PathBuf> {
+    let script = format!(
+        "tell application \"Finder\" to return POSIX path of ((delete POSIX file {}) as alias)",
+        quote(&path.to_string_lossy())
+    );
+
+    let output = std::process::Command::new("osascript").arg("-e").arg(script).output()?;
+
+    if !output.status.success() {
+        return Err(TrashError(format!("Finder could not trash it: {}", String::from_utf8_lossy(&output.stderr).trim())))
+    }
+
+    // Directories come back with a trailing slash
+    let trashed = String::from_utf8_lossy(&output.stdout);
+    Ok(PathBuf::from(trashed.trim_end_matches('\n').trim_end_matches('/')))
+}
+
+#[cfg(not(target_os = "macos"))]
+pub fn put_in_trash(_path: &Path) -> TrashResult<PathBuf> {
+    Err(TrashError::new("The macOS trash is only there on macOS"))
+}
+
+/// An AppleScript string literal
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trash_dir() {
+        let external = trash_dir(Path::new("/Volumes/Backup/photos/a.jpg")).unwrap();
+
+        assert_eq!(external, Path::new("/Volumes/Backup/.Trashes").join(uid().to_string()));
+        assert_eq!(trash_dir(Path::new("/Users/me/a.txt")), dirs::home_dir().map(|home| home.join(".Trash")));
+        assert_eq!(quote(r#"my "notes"\a"#), r#""my \"notes\"\\a""#);
+    }
+}
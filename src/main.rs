@@ -1,281 +1,1579 @@
+mod setup;
 
 use std::{env, fs};
-use std::fs::{File, rename};
-use std::path::PathBuf;
-use std::io::{BufReader, Write};
+use std::fs::File;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::io::{self, BufReader, Write};
 use std::process::ExitCode;
+use std::time::{Duration, SystemTime};
 
-use clap::Parser;
-use serde::{Serialize, Deserialize};
-use glob::glob;
-use log::{info, error, LevelFilter};
+use clap::{Parser, Subcommand, ValueEnum};
+use glob::Pattern;
+use log::{info, error, warn, debug, LevelFilter};
 use env_logger::Builder;
 use colorize::{colorize, print_color};
 
-#[derive(Serialize, Deserialize, Debug)]
-struct HistoryPair(PathBuf, PathBuf);
-
-type HistoryPairs = Vec<HistoryPair>;
-type History = Vec<HistoryPairs>;
-
-#[derive(Debug)]
-pub struct Trash {
-    hist: History,
-    hist_path: PathBuf,
-    trash_path: PathBuf,
-    explain: bool
+use trash::*;
+use trash::config::{self, Config};
+use trash::freedesktop::FreeDesktop;
+use trash::ignore::Ignore;
+use trash::lock::{Lock, LockMode};
+use trash::logging::{self, LogFormat};
+use trash::migrate::Source;
+use trash::plan::{Action, Plan, PlanFormat};
+use trash::progress::ProgressFormat;
+use trash::state::{Rewrite, State};
+use trash::table::{Cell, Table};
+use trash::webhook::Event;
+use trash::{metrics, schedule, secure};
+
+/// How to bucket the history view
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum GroupBy {
+    /// The directory each item was trashed from
+    Dir
 }
 
-
 #[derive(Parser)]
-#[command(version, about, long_about=None)]
+#[command(version, about, long_about=None, args_conflicts_with_subcommands=true, subcommand_negates_reqs=true)]
 struct Args {
-    /// Undo last trash command
-    #[arg(long, short)]
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Same as `trash undo`
+    #[arg(long, short, hide=true, conflicts_with_all(["view", "name"]))]
     undo: bool,
 
-    /// Show full output from command detailing all moves
-    #[arg(long, short)]
-    verbose: bool,
+    /// Same as `trash undo --entry`
+    #[arg(long, requires("undo"), hide=true, value_name="N")]
+    entry: Option<usize>,
 
-    /// Do not take action, only explain what would occur. Same log level as verbose.
-    #[arg(long, short)]
+    /// Show more of what is going on: -v every move, -vv why each item is handled the way it
+    /// is, -vvv the system calls that had to fall back to something else
+    #[arg(long, short, global=true, action=clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Do not take action, only explain what would occur. Implies -v.
+    #[arg(long, short, global=true)]
     explain: bool,
 
-    /// View history
-    #[arg(long, short='w')]
+    /// How --explain reports the plan of everything it would do
+    #[arg(long, value_enum, global=true, requires("explain"), value_name="FORMAT")]
+    output: Option<PlanFormat>,
+
+    /// How to restore items whose original path is now occupied
+    #[arg(long, value_enum, default_value_t, global=true)]
+    on_conflict: Conflict,
+
+    /// Go through targets and directory contents in name order, so logs, history and plans come
+    /// out the same on every run
+    #[arg(long, global=true)]
+    sorted: bool,
+
+    #[command(flatten)]
+    put: PutOptions,
+
+    /// Same as `trash history`
+    #[arg(long, short='w', hide=true, conflicts_with("name"))]
     view: bool,
 
-    /// Name of file or directory to remove
+    /// Same as `trash history --stats`
+    #[arg(long, requires("view"), hide=true)]
+    stats: bool,
+
+    /// Same as `trash history --group-by`
+    #[arg(long, value_enum, requires("view"), hide=true)]
+    group_by: Option<GroupBy>,
+
+    /// Same as `trash history --timeline`
+    #[arg(long, requires("view"), conflicts_with("group_by"), hide=true)]
+    timeline: bool,
+
+    /// Show full paths in tables instead of shortening them to fit the terminal
+    #[arg(long, global=true)]
+    no_truncate: bool,
+
+    /// Buffer size for copies between filesystems, e.g. 4M or 512K
+    #[arg(long, global=true, value_parser(parse_size), default_value("1M"), value_name="SIZE")]
+    buffer_size: usize,
+
+    /// Times to try again, waiting longer each time, when a file is busy (held open by an editor or
+    /// virus scanner, or a running program) before giving up on it
+    #[arg(long, global=true, default_value_t=move_files::DEFAULT_RETRIES, value_name="N")]
+    retries: usize,
+
+    /// What to do with FIFOs, sockets and devices when moving between filesystems
+    #[arg(long, value_enum, global=true, default_value_t, value_name="WHAT")]
+    special_files: move_files::Special,
+
+    /// Report progress of moves and restores on stderr in this format
+    #[arg(long, value_enum, global=true, value_name="FORMAT")]
+    progress: Option<ProgressFormat>,
+
+    /// How log messages on stderr look, whatever the verbosity. Defaults to `log_format` in
+    /// config.toml, or pretty.
+    #[arg(long, value_enum, global=true, value_name="FORMAT")]
+    log_format: Option<LogFormat>,
+
+    /// Keep the trash and its history under this directory instead of the usual places
+    #[arg(long, global=true, env="TRASH_ROOT", value_name="DIR")]
+    root: Option<PathBuf>,
+
+    /// Keep trashed items in this directory, whatever `trash_dir` in config.toml says
+    #[arg(long, global=true, env="TRASH_DIR", value_name="DIR")]
+    trash_dir: Option<PathBuf>,
+
+    /// Keep the history in this file, whatever `history` in config.toml says
+    #[arg(long, global=true, env="TRASH_HISTORY", value_name="FILE")]
+    history: Option<PathBuf>,
+
+    /// Keep trashed items in the freedesktop.org trash, where file managers and trash-cli see them,
+    /// whatever `freedesktop` in config.toml says
+    #[arg(long, global=true, env="TRASH_FREEDESKTOP")]
+    freedesktop: bool,
+
+    /// Flush trashed items and history to disk before finishing, so a crash right after can't
+    /// lose either. Slower, especially for big directories.
+    #[arg(long, global=true)]
+    sync: bool,
+
+    /// Use the trash even if it, its history or their directories are writable by everyone or
+    /// belong to someone else
+    #[arg(long, global=true)]
+    insecure: bool,
+
+    /// Trash paths even if they match the ignore file next to the config
+    #[arg(long, global=true)]
+    no_ignore: bool,
+
+    /// Whether commands that change the trash wait for another one that is already running or
+    /// fail straight away. Defaults to `lock` in config.toml, or wait.
+    #[arg(long, value_enum, global=true, value_name="MODE")]
+    lock: Option<LockMode>,
+
+    /// Name of file or directory to remove, the same as `trash put`
     #[arg(required_unless_present_any(["undo", "view"]))]
-    name: Option<Vec<String>>
+    name: Option<Vec<OsString>>
 }
 
-#[derive(Debug)]
-pub struct TrashError(String);
-type TrashResult<T> = Result<T, TrashError>;
+impl Args {
+    /// The subcommand given, or the one that bare paths, --undo or --view stand for
+    fn take_command(&mut self) -> Command {
+        if let Some(command) = self.command.take() {
+            return command
+        }
 
-impl TrashError {
-    fn new(err: &str) -> Self {
-        Self(err.to_string())
-    } 
+        if self.view {
+            Command::History(HistoryArgs { stats: self.stats, group_by: self.group_by, timeline: self.timeline })
+        } else if self.undo {
+            Command::Undo { entry: self.entry }
+        } else {
+            Command::Put { options: self.put.clone(), name: self.name.take().unwrap_or_default() }
+        }
+    }
 }
 
-impl From<std::io::Error> for TrashError {
-    fn from(value: std::io::Error) -> Self {
-        Self(value.to_string())
+#[derive(Subcommand)]
+enum Command {
+    /// Move files and directories to the trash, what `trash <NAME>...` does too
+    Put {
+        #[command(flatten)]
+        options: PutOptions,
+
+        /// Name of file or directory to remove
+        #[arg(required=true)]
+        name: Vec<OsString>
+    },
+    /// Put back everything the last trash command trashed
+    Undo {
+        /// Undo this operation instead of the last one, by its number in history. Refused while
+        /// newer operations trashed any of the same paths again.
+        #[arg(long, value_name="N")]
+        entry: Option<usize>
+    },
+    /// Show every trash operation with the items it trashed
+    History(HistoryArgs),
+    /// Restore the most recently trashed item with the given file name
+    Restore(RestoreArgs),
+    /// List every item in the trash along with its ID
+    List(ListArgs),
+    /// Show everything known about one trashed item
+    Info {
+        /// Item ID or history position, or the original or trash path of the item
+        item: String
+    },
+    /// List every trashed version of a path, oldest first
+    Versions {
+        /// Original path of the item
+        path: PathBuf
+    },
+    /// Whether a path (or a file name) was ever trashed, when, and where it is now
+    Which {
+        /// A path, or just a file name to match anywhere
+        query: String
+    },
+    /// Print where the most recently trashed version of a path is, or restore it
+    Latest {
+        /// Original path of the item
+        path: PathBuf,
+
+        /// Restore it instead of printing its trash path
+        #[arg(long)]
+        restore: bool,
+
+        /// Restore without asking for confirmation
+        #[arg(long, short, requires("restore"))]
+        yes: bool
+    },
+    /// Permanently delete everything in the trash
+    Empty {
+        /// Pick which operations and items go, with their sizes, before anything is deleted
+        #[arg(long, short)]
+        interactive: bool,
+
+        /// Only delete items trashed more than this long ago, e.g. 30d, 12h, 2w. With --interactive
+        /// they start out selected and the rest don't.
+        #[arg(long, value_parser(parse_age), value_name="AGE")]
+        older_than: Option<Duration>,
+
+        /// Delete without asking for confirmation
+        #[arg(long, short, conflicts_with("interactive"))]
+        yes: bool
+    },
+    /// Work out what trashing the targets would do and save it, to be reviewed and applied later
+    Plan {
+        /// Files, directories or globs to trash
+        #[arg(required(true))]
+        targets: Vec<OsString>,
+
+        /// Where to save the plan
+        #[arg(long = "out", short, value_name = "FILE")]
+        out: PathBuf
+    },
+    /// Carry out a plan saved by `trash plan`, if nothing it touches has changed since
+    Apply {
+        plan: PathBuf
+    },
+    /// Move everything trashed with another tool into this trash, as one operation
+    Migrate {
+        #[arg(long, value_enum)]
+        from: Source,
+
+        /// Where that tool keeps its trash, if not its default
+        #[arg(long, value_name = "DIR")]
+        dir: Option<PathBuf>
+    },
+    /// Save the history and what is known about each item, not the items themselves, to a TOML
+    /// file (or JSON if it ends in .json)
+    ExportState {
+        file: PathBuf
+    },
+    /// Add the history saved by export-state to this trash's own
+    ImportState {
+        file: PathBuf,
+
+        /// Swap the OLD prefix of original and trash paths for NEW, e.g. after moving the trash
+        /// to another disk. Can be given more than once, the first match wins.
+        #[arg(long, value_name = "OLD=NEW")]
+        rewrite: Vec<Rewrite>
+    },
+    /// Read or change settings in config.toml
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction
+    },
+    /// Print metrics about the trash in the Prometheus text format
+    Metrics {
+        /// Write them to this file for a textfile collector instead, replacing it whole
+        #[arg(long, short, value_name="FILE")]
+        output: Option<PathBuf>
+    },
+    /// Show how much space the trash is using
+    Du {
+        /// Break it down by the directory items were trashed from, biggest first. Directories
+        /// inside another one that items came from count towards that one.
+        #[arg(long)]
+        by_origin: bool
+    },
+    /// Run the maintenance tasks scheduled in config.toml that are due, including any
+    /// missed while the machine was off
+    Maintain {
+        /// Keep running, waking up for each scheduled time
+        #[arg(long)]
+        daemon: bool
+    },
+    /// Show what changed between a trashed item and whatever is at its original path now
+    Diff {
+        /// Item ID or history position, or the original or trash path of the item
+        item: String
+    },
+    /// Permanently delete specific items from the trash
+    Purge {
+        /// Glob matched against the original path of each item, or just its file name if
+        /// the pattern has no slashes
+        #[arg(required_unless_present("id"))]
+        pattern: Option<String>,
+
+        /// Item ID, or history position as shown by history: N for a whole operation, N:M for one item
+        #[arg(long, num_args(1..))]
+        id: Vec<ItemRef>,
+
+        /// Delete without asking for confirmation
+        #[arg(long, short)]
+        yes: bool
     }
 }
 
-impl From<&str> for TrashError {
-    fn from(value: &str) -> Self {
-        Self(value.to_string())
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print a setting, as merged from every config file
+    Get {
+        /// Dotted key, like expire_after or schedule.purge_expired
+        key: String
+    },
+    /// Change a setting in your own config.toml
+    Set {
+        /// Dotted key, like expire_after or schedule.purge_expired
+        key: String,
+
+        /// Read as a TOML value, or as a string if it isn't one
+        value: String
+    }
+}
+
+// How `put` goes about trashing, also taken before bare paths. Not a doc comment, clap would
+// use it as the help text of whatever flattens it.
+#[derive(clap::Args, Clone, Default)]
+struct PutOptions {
+    /// Ask once before trashing more than three items or any directory, instead of for each one
+    #[arg(short='I')]
+    interactive_once: bool,
+
+    /// What to do with targets that are already in the trash
+    #[arg(long, value_enum, default_value_t)]
+    in_trash: InTrash,
+
+    /// Stop at the first target that can't be trashed and put back everything already trashed,
+    /// instead of trashing what can be and exiting with 3
+    #[arg(long)]
+    strict: bool,
+
+    /// Trash what symlinks point to instead of the links themselves
+    #[arg(long)]
+    follow_symlinks: bool,
+
+    /// What to do with targets on another filesystem than the trash. Defaults to local-trash if
+    /// `mount_trash` is set in config.toml, or copy.
+    #[arg(long, value_enum, value_name="POLICY")]
+    xdev: Option<Xdev>
+}
+
+#[derive(clap::Args)]
+struct HistoryArgs {
+    /// Append per-operation and grand totals
+    #[arg(long)]
+    stats: bool,
+
+    /// Group items instead of showing them operation by operation
+    #[arg(long, value_enum)]
+    group_by: Option<GroupBy>,
+
+    /// Show operations under date headings, e.g. Today, Yesterday, 2024-05-02
+    #[arg(long, conflicts_with("group_by"))]
+    timeline: bool
+}
+
+#[derive(clap::Args)]
+struct RestoreArgs {
+    /// File or directory name (not the full path) of the trashed item, its original path with --version,
+    /// or a glob of original paths like 'src/**/*.rs' to restore the latest version of each match
+    #[arg(required_unless_present_any(["all", "last", "id"]), conflicts_with_all(["all", "last", "id"]))]
+    name: Option<String>,
+
+    /// ID of the item to restore, as shown by list or history. A history position restores a
+    /// whole operation (N) or one item of it (N:M), with no need to undo everything since.
+    #[arg(long, conflicts_with_all(["all", "last"]))]
+    id: Option<ItemRef>,
+
+    /// Restore the latest version of every item still in the trash, reporting what couldn't be
+    #[arg(long, short, conflicts_with("last"))]
+    all: bool,
+
+    /// Restore the last N trash operations. Each operation is restored completely or not at all.
+    #[arg(long, short, value_name="N")]
+    last: Option<usize>,
+
+    /// Restore without asking for confirmation
+    #[arg(long, short)]
+    yes: bool,
+
+    /// Restore this version of the item, as numbered by versions. The name is taken as
+    /// the item's original path.
+    #[arg(long, requires("name"), value_name="N")]
+    version: Option<usize>,
+
+    /// Restore items even when their original path holds a file with identical content.
+    /// They are skipped by default.
+    #[arg(long)]
+    even_if_identical: bool,
+
+    /// Copy the item back and leave it in the trash as well
+    #[arg(long, short, conflicts_with_all(["all", "last"]))]
+    keep: bool,
+
+    /// Restore the newest matching item from another tool's trash instead, where it still is
+    #[arg(long, value_enum, requires("name"), conflicts_with_all(["id", "all", "last", "version", "keep"]))]
+    from: Option<Source>,
+
+    /// Where that tool keeps its trash, if not its default
+    #[arg(long, requires("from"), value_name = "DIR")]
+    dir: Option<PathBuf>
+}
+
+#[derive(clap::Args)]
+struct ListArgs {
+    /// Only list items whose original path matches this glob (or file name, if it has no slashes)
+    pattern: Option<String>,
+
+    /// Only list items trashed more than this long ago, e.g. 90d, 12h, 2w
+    #[arg(long, value_parser(parse_age))]
+    older_than: Option<Duration>,
+
+    /// One tab-separated line per item with no colors: ID, original path, trash path,
+    /// trash time in seconds since the epoch, size in bytes. Meant for scripts, the
+    /// format won't change.
+    #[arg(long, conflicts_with_all(["ids", "columns"]))]
+    porcelain: bool,
+
+    /// Print nothing but item IDs, one per line
+    #[arg(long, conflicts_with("columns"))]
+    ids: bool,
+
+    /// Comma-separated columns to show, in order
+    #[arg(long, value_enum, value_delimiter(','), default_value("id,orig,trash"))]
+    columns: Vec<Column>,
+
+    /// List what another tool has in its trash instead. With --porcelain: original path, trash
+    /// path, trash time in seconds since the epoch.
+    #[arg(long, value_enum, conflicts_with_all(["ids", "columns"]))]
+    from: Option<Source>,
+
+    /// Where that tool keeps its trash, if not its default
+    #[arg(long, requires("from"), value_name = "DIR")]
+    dir: Option<PathBuf>
+}
+
+/// A field that can be shown as a column by list
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Column {
+    /// Item ID
+    Id,
+    /// Where the item was trashed from
+    Orig,
+    /// Where the item is in the trash
+    Trash,
+    /// Size on disk
+    Size,
+    /// How long ago it was trashed
+    Age,
+    /// When it was trashed
+    Time,
+    /// Who trashed it
+    User
+}
+
+impl Column {
+    fn header(&self) -> &'static str {
+        match self {
+            Column::Id => "ID",
+            Column::Orig => "Original",
+            Column::Trash => "Trash",
+            Column::Size => "Size",
+            Column::Age => "Age",
+            Column::Time => "Trashed at",
+            Column::User => "User"
+        }
+    }
+
+    fn cell(&self, HistoryPair(old, new, meta): &HistoryPair, now: SystemTime) -> Cell {
+        match self {
+            Column::Id => Cell::id(&meta.id),
+            Column::Orig => Cell::path(old),
+            Column::Trash => Cell::path(new),
+            Column::Size => Cell::plain(human_size(meta.size)),
+            Column::Age => Cell::plain(
+                meta.trashed_at
+                    .and_then(|t| now.duration_since(t).ok())
+                    .map(human_age)
+                    .unwrap_or_else(|| String::from("-"))
+            ),
+            Column::Time => Cell::plain(meta.trashed_at.map(format_time).unwrap_or_else(|| String::from("-"))),
+            Column::User => Cell::plain(meta.user.as_deref().unwrap_or("-"))
+        }
     }
 }
 
-impl From<serde_json::Error> for TrashError {
-    fn from(value: serde_json::Error) -> Self {
-        Self(value.to_string())
+#[derive(Clone, Debug, PartialEq)]
+pub enum ListFormat {
+    Table(Vec<Column>),
+    Porcelain,
+    Ids
+}
+
+/// Rough age in the largest unit that fits, e.g. 45s, 12m, 5h, 3d
+fn human_age(age: Duration) -> String {
+    let secs = age.as_secs();
+
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400)
     }
 }
 
+/// `id, original, trash path, epoch seconds, bytes`, tab-separated. The epoch is left
+/// empty for items trashed before times were recorded.
+fn porcelain_line(old: &Path, new: &Path, meta: &ItemMeta) -> String {
+    let epoch = meta.trashed_at
+        .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs().to_string())
+        .unwrap_or_default();
 
-impl std::fmt::Display for TrashError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", colorize!(Frb->"trash error:", b->self.0.as_str()))
+    format!("{}\t{}\t{}\t{}\t{}", meta.id, old.display(), new.display(), epoch, meta.size)
+}
+
+/// Local date and time, e.g. 2024-05-02 14:03:59
+fn format_time(time: SystemTime) -> String {
+    match jiff::Timestamp::try_from(time) {
+        Ok(ts) => ts.to_zoned(jiff::tz::TimeZone::system()).strftime("%Y-%m-%d %H:%M:%S").to_string(),
+        Err(_) => String::from("unknown")
     }
 }
 
+fn local_date(time: SystemTime) -> Option<jiff::civil::Date> {
+    let ts = jiff::Timestamp::try_from(time).ok()?;
+    Some(ts.to_zoned(jiff::tz::TimeZone::system()).date())
+}
 
-impl Trash {
-    pub fn new(hist_path: PathBuf, trash_path: PathBuf) -> TrashResult<Self> {
-        let file = File::open(&hist_path)?;
-        let reader = BufReader::new(file);
+/// "Today", "Yesterday" or the date itself
+fn day_label(day: jiff::civil::Date, today: jiff::civil::Date) -> String {
+    if day == today {
+        String::from("Today")
+    } else if today.yesterday().is_ok_and(|y| y == day) {
+        String::from("Yesterday")
+    } else {
+        day.to_string()
+    }
+}
 
-        let hist: History = serde_json::from_reader(reader)?;
+/// Exit code when some targets were trashed and others couldn't be
+const PARTIAL_EXIT: u8 = 3;
 
-        Ok(Self {
-            hist_path,
-            hist,
-            trash_path,
-            explain: false
-        })
+/// Prints what a bulk restore did, with a one line count of each outcome at the end
+fn report(summary: &RestoreSummary) {
+    for (old, dest) in summary.renamed.iter() {
+        info!("{}", colorize!(Fyb->"Restored", b->old, Fyb->"as", b->dest));
     }
 
-    pub fn default() -> TrashResult<Self> {
-        let (hist_path, trash_path) = resolve_paths()?;
-        let file = File::open(&hist_path)?;
-        let reader = BufReader::new(file);
+    for path in summary.conflicts.iter() {
+        error!("{}", colorize!(Frb->"Skipped", b->path, b->"- something already exists at the original path"));
+    }
 
-        let hist: History = serde_json::from_reader(reader)?;
-        
-        Ok(Self {
-            hist_path,
-            hist,
-            trash_path,
-            explain: false
-        })
+    for path in summary.identical.iter() {
+        warn!("{}", colorize!(Fyb->"Skipped", b->path, b->"- the file there has identical content"));
     }
 
-    pub fn undo(&mut self) -> TrashResult<()> {
-        let last = match self.hist.pop() {
-            Some(l) => l,
-            None => return Err(TrashError::new("No history found!"))
-        };
+    for path in summary.missing.iter() {
+        error!("{}", colorize!(Frb->"Skipped", b->path, b->"- no longer in the trash"));
+    }
+
+    for (path, e) in summary.failed.iter() {
+        error!("{}", colorize!(Frb->"Failed", b->path, b->"-", b->e.0.as_str()));
+    }
+
+    for op in summary.skipped_ops.iter() {
+        error!("{}", colorize!(Frb->"Operation", b->format!("#{}", op), b->"was left in the trash"));
+    }
+
+    if summary.older > 0 {
+        warn!("{}", colorize!(Fyb->"Left", b->summary.older, Fyb->"older version(s) in the trash, see trash versions"));
+    }
+
+    print_color!(
+        Fgb->"Restored", b->summary.restored,
+        Fyb->"Conflicts", b->summary.conflicts.len(),
+        Fyb->"Identical", b->summary.identical.len(),
+        Fyb->"Missing", b->summary.missing.len(),
+        Frb->"Failed", b->summary.failed.len()
+    );
+}
+
+fn view(trash: &Trash, stats: bool, truncate: bool) {
+    let (mut total_items, mut total_bytes) = (0, 0);
+    let mut table = Table::new(vec!["ID", "Original", "Trash"], truncate);
+
+    for (i, pairs) in trash.history().iter().enumerate() {
+        table.text(colorize!(NFb->"#", Fbb->i + 1));
+
+        for HistoryPair(old, new, meta) in pairs.iter() {
+            table.row(vec![Cell::id(&meta.id), Cell::path(old), Cell::path(new)]);
+        }
+
+        if stats {
+            let bytes: u64 = pairs.iter().map(|p| p.2.size).sum();
+            table.text(colorize!(Fyb->"Total:", b->pairs.len(), b->"item(s),", b->human_size(bytes)));
+            total_items += pairs.len();
+            total_bytes += bytes;
+        }
+    }
+
+    if stats {
+        table.text(colorize!(NFyb->"Grand total:", b->trash.history().len(), b->"operation(s),", b->total_items, b->"item(s),", b->human_size(total_bytes)));
+    }
+
+    table.print();
+}
+
+/// Like `view`, but with items bucketed by the directory they were trashed from
+fn view_by_dir(trash: &Trash, stats: bool, truncate: bool) {
+    let mut table = Table::new(vec!["ID", "Original", "Trash"], truncate);
+    let groups = trash.by_dir();
+
+    for (dir, pairs) in groups.iter() {
+        let bytes: u64 = pairs.iter().map(|p| p.2.size).sum();
+        table.text(colorize!(NFb->link(dir), b->format!("({} item(s), {})", pairs.len(), human_size(bytes))));
+
+        for HistoryPair(old, new, meta) in pairs.iter() {
+            table.row(vec![Cell::id(&meta.id), Cell::path(old), Cell::path(new)]);
+        }
+    }
+
+    if stats {
+        let items: usize = groups.values().map(Vec::len).sum();
+        let bytes: u64 = groups.values().flatten().map(|p| p.2.size).sum();
+        table.text(colorize!(NFyb->"Grand total:", b->groups.len(), b->"director(ies),", b->items, b->"item(s),", b->human_size(bytes)));
+    }
+
+    table.print();
+}
+
+/// Like `view`, but with operations listed under the day they happened on
+fn view_timeline(trash: &Trash, truncate: bool) {
+    let mut table = Table::new(vec!["ID", "Original", "Trash"], truncate);
+    let today = jiff::Zoned::now().date();
+    let mut last_day = None;
+
+    for (i, pairs) in trash.history().iter().enumerate() {
+        // Every item in an operation is trashed at (nearly) the same moment
+        let trashed_at = pairs.first().and_then(|p| p.2.trashed_at);
+        let day = trashed_at.and_then(local_date);
+
+        if i == 0 || day != last_day {
+            let label = day.map_or_else(|| String::from("Unknown date"), |d| day_label(d, today));
+            table.text(colorize!(NFyb->label));
+            last_day = day;
+        }
+
+        let time = trashed_at.map(format_time).and_then(|t| t.split_once(' ').map(|(_, t)| t.to_string()));
+        table.text(colorize!(Fb->"#", Fbb->i + 1, b->time.unwrap_or_default()));
+
+        for HistoryPair(old, new, meta) in pairs.iter() {
+            table.row(vec![Cell::id(&meta.id), Cell::path(old), Cell::path(new)]);
+        }
+    }
+
+    table.print();
+}
+
+fn print_info(trash: &Trash, (op, item): (usize, usize)) {
+    let HistoryPair(old, new, meta) = &trash.history()[op][item];
+    let unknown = || String::from("unknown");
+
+    let hash = match new.symlink_metadata() {
+        Ok(m) if m.is_file() => hash_file(new).unwrap_or_else(|e| e.to_string()),
+        Ok(_) => String::from("-"),
+        Err(_) => unknown()
+    };
+
+    let status = match restorable(old, new) {
+        "no" => "no - it is no longer in the trash",
+        "conflict" => "conflict - something now exists at the original path",
+        status => status
+    };
+
+    print_color!(Fbb->"ID:", b->&meta.id);
+    print_color!(Fbb->"Original path:", b->old);
+    print_color!(Fbb->"Trash path:", b->new);
+    print_color!(Fbb->"Operation:", b->format!("#{} ({} of {} item(s))", op + 1, item + 1, trash.history()[op].len()));
+    print_color!(Fbb->"Trashed at:", b->meta.trashed_at.map(format_time).unwrap_or_else(unknown));
+    print_color!(Fbb->"Trashed by:", b->meta.user.clone().unwrap_or_else(unknown));
+    print_color!(Fbb->"Size:", b->human_size(meta.size));
+
+    if let Some(link) = &meta.link {
+        print_color!(Fbb->"Link to:", b->link);
+    }
+
+    print_color!(Fbb->"SHA-256:", b->hash);
+    print_color!(Fbb->"Restorable:", b->status);
+}
+
+/// One line per item
+fn print_items(trash: &Trash, items: &[(usize, usize)], format: &ListFormat, truncate: bool) {
+    let now = SystemTime::now();
+
+    match format {
+        ListFormat::Table(columns) => {
+            let mut table = Table::new(columns.iter().map(Column::header).collect(), truncate);
+
+            for &(op, item) in items {
+                table.row(columns.iter().map(|c| c.cell(&trash.history()[op][item], now)).collect());
+            }
+
+            table.print();
+        },
+        ListFormat::Porcelain => for &(op, item) in items {
+            let HistoryPair(old, new, meta) = &trash.history()[op][item];
+            println!("{}", porcelain_line(old, new, meta));
+        },
+        ListFormat::Ids => for &(op, item) in items {
+            println!("{}", trash.history()[op][item].2.id);
+        }
+    }
+}
+
+/// Asks a yes/no question on stdin, defaulting to no.
+fn confirm(question: &str) -> bool {
+    print!("{} [y/N] ", question);
+
+    if io::stdout().flush().is_err() {
+        return false
+    }
+
+    let mut answer = String::new();
 
-        let mut unresolved: Vec<HistoryPair> = Vec::with_capacity(last.len());
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false
+    }
+
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+fn restore(trash: &mut Trash, args: RestoreArgs) -> TrashResult<()> {
+    let RestoreArgs { name, id, all, last, yes, version, even_if_identical, keep, from, dir } = args;
 
-        for l in last {
-            let (old, new) = (l.0, l.1);
+    trash.set_skip_identical(!even_if_identical);
+
+    if let Some(source) = from {
+        // Clap requires a name with --from
+        return restore_foreign(trash, source, dir, &name.unwrap_or_default(), yes)
+    }
+
+    if let Some(count) = last {
+        report(&trash.restore_last(count));
+        return Ok(())
+    }
 
-            info!("{}", colorize!(b->"Moving", Fgb->&new, b->"to", Fgb->&old));
+    if all {
+        let question = format!("Restore all {} item(s) in the trash?", trash.newest(trash.select(None, None)).len());
 
-            if self.explain {
-                continue
+        if !yes && !trash.explaining() && !confirm(&question) {
+            info!("{}", colorize!(Fyb->"Nothing restored"));
+            return Ok(())
+        }
+
+        report(&trash.restore_all());
+        return Ok(())
+    }
+
+    let idx = match (id, name) {
+        (None, Some(path)) if version.is_some() => {
+            let versions = trash.versions(Path::new(&path));
+            let n = version.unwrap();
+
+            match n.checked_sub(1).and_then(|i| versions.get(i)) {
+                Some(&idx) => idx,
+                None => return Err(TrashError(format!("{} has {} version(s) in the trash, there is no version {}", path, versions.len(), n)))
             }
-            
-            if let Err(e) = rename(&new, &old) {
-                unresolved.push(HistoryPair(old, new));
-                error!("{}", colorize!(Frb->"trash error:", e))
+        },
+        (Some(ItemRef::Index(HistIndex { op, item: None })), _) => {
+            if keep {
+                return Err(TrashError::new("--keep copies back single items, give one as N:M"))
             }
-            
-        };
 
-        if !unresolved.is_empty() {
-            self.hist.push(unresolved)
+            let count = trash.resolve_index(HistIndex { op, item: None })?.len();
+            let question = format!("Restore the {} item(s) of operation #{}?", count, op);
+
+            if !yes && !trash.explaining() && !confirm(&question) {
+                info!("{}", colorize!(Fyb->"Nothing restored"));
+                return Ok(())
+            }
+
+            report(&trash.restore_entry(op)?);
+            return Ok(())
+        },
+        (Some(id), _) => match trash.resolve(&id)?[..] {
+            [idx] => idx,
+            _ => unreachable!("only a whole operation resolves to several items")
+        },
+        (None, Some(pattern)) if is_glob(&pattern) => {
+            let pattern = original_pattern(&pattern)?;
+            let items = trash.newest(trash.matching(&pattern));
+
+            if items.is_empty() {
+                return Err(TrashError(format!("Nothing in the trash was trashed from {}", pattern)))
+            }
+
+            for &idx in items.iter() {
+                print_color!(Fgb->"Will restore", b->trash.original_path(idx));
+            }
+
+            if !yes && !trash.explaining() && !confirm(&format!("Restore {} item(s)?", items.len())) {
+                info!("{}", colorize!(Fyb->"Nothing restored"));
+                return Ok(())
+            }
+
+            report(&trash.restore_matching(&pattern));
+            return Ok(())
+        },
+        (None, Some(name)) => match trash.find_by_name(&name) {
+            Some(idx) => idx,
+            None => return Err(TrashError(format!("No trashed item named {} found", name)))
+        },
+        // Clap guarantees a name or ID whenever --all and --last are absent
+        (None, None) => unreachable!()
+    };
+
+    restore_one(trash, idx, keep, yes)
+}
+
+/// Restores, or with `keep` copies back, the single item at `idx` after asking
+fn restore_one(trash: &mut Trash, idx: (usize, usize), keep: bool, yes: bool) -> TrashResult<()> {
+    let original = trash.original_path(idx).to_path_buf();
+
+    if trash.is_identical(&original, trash.trashed_path(idx)) {
+        warn!("{}", colorize!(Fyb->"Nothing restored,", b->&original, b->"already has identical content"));
+        trash.record(Action::Skip { path: original, reason: SKIP_IDENTICAL.to_string() });
+        return Ok(())
+    }
+
+    let verb = if keep { "Copy" } else { "Restore" };
+    let question = format!("{} {} to {}?", verb, trash.trashed_path(idx).display(), original.display());
+
+    if !yes && !trash.explaining() && !confirm(&question) {
+        info!("{}", colorize!(Fyb->"Nothing restored"));
+        return Ok(())
+    }
+
+    let dest = match trash.destination(&original) {
+        Some(dest) => dest,
+        None => return Err(TrashError(format!("{} already exists, nothing restored", original.display())))
+    };
+
+    if keep {
+        trash.copy_item(idx, &dest)?;
+    } else {
+        trash.restore_item(idx, &dest)?;
+    }
+
+    if dest != original {
+        info!("{}", colorize!(Fyb->"Restored", b->&original, Fyb->"as", b->&dest));
+    }
+
+    Ok(())
+}
+
+/// Restores items trashed by another tool whose original paths match `name` like a glob of
+/// original paths does, without bringing them into this trash first
+fn restore_foreign(trash: &mut Trash, source: Source, dir: Option<PathBuf>, name: &str, yes: bool) -> TrashResult<()> {
+    let dir = source_dir(source, dir)?;
+    let pattern = original_pattern(name)?;
+    let found = migrate::newest(source.scan(&dir)?.into_iter().filter(|f| f.matches(&pattern)).collect());
+
+    if found.is_empty() {
+        return Err(TrashError(format!("Nothing in the {} trash was trashed from {}", source.name(), name)))
+    }
+
+    for item in found.iter() {
+        print_color!(Fgb->"Will restore", b->&item.original);
+    }
+
+    if !yes && !trash.explaining() && !confirm(&format!("Restore {} item(s) from {}?", found.len(), source.name())) {
+        info!("{}", colorize!(Fyb->"Nothing restored"));
+        return Ok(())
+    }
+
+    report(&trash.restore_foreign(source, &dir, &pattern)?);
+
+    Ok(())
+}
+
+/// `dir`, or wherever `source` keeps its trash by default
+fn source_dir(source: Source, dir: Option<PathBuf>) -> TrashResult<PathBuf> {
+    dir.or_else(|| source.default_dir())
+        .ok_or_else(|| TrashError(format!("Can't tell where {} keeps its trash, give it with --dir", source.name())))
+}
+
+fn is_glob(text: &str) -> bool {
+    text.contains(['*', '?', '['])
+}
+
+/// A glob over original paths. Relative ones with a slash are taken from the current
+/// directory, like the paths that were trashed, and ones without match file names.
+fn original_pattern(text: &str) -> TrashResult<Pattern> {
+    let text = if text.contains('/') && Path::new(text).is_relative() {
+        format!("{}/{}", Pattern::escape(&env::current_dir()?.to_string_lossy()), text)
+    } else {
+        text.to_string()
+    };
+
+    Pattern::new(&text).map_err(|e| TrashError(e.to_string()))
+}
+
+fn list(trash: &Trash, args: ListArgs, no_truncate: bool) -> TrashResult<()> {
+    let pattern = match args.pattern {
+        Some(p) => Some(Pattern::new(&p).map_err(|e| TrashError(e.to_string()))?),
+        None => None
+    };
+
+    if let Some(source) = args.from {
+        return list_foreign(source, args.dir, pattern.as_ref(), args.older_than, args.porcelain, no_truncate)
+    }
+
+    let format = if args.porcelain {
+        ListFormat::Porcelain
+    } else if args.ids {
+        ListFormat::Ids
+    } else {
+        ListFormat::Table(args.columns)
+    };
+
+    print_items(trash, &trash.select(pattern.as_ref(), args.older_than), &format, !no_truncate);
+
+    Ok(())
+}
+
+/// What another tool has in its trash, oldest first. There are no IDs, history doesn't know them.
+fn list_foreign(source: Source, dir: Option<PathBuf>, pattern: Option<&Pattern>, older_than: Option<Duration>, porcelain: bool, no_truncate: bool) -> TrashResult<()> {
+    let now = SystemTime::now();
+    let found: Vec<_> = source.scan(&source_dir(source, dir)?)?
+        .into_iter()
+        .filter(|f| pattern.is_none_or(|p| f.matches(p)))
+        .filter(|f| match (older_than, f.trashed_at) {
+            (None, _) => true,
+            (Some(age), Some(at)) => now.duration_since(at).is_ok_and(|d| d > age),
+            (Some(_), None) => false
+        })
+        .collect();
+
+    if porcelain {
+        for f in found.iter() {
+            let secs = f.trashed_at.and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok()).map(|d| d.as_secs().to_string());
+            println!("{}\t{}\t{}", f.original.display(), f.stored.display(), secs.unwrap_or_default());
         }
 
-        Ok(())
+        return Ok(())
     }
 
-    pub fn remove(&mut self, target: Vec<String>) -> TrashResult<()> {
-        let mut hist_item: HistoryPairs = vec![];
-        let trash_dir = &self.trash_path;
+    let mut table = Table::new(vec!["Original", "Trash", "Trashed at"], !no_truncate);
 
-        // There's no reliable way to tell between normal args and globs, so all are treated as globs
-        for t in target {
-            for e in glob(&t).expect("Failed to read glob") {
-                let old_path = match e {
-                    Ok(ent) if ent == self.hist_path => continue,
-                    Ok(ent) => ent.canonicalize()?,
-                    _ => continue
-                };
-                let mut new_path = PathBuf::from_iter([trash_dir.as_os_str(), old_path.file_name().unwrap()]);
-                
+    for f in found.iter() {
+        table.row(vec![
+            Cell::path(&f.original),
+            Cell::path(&f.stored),
+            Cell::plain(f.trashed_at.map(format_time).unwrap_or_else(|| String::from("-")))
+        ]);
+    }
 
-                info!("{}", colorize!(b->"Moving", Fgb->&old_path, b->"to", Fgb->&new_path));
+    table.print();
 
-                if self.explain {
-                    continue
-                }
+    Ok(())
+}
+
+fn versions(trash: &Trash, path: &Path, no_truncate: bool) -> TrashResult<()> {
+    let versions = trash.versions(path);
+
+    if versions.is_empty() {
+        return Err(TrashError(format!("Nothing trashed from {}", path.display())))
+    }
+
+    let mut table = Table::new(vec!["Version", "ID", "Trashed at", "Size", "Trash"], !no_truncate);
+
+    for (n, &(op, item)) in versions.iter().enumerate() {
+        let HistoryPair(_, new, meta) = &trash.history()[op][item];
+
+        table.row(vec![
+            Cell::plain(n + 1),
+            Cell::id(&meta.id),
+            Cell::plain(meta.trashed_at.map(format_time).unwrap_or_else(|| String::from("-"))),
+            Cell::plain(human_size(meta.size)),
+            Cell::path(new)
+        ]);
+    }
+
+    table.print();
+
+    Ok(())
+}
+
+fn which(trash: &Trash, query: &str, no_truncate: bool) -> TrashResult<()> {
+    let found = trash.which(query);
+
+    if found.is_empty() {
+        return Err(TrashError(format!("{} was never trashed", query)))
+    }
+
+    let mut table = Table::new(vec!["Op", "ID", "Trashed at", "Original", "Trash", "Restorable"], !no_truncate);
+
+    for (op, item, now) in &found {
+        let HistoryPair(old, _, meta) = &trash.history()[*op][*item];
+        let original = old.join(now.strip_prefix(trash.trashed_path((*op, *item))).unwrap_or(Path::new("")));
+
+        table.row(vec![
+            Cell::plain(op + 1),
+            Cell::id(&meta.id),
+            Cell::plain(meta.trashed_at.map(format_time).unwrap_or_else(|| String::from("-"))),
+            Cell::path(&original),
+            Cell::path(now),
+            Cell::plain(restorable(&original, now))
+        ]);
+    }
+
+    table.print();
+
+    Ok(())
+}
+
+/// `yes`, `no` when the trashed copy is gone, or `conflict` when something took its old place
+fn restorable(old: &Path, new: &Path) -> &'static str {
+    if new.symlink_metadata().is_err() {
+        "no"
+    } else if old.symlink_metadata().is_ok() {
+        "conflict"
+    } else {
+        "yes"
+    }
+}
+
+fn latest(trash: &mut Trash, path: PathBuf, restore_it: bool, yes: bool) -> TrashResult<()> {
+    let versions = trash.versions(&path);
+
+    let Some(&(op, item)) = versions.last() else {
+        return Err(TrashError(format!("Nothing trashed from {}", path.display())))
+    };
+
+    if !restore_it {
+        println!("{}", trash.history()[op][item].1.display());
+        return Ok(())
+    }
+
+    // By position, as a path that isn't valid UTF-8 can't be given as a name
+    restore_one(trash, (op, item), false, yes)
+}
+
+fn save_plan(trash: &mut Trash, targets: Vec<OsString>, out: &Path) -> TrashResult<()> {
+    trash.toggle_explain();
+    trash.remove(targets)?;
+
+    let mut plan = trash.take_plan();
+
+    plan.trash = Some(trash.trash_path().to_path_buf());
+    plan.snapshot();
+    plan.print(PlanFormat::Text, true);
+
+    fs::write(out, serde_json::to_string_pretty(&plan)?)?;
+    info!("{}", colorize!(Fgb->"Plan saved to", b->out));
+
+    Ok(())
+}
 
-                if new_path.exists() {
-                    new_path = new_dir_name(new_path);
-                    info!("{}", colorize!(b->"Directory path already exists. Switching to", Fgb->&new_path));
+fn apply(trash: &mut Trash, path: &Path) -> TrashResult<()> {
+    let file = File::open(path).map_err(|e| TrashError(format!("{}: {}", path.display(), e)))?;
+    let plan: Plan = serde_json::from_reader(BufReader::new(file))?;
+
+    trash.apply(&plan)
+}
+
+fn export_metrics(trash: &Trash, output: Option<&Path>) -> TrashResult<()> {
+    let counters = metrics::Counters::load(&metrics::counters_path(trash.hist_path()))?;
+    let text = metrics::render(&trash.gauges(), &counters);
+
+    match output {
+        Some(path) => metrics::write_textfile(path, &text),
+        None => {
+            print!("{}", text);
+            Ok(())
+        }
+    }
+}
+
+/// Counting is best effort, a failure to save the counts never fails the operation
+fn record_op(hist_path: &Path, op: &str, ok: bool) {
+    let path = metrics::counters_path(hist_path);
+
+    let res = metrics::Counters::load(&path).and_then(|mut counters| {
+        counters.record(op, ok);
+        counters.save(&path)
+    });
+
+    if let Err(e) = res {
+        warn!("Could not update metrics: {}", e);
+    }
+}
+
+fn configure(root: Option<&Path>, action: &ConfigAction) -> TrashResult<()> {
+    let dir = config_dir(root);
+
+    match action {
+        ConfigAction::Get { key } => config::get(&config::layers(&dir, root.is_some()), key).map(|value| match value.as_str() {
+            Some(text) => println!("{}", text),
+            None => println!("{}", value)
+        }),
+        ConfigAction::Set { key, value } => {
+            secure::create_private_dir(&dir)?;
+            config::set(&config::config_path(&dir), key, value)
+        }
+    }
+}
+
+/// Takes the lock that keeps runs which change the trash from rewriting history at the same
+/// time. It has to be held before the history is read, or the run works from a stale copy.
+fn lock_history(root: Option<&Path>, config: &Config, overrides: &Overrides, mode: Option<LockMode>) -> TrashResult<Lock> {
+    let (hist_path, _) = resolve_paths(root, config, overrides)?;
+
+    Lock::acquire(&lock::lock_path(&hist_path), mode.or(config.lock).unwrap_or_default())
+}
+
+/// Purges everything trashed more than `age` ago and saves history straight away, as
+/// some commands return without writing it
+fn expire(trash: &mut Trash, age: Duration) -> TrashResult<()> {
+    let items = trash.select(None, Some(age));
+
+    if items.is_empty() {
+        return Ok(())
+    }
+
+    info!("{} item(s) expired", items.len());
+
+    let res = trash.purge(items);
+
+    if !trash.explaining() {
+        trash.write()?;
+        record_op(trash.hist_path(), config::Task::PurgeExpired.name(), res.is_ok());
+    }
+
+    res
+}
+
+/// Longest the daemon sleeps at a time, so it notices config changes and clock jumps
+const MAINTAIN_POLL: Duration = Duration::from_secs(60 * 60);
+
+fn maintain(root: Option<&Path>, overrides: &Overrides, daemon: bool, explain: bool, lock: Option<LockMode>) -> TrashResult<()> {
+    loop {
+        let res = run_due_tasks(root, overrides, explain, lock);
+
+        if !daemon {
+            return res.map(|_| ())
+        }
+
+        let wait = match res {
+            Ok(Some(next)) => next.duration_since(SystemTime::now()).unwrap_or_default().min(MAINTAIN_POLL),
+            Ok(None) => MAINTAIN_POLL,
+            Err(e) => {
+                error!("{}", e);
+                MAINTAIN_POLL
+            }
+        };
+
+        debug!("Next maintenance check in {}", human_age(wait));
+        std::thread::sleep(wait);
+    }
+}
+
+/// Runs every scheduled task that is due and returns when the next one will be
+fn run_due_tasks(root: Option<&Path>, overrides: &Overrides, explain: bool, lock: Option<LockMode>) -> TrashResult<Option<SystemTime>> {
+    let config = load_config(root)?;
+    let _lock = lock_history(root, &config, overrides, lock)?;
+    let mut trash = Trash::try_new(root, &config, overrides)?;
+
+    if explain {
+        trash.toggle_explain();
+    }
+
+    let state_path = trash.hist_path().with_file_name("maintenance.json");
+    let mut state = schedule::State::load(&state_path)?;
+    let now = jiff::Zoned::now();
+
+    for (task, cron) in config.schedule.tasks() {
+        if !state.is_due(task.name(), cron, &now) {
+            continue
+        }
+
+        info!("{}", colorize!(Fgb->"Running", b->task.name()));
+
+        let res = match task {
+            config::Task::PurgeExpired => {
+                let items = trash.select(None, config.expire_after);
+
+                info!("{} item(s) expired", items.len());
+
+                if items.is_empty() { Ok(()) } else { trash.purge(items) }
+            },
+            config::Task::Verify => {
+                let missing = trash.unrestorable();
+
+                for path in missing.iter() {
+                    warn!("{}", colorize!(Fyb->"Can't be restored, gone from the trash:", b->path));
                 }
-                
-                // Todo: Better error handling when move doesn't work
-                rename(&old_path, &new_path)?;
 
-                let pair = HistoryPair(old_path, new_path);
+                if let (Some(hook), false) = (&config.webhook, missing.is_empty() || explain) {
+                    hook.notify(&Event::Unrestorable { paths: missing.iter().map(|p| p.to_path_buf()).collect() });
+                }
 
-                hist_item.push(pair);
+                Ok(())
             }
+        };
+
+        if !explain {
+            record_op(trash.hist_path(), task.name(), res.is_ok());
         }
 
-        self.hist.push(hist_item);
+        res?;
+        state.ran(task.name(), SystemTime::now());
+    }
+
+    if !explain {
+        trash.write()?;
+        state.save(&state_path)?;
 
-        Ok(())
+        if let Some(path) = &config.metrics_file {
+            export_metrics(&trash, Some(path))?;
+        }
     }
 
-    pub fn view(&self) {
-        for (i, pairs) in self.hist.iter().enumerate() {
-            print_color!(NFb->"#", Fbb->i + 1);
-            for pair in pairs.iter() {
-                print_color!(Fgb->"Moved", b->&pair.0, Fgb->"to", b->&pair.1)
-            }
+    Ok(config.schedule.tasks().iter()
+        .filter_map(|(_, cron)| cron.next(now.datetime()).and_then(schedule::to_system_time))
+        .min())
+}
+
+fn du(trash: &Trash, by_origin: bool, no_truncate: bool) {
+    let total: u64 = trash.history().iter().flatten().map(|p| p.2.size).sum();
+
+    if by_origin {
+        let mut table = Table::new(vec!["Origin", "Items", "Size"], !no_truncate);
+
+        for (dir, count, bytes) in trash.usage_by_origin() {
+            table.row(vec![Cell::path(&dir), Cell::plain(count), Cell::plain(human_size(bytes))]);
         }
+
+        table.print();
     }
 
-    pub fn write(&self) -> TrashResult<()> {
-        let file = File::create(&self.hist_path)?;
+    print_color!(Fyb->"Total:", b->trash.item_count(), b->"item(s),", b->human_size(total));
+}
 
-        serde_json::to_writer_pretty(file, &self.hist)?;
-        Ok(())
+/// A single item given as an ID, history position or either of its paths
+fn find_item(trash: &Trash, item: &str) -> TrashResult<(usize, usize)> {
+    let by_ref = match item.parse::<ItemRef>() {
+        Ok(item_ref) => trash.resolve(&item_ref).ok(),
+        Err(_) => None
+    };
+
+    match by_ref.as_deref() {
+        Some([idx]) => Ok(*idx),
+        Some(_) => Err(TrashError::new("That's a whole operation, pick one item with N:M")),
+        None => trash
+            .find_by_path(Path::new(item))
+            .ok_or_else(|| TrashError(format!("Nothing in the trash matches {}", item)))
     }
+}
 
-    pub fn toggle_explain(&mut self) {
-        self.explain = true;
+fn info(trash: &Trash, item: &str) -> TrashResult<()> {
+    print_info(trash, find_item(trash, item)?);
+
+    Ok(())
+}
+
+/// Runs `diff -u` from the trashed copy to whatever is at the original path now (an empty
+/// file if nothing is). Returns whether they differ.
+fn diff(trash: &Trash, item: &str) -> TrashResult<bool> {
+    let (op, item) = find_item(trash, item)?;
+    let HistoryPair(old, new, _) = &trash.history()[op][item];
+
+    if new.symlink_metadata().is_err() {
+        return Err(TrashError(format!("{} is no longer in the trash", new.display())))
     }
 
+    let current = if old.symlink_metadata().is_ok() { old.as_path() } else { Path::new(NULL_DEVICE) };
+
+    let status = std::process::Command::new("diff")
+        .args(["-u", "-r", "-N"])
+        .arg("-L").arg(format!("trash: {}", old.display()))
+        .arg("-L").arg(old)
+        .arg(new)
+        .arg(current)
+        .status()
+        .map_err(|e| TrashError(format!("Could not run diff: {}", e)))?;
+
+    // Same convention as diff itself: 0 the same, 1 different, anything else is trouble
+    match status.code() {
+        Some(0) => Ok(false),
+        Some(1) => Ok(true),
+        _ => Err(TrashError::new("diff failed"))
+    }
 }
 
+#[cfg(unix)]
+const NULL_DEVICE: &str = "/dev/null";
+#[cfg(windows)]
+const NULL_DEVICE: &str = "NUL";
 
-pub fn resolve_paths() -> TrashResult<(PathBuf, PathBuf)> {
-    let mut hist_path = PathBuf::from_iter([
-        dirs::home_dir().unwrap(),
-        PathBuf::from(".config/trash/")
-    ]);
+fn purge(trash: &mut Trash, pattern: Option<String>, ids: Vec<ItemRef>, yes: bool) -> TrashResult<()> {
+    let mut items = vec![];
 
-    if !hist_path.try_exists().unwrap() {
-        fs::create_dir_all(&hist_path)?;
+    if let Some(pattern) = pattern {
+        let pattern = Pattern::new(&pattern).map_err(|e| TrashError(e.to_string()))?;
+        items.extend(trash.matching(&pattern));
     }
 
-    hist_path.push("trash-history.json");
+    for id in ids.iter() {
+        items.extend(trash.resolve(id)?);
+    }
+
+    items.sort_unstable();
+    items.dedup();
+
+    if items.is_empty() {
+        return Err(TrashError::new("Nothing in the trash matched"))
+    }
 
-    if !hist_path.try_exists().unwrap() {
-        let mut file = File::create(&hist_path)?;
-        file.write_all(b"[]").unwrap();
+    for &idx in items.iter() {
+        print_color!(Frb->"Will delete", b->trash.trashed_path(idx), Fyb->"from", b->trash.original_path(idx));
     }
 
-    let mut trash_dir = env::temp_dir();
-    trash_dir.push("trash/");
+    let question = format!("Permanently delete {} item(s)?", items.len());
 
-    // Most likely meaning the computer has restart and /tmp has been cleared
-    // New cfg is necessary along with the creation of the directory
-    if !trash_dir.try_exists()? {
-        fs::create_dir(&trash_dir)?;
-        let mut file = File::create(&hist_path)?;
-        file.write_all(b"[]")?;
+    if !yes && !trash.explaining() && !confirm(&question) {
+        info!("{}", colorize!(Fyb->"Nothing deleted"));
+        return Ok(())
     }
 
-    Ok((hist_path, trash_dir))
+    trash.purge(items)
 }
 
-fn new_dir_name(mut dir: PathBuf) -> PathBuf {
-    let mut count = 1;
+fn empty(trash: &mut Trash, interactive: bool, older_than: Option<Duration>, yes: bool, no_truncate: bool) -> TrashResult<()> {
+    if trash.history().is_empty() {
+        warn!("{}", colorize!(Fyb->"The trash is already empty"));
+        return Ok(())
+    }
+
+    let all: Vec<(usize, usize)> = trash.select(None, None);
+    let candidates: Vec<(usize, usize)> = trash.select(None, older_than);
+
+    if !interactive {
+        let bytes: u64 = candidates.iter().map(|&(op, item)| trash.history()[op][item].2.size).sum();
+
+        let question = match older_than {
+            Some(age) if candidates.is_empty() => {
+                info!("{}", colorize!(Fyb->"Nothing in the trash is older than", b->human_age(age)));
+                return Ok(())
+            },
+            Some(age) => format!("Permanently delete {} item(s) trashed more than {} ago, {}?", candidates.len(), human_age(age), human_size(bytes)),
+            None => format!("Permanently delete all {} item(s), {}?", candidates.len(), human_size(bytes))
+        };
+
+        if !yes && !trash.explaining() && !confirm(&question) {
+            info!("{}", colorize!(Fyb->"Nothing deleted"));
+            return Ok(())
+        }
+
+        return trash.purge(candidates)
+    }
+
+    // Everything (old enough) starts out selected, it's the things to keep that get picked out
+    let mut selected: Vec<Vec<bool>> = trash.history().iter().map(|pairs| vec![false; pairs.len()]).collect();
+
+    for &(op, item) in candidates.iter() {
+        selected[op][item] = true;
+    }
 
     loop {
-        dir.set_extension(count.to_string());
+        print_selection(trash, &selected, !no_truncate);
+        print!("Toggle N or N:M, (a)ll, (n)one, (d)elete selected, (q)uit: ");
 
-        if !dir.exists() {
-            return dir
+        let mut answer = String::new();
+
+        if io::stdout().flush().is_err() || io::stdin().read_line(&mut answer).map_or(true, |n| n == 0) {
+            return Ok(())
         }
 
-        count += 1;
+        for word in answer.split(|c: char| c.is_whitespace() || c == ',').filter(|w| !w.is_empty()) {
+            match word {
+                "a" | "all" => selected.iter_mut().flatten().for_each(|s| *s = true),
+                "n" | "none" => selected.iter_mut().flatten().for_each(|s| *s = false),
+                "q" | "quit" => {
+                    info!("{}", colorize!(Fyb->"Nothing deleted"));
+                    return Ok(())
+                },
+                "d" | "delete" => {
+                    let items: Vec<(usize, usize)> = all.iter().copied().filter(|&(op, item)| selected[op][item]).collect();
+
+                    if items.is_empty() {
+                        info!("{}", colorize!(Fyb->"Nothing selected, nothing deleted"));
+                        return Ok(())
+                    }
+
+                    if !trash.explaining() && !confirm(&format!("Permanently delete {} item(s)?", items.len())) {
+                        info!("{}", colorize!(Fyb->"Nothing deleted"));
+                        return Ok(())
+                    }
+
+                    return trash.purge(items)
+                },
+                _ => match word.parse::<HistIndex>().map_err(TrashError).and_then(|idx| trash.resolve_index(idx)) {
+                    Ok(items) => toggle(&mut selected, &items),
+                    Err(e) => error!("{}", e)
+                }
+            }
+        }
     }
 }
 
+/// Flips a single item, or a whole operation: off if all of it was selected, on otherwise
+fn toggle(selected: &mut [Vec<bool>], items: &[(usize, usize)]) {
+    let on = !items.iter().all(|&(op, item)| selected[op][item]);
+
+    for &(op, item) in items {
+        selected[op][item] = on;
+    }
+}
+
+fn print_selection(trash: &Trash, selected: &[Vec<bool>], truncate: bool) {
+    let mark = |on: bool| if on { "[x]" } else { "[ ]" };
+    let mut table = Table::new(vec!["", "Item", "Original", "Size"], truncate);
+    let (mut items, mut bytes) = (0, 0);
+
+    for (op, pairs) in trash.history().iter().enumerate() {
+        let op_bytes: u64 = pairs.iter().map(|p| p.2.size).sum();
+        let op_mark = match selected[op].iter().filter(|&&s| s).count() {
+            0 => "[ ]",
+            n if n == pairs.len() => "[x]",
+            _ => "[-]"
+        };
+
+        table.text(colorize!(NFb->format!("{} #{}", op_mark, op + 1), b->format!("({} item(s), {})", pairs.len(), human_size(op_bytes))));
+
+        for (item, HistoryPair(old, _, meta)) in pairs.iter().enumerate() {
+            let on = selected[op][item];
+
+            if on {
+                items += 1;
+                bytes += meta.size;
+            }
+
+            table.row(vec![
+                Cell::plain(mark(on)),
+                Cell::id(&format!("{}:{}", op + 1, item + 1)),
+                Cell::path(old),
+                Cell::plain(human_size(meta.size))
+            ]);
+        }
+    }
+
+    table.text(colorize!(NFyb->"Selected:", b->items, b->"item(s),", b->human_size(bytes)));
+    table.print();
+}
+
 fn main() -> ExitCode {
-    let args = Args::parse();
+    let mut args = Args::parse();
+    let command = args.take_command();
 
+    let loaded = load_config(args.root.as_deref());
     let mut logger = Builder::new();
 
-    logger.format(|buf, record| {
-        writeln!(
-            buf,
-            "{}",
-            record.args()
-        )
+    // A broken config is reported once the logger is up, with the default format
+    let log_format = args.log_format.or(loaded.as_ref().ok().and_then(|config| config.log_format)).unwrap_or_default();
+
+    logger.format(move |buf, record| logging::write(buf, record, log_format));
+
+    logger.filter_level(match args.verbose.max(args.explain as u8) {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace
     });
 
-    if args.verbose || args.explain {
-        logger.filter_level(LevelFilter::Debug);
+    logger.init();
+
+    // Before the trash is opened, which needs a working config, so a broken one can be fixed
+    if let Command::Config { action } = &command {
+        return match configure(args.root.as_deref(), action) {
+            Ok(_) => ExitCode::SUCCESS,
+            Err(e) => {
+                error!("{}", e);
+                ExitCode::FAILURE
+            }
+        }
+    }
+
+    let loaded = if args.root.is_none() && setup::should_run(&config_dir(None)) {
+        match setup::run(&config_dir(None)) {
+            Ok(_) => load_config(None),
+            Err(e) => {
+                error!("{}", e);
+                return ExitCode::FAILURE
+            }
+        }
+    } else {
+        loaded
     };
 
-    logger.init();
+    let config = match loaded {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            return ExitCode::FAILURE
+        }
+    };
+
+    // maintain purges on its own schedule and takes the lock itself
+    let expiring = match &command {
+        Command::Maintain { .. } => None,
+        _ => config.expire_after.filter(|_| config.expire_on_run)
+    };
 
-    let mut trash = match Trash::default() {
+    // Operations that change the trash, counted for metrics
+    let op = match &command {
+        Command::Restore(_) | Command::Latest { restore: true, .. } => Some("restore"),
+        Command::Empty { .. } => Some("empty"),
+        Command::Apply { .. } => Some("apply"),
+        Command::Migrate { .. } => Some("migrate"),
+        Command::ImportState { .. } => Some("import_state"),
+        Command::Purge { .. } => Some("purge"),
+        Command::Undo { .. } => Some("undo"),
+        Command::Put { .. } => Some("remove"),
+        _ => None
+    };
+
+    // Anything that changes history holds the lock from reading it to writing it back
+    let locking = op.is_some() || expiring.is_some();
+
+    // Relative to where trash was run, history only keeps absolute paths
+    let overrides = Overrides {
+        trash_dir: args.trash_dir.take().and_then(|dir| std::path::absolute(dir).ok()),
+        history: args.history.take().and_then(|file| std::path::absolute(file).ok())
+    };
+
+    let _lock = match locking.then(|| lock_history(args.root.as_deref(), &config, &overrides, args.lock)).transpose() {
+        Ok(lock) => lock,
+        Err(e) => {
+            error!("{}", e);
+            return ExitCode::FAILURE
+        }
+    };
+
+    let mut trash = match Trash::try_new(args.root.as_deref(), &config, &overrides) {
         Ok(t) => t,
         Err(e) => {
             error!("{}", e);
@@ -283,9 +1581,11 @@ fn main() -> ExitCode {
         }
     };
 
-    if args.view {
-        trash.view();
-        return ExitCode::SUCCESS
+    if !args.insecure {
+        if let Err(e) = trash.check_private() {
+            error!("{}", e);
+            return ExitCode::FAILURE
+        }
     }
 
     if args.explain {
@@ -293,23 +1593,207 @@ fn main() -> ExitCode {
         trash.toggle_explain();
     }
 
-    if args.undo {
-        if let Err(e) = trash.undo() {
-            error!("{}", e);
-            return ExitCode::FAILURE
+    trash.set_prompt(confirm);
+    trash.set_conflict(args.on_conflict);
+
+    if let Command::Put { options, .. } = &command {
+        trash.set_in_trash(options.in_trash);
+        trash.set_strict(options.strict);
+        trash.set_follow_symlinks(options.follow_symlinks);
+
+        if let Some(xdev) = options.xdev {
+            trash.set_xdev(xdev);
         }
-    } else {
-        if let Err(e) = trash.remove(args.name.unwrap()) {
-            error!("{}", e);
-            return ExitCode::FAILURE
+
+        if options.interactive_once {
+            trash.set_confirm_bulk(true);
+        }
+    }
+
+    trash.set_progress(args.progress);
+
+    if args.sync {
+        trash.set_sync(true);
+    }
+
+    if let Some(fd) = FreeDesktop::home().filter(|_| args.freedesktop) {
+        trash.set_backend(Box::new(fd));
+    }
+
+    // Only runs that change the trash clean up after one that died, they hold the lock
+    if locking && !args.explain {
+        if let Err(e) = trash.recover() {
+            warn!("{}", colorize!(Fyb->"Could not recover from an interrupted run -", b->e));
+        }
+    }
+
+    if let Some(age) = expiring {
+        if let Err(e) = expire(&mut trash, age) {
+            warn!("{}", colorize!(Fyb->"Could not purge expired items -", b->e));
         }
     }
 
-    if !args.explain {
+    if !args.no_ignore {
+        match Ignore::load(&config::ignore_layers(&config_dir(args.root.as_deref()), args.root.is_some())) {
+            Ok(ignore) => trash.set_ignore(ignore),
+            Err(e) => {
+                error!("{}", e);
+                return ExitCode::FAILURE
+            }
+        }
+    }
+
+    trash.set_buffer_size(args.buffer_size);
+    trash.set_sorted(args.sorted);
+    trash.set_retries(args.retries);
+    trash.set_special_files(args.special_files);
+
+    let res = match command {
+        Command::Restore(restore_args) => restore(&mut trash, restore_args),
+        Command::List(list_args) => {
+            return match list(&trash, list_args, args.no_truncate) {
+                Ok(_) => ExitCode::SUCCESS,
+                Err(e) => {
+                    error!("{}", e);
+                    ExitCode::FAILURE
+                }
+            }
+        },
+        Command::Info { item } => {
+            return match info(&trash, &item) {
+                Ok(_) => ExitCode::SUCCESS,
+                Err(e) => {
+                    error!("{}", e);
+                    ExitCode::FAILURE
+                }
+            }
+        },
+        Command::Which { query } => {
+            return match which(&trash, &query, args.no_truncate) {
+                Ok(_) => ExitCode::SUCCESS,
+                Err(e) => {
+                    error!("{}", e);
+                    ExitCode::FAILURE
+                }
+            }
+        },
+        Command::Versions { path } => {
+            return match versions(&trash, &path, args.no_truncate) {
+                Ok(_) => ExitCode::SUCCESS,
+                Err(e) => {
+                    error!("{}", e);
+                    ExitCode::FAILURE
+                }
+            }
+        },
+        Command::Du { by_origin } => {
+            du(&trash, by_origin, args.no_truncate);
+            return ExitCode::SUCCESS
+        },
+        Command::Maintain { daemon } => {
+            return match maintain(args.root.as_deref(), &overrides, daemon, args.explain, args.lock) {
+                Ok(_) => ExitCode::SUCCESS,
+                Err(e) => {
+                    error!("{}", e);
+                    ExitCode::FAILURE
+                }
+            }
+        },
+        Command::Plan { targets, out } => {
+            return match save_plan(&mut trash, targets, &out) {
+                Ok(_) => ExitCode::SUCCESS,
+                Err(e) => {
+                    error!("{}", e);
+                    ExitCode::FAILURE
+                }
+            }
+        },
+        Command::Apply { plan } => apply(&mut trash, &plan),
+        Command::ExportState { file } => {
+            return match trash.export_state().save(&file) {
+                Ok(_) => ExitCode::SUCCESS,
+                Err(e) => {
+                    error!("{}", e);
+                    ExitCode::FAILURE
+                }
+            }
+        },
+        Command::ImportState { file, rewrite } => match State::load(&file) {
+            Ok(_) if trash.explaining() => {
+                info!("{}", colorize!(b->"Would import", Fgb->&file));
+                Ok(())
+            },
+            Ok(state) => trash.import_state(state, &rewrite),
+            Err(e) => Err(e)
+        },
+        Command::Migrate { from, dir } => source_dir(from, dir).and_then(|dir| trash.migrate(from, &dir)),
+        Command::Config { .. } => unreachable!("config is handled before the trash is opened"),
+        Command::Metrics { output } => {
+            return match export_metrics(&trash, output.as_deref()) {
+                Ok(_) => ExitCode::SUCCESS,
+                Err(e) => {
+                    error!("{}", e);
+                    ExitCode::FAILURE
+                }
+            }
+        },
+        Command::Diff { item } => {
+            return match diff(&trash, &item) {
+                Ok(false) => ExitCode::SUCCESS,
+                Ok(true) => ExitCode::from(1),
+                Err(e) => {
+                    error!("{}", e);
+                    ExitCode::from(2)
+                }
+            }
+        },
+        Command::Latest { path, restore, yes } => latest(&mut trash, path, restore, yes),
+        Command::Empty { interactive, older_than, yes } => empty(&mut trash, interactive, older_than, yes, args.no_truncate),
+        Command::Purge { pattern, id, yes } => purge(&mut trash, pattern, id, yes),
+        Command::History(HistoryArgs { stats, group_by, timeline }) => {
+            match group_by {
+                Some(GroupBy::Dir) => view_by_dir(&trash, stats, !args.no_truncate),
+                None if timeline => view_timeline(&trash, !args.no_truncate),
+                None => view(&trash, stats, !args.no_truncate)
+            }
+            return ExitCode::SUCCESS
+        },
+        Command::Undo { entry } => match entry {
+            Some(entry) => trash.undo_entry(entry),
+            None => trash.undo()
+        },
+        Command::Put { name, .. } => trash.remove(name)
+    };
+
+    if args.explain {
+        trash.plan().print(args.output.unwrap_or_default(), !args.no_truncate);
+    }
+
+    // History is written even after an error, it has to account for whatever was done before it
+    if !args.explain && op.is_some() {
         if let Err(e) = trash.write() {
             error!("{}", e);
             return ExitCode::FAILURE
         }
+
+        if let Some(op) = op {
+            record_op(trash.hist_path(), op, res.is_ok());
+        }
+    }
+
+    if let Err(e) = res {
+        for (path, why) in trash.failures() {
+            error!("{}", colorize!(Frb->"Not trashed:", b->path, b->"-", b->why));
+        }
+
+        error!("{}", e);
+
+        // Some of it went through, scripts need to tell that apart from nothing at all
+        if !trash.failures().is_empty() && trash.trashed() > 0 {
+            return ExitCode::from(PARTIAL_EXIT)
+        }
+
+        return ExitCode::FAILURE
     }
 
     ExitCode::SUCCESS
@@ -318,174 +1802,221 @@ fn main() -> ExitCode {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs::create_dir;
 
-    fn trash_dir() -> (tempfile::TempDir, PathBuf) {
+    /// A trash with test_dir/test0.txt to test2.txt next to it
+    fn trash_dir() -> (tempfile::TempDir, Trash) {
         let tmp_dir = tempfile::tempdir().unwrap();
-        let mut tmp_path = tmp_dir.path().to_owned();
+        let hist_path = tmp_dir.path().join("trash-history.json");
 
-        tmp_path.push("trash-history.json");
+        fs::write(&hist_path, "").unwrap();
+        fs::create_dir(tmp_dir.path().join("trash_dir")).unwrap();
+        fs::create_dir(tmp_dir.path().join("test_dir")).unwrap();
 
-        let hist_path = tmp_path.clone();
+        for i in 0..3 {
+            fs::write(tmp_dir.path().join(format!("test_dir/test{}.txt", i)), "").unwrap();
+        }
 
-        let mut cfg = File::create(&tmp_path).unwrap();
-        cfg.write_all(b"[]").unwrap();
-        tmp_path.pop();
+        let trash = Trash::new(hist_path, tmp_dir.path().join("trash_dir")).unwrap();
 
-        tmp_path.push("test_dir");
-        create_dir(&tmp_path).unwrap();
+        (tmp_dir, trash)
+    }
 
-        for i in 0..3 {
-            tmp_path.push(format!("test{}.txt", i));
-            File::create(&tmp_path).unwrap();
-            tmp_path.pop();
-        }
+    /// Reopens `trash` with the item at `idx` trashed `age` ago, by way of the history file
+    fn backdate(mut trash: Trash, (op, item): (usize, usize), age: Duration) -> Trash {
+        trash.write().unwrap();
 
-        tmp_path.pop();
-        tmp_path.push("trash_dir");
-        create_dir(&tmp_path).unwrap();
-    
+        let (mut hist, _) = trash::history::load(trash.hist_path()).unwrap();
+        hist[op][item].2.trashed_at = Some(SystemTime::now() - age);
+        trash::history::rewrite(trash.hist_path(), &hist, false).unwrap();
 
-        (tmp_dir, hist_path)
+        Trash::new(trash.hist_path().to_path_buf(), trash.trash_path().to_path_buf()).unwrap()
     }
 
     #[test]
-    fn test_trash() {
-    
-        let (tmp_dir, hist_path) = trash_dir();
-        let mut trash_dir = tmp_dir.path().to_owned();
-        let mut test_dir = tmp_dir.path().to_owned();
-
-        trash_dir.push("trash_dir");
-        test_dir.push("test_dir");
+    fn test_porcelain_line() {
+        let meta = ItemMeta {
+            id: String::from("abcdef"),
+            size: 42,
+            trashed_at: Some(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1700000000)),
+            ..Default::default()
+        };
 
-        env::set_current_dir(&test_dir).unwrap();
+        assert_eq!(
+            porcelain_line(Path::new("/home/me/a.txt"), Path::new("/tmp/trash/a.txt"), &meta),
+            "abcdef\t/home/me/a.txt\t/tmp/trash/a.txt\t1700000000\t42"
+        );
 
-        let files: Vec<String> = test_dir
-            .read_dir()
-            .unwrap()
-            .map(|f|{
-                f.unwrap()
-                    .file_name()
-                    .to_str()
-                    .unwrap()
-                    .to_string()
-            })
-            .collect();
+        assert_eq!(
+            porcelain_line(Path::new("/a"), Path::new("/b"), &ItemMeta::default()),
+            "\t/a\t/b\t\t0"
+        );
+    }
 
-        let mut trash = Trash::new(hist_path.to_owned(), trash_dir.to_owned()).unwrap();
+    #[test]
+    fn test_human_age() {
+        assert_eq!(human_age(Duration::from_secs(45)), "45s");
+        assert_eq!(human_age(Duration::from_secs(3 * 3600 + 5)), "3h");
+        assert_eq!(human_age(Duration::from_secs(10 * 86400)), "10d");
+    }
 
-        trash.remove(files.clone()).unwrap();
+    #[test]
+    fn test_list_columns() {
+        let args = Args::try_parse_from(["trash", "list", "--columns", "id,size,age"]).unwrap();
 
-        for file in files {
-            test_dir.push(file);
-            assert!(!test_dir.exists());
-            test_dir.pop();
+        match args.command {
+            Some(Command::List(list_args)) => assert_eq!(list_args.columns, vec![Column::Id, Column::Size, Column::Age]),
+            _ => panic!("expected list")
         }
+
+        assert!(Args::try_parse_from(["trash", "list", "--columns", "id,bogus"]).is_err());
+        assert!(Args::try_parse_from(["trash", "list", "--ids", "--columns", "id"]).is_err());
     }
 
     #[test]
-    fn test_glob() {
-        let (tmp_dir, hist_path) = trash_dir();
-        let mut trash_dir = tmp_dir.path().to_owned();
-        let mut test_dir = tmp_dir.path().to_owned();
+    fn test_commands() {
+        let command = |argv: &[&str]| Args::try_parse_from(argv).map(|mut args| args.take_command());
 
-        trash_dir.push("trash_dir");
+        assert!(matches!(command(&["trash", "a", "b"]), Ok(Command::Put { name, .. }) if name == ["a", "b"]));
+        assert!(matches!(command(&["trash", "put", "--strict", "a"]), Ok(Command::Put { options, .. }) if options.strict));
+        assert!(matches!(command(&["trash", "-u", "--entry", "2"]), Ok(Command::Undo { entry: Some(2) })));
+        assert!(matches!(command(&["trash", "-w", "--stats"]), Ok(Command::History(HistoryArgs { stats: true, .. }))));
 
-        env::set_current_dir(&test_dir).unwrap();
+        assert!(command(&["trash", "put"]).is_err());
+        assert!(command(&["trash", "-u", "a"]).is_err());
+        assert!(command(&["trash", "-u", "-w"]).is_err());
 
-        let mut trash = Trash::new(hist_path.to_owned(), trash_dir.to_owned()).unwrap();
+        assert!(matches!(command(&["trash", "restore", "a.txt", "--from", "trash-cli"]), Ok(Command::Restore(RestoreArgs { from: Some(Source::TrashCli), .. }))));
+        assert!(command(&["trash", "restore", "--all", "--from", "trash-cli"]).is_err());
+        assert!(command(&["trash", "list", "--dir", "/tmp/Trash"]).is_err());
+        assert!(command(&["trash", "list", "--from", "rip", "--ids"]).is_err());
 
-        trash.remove(vec![String::from("test_dir/*")]).unwrap();
+        let args = Args::try_parse_from(["trash", "list", "--trash-dir", "/tmp/a", "--history", "/tmp/b.json"]).unwrap();
 
-        test_dir.push("test_dir");
-
-        for i in 0..3 {
-            test_dir.push(format!("test{}.txt", i));
-            assert!(!test_dir.exists());
-            test_dir.pop();
-        }
+        assert_eq!(args.trash_dir, Some(PathBuf::from("/tmp/a")));
+        assert_eq!(args.history, Some(PathBuf::from("/tmp/b.json")));
     }
 
     #[test]
-    fn test_trash_explain() {
-        let (tmp_dir, hist_path) = trash_dir();
-        let trash_dir = tmp_dir.path().to_owned();
+    fn test_toggle() {
+        let mut selected = vec![vec![true, true], vec![true]];
 
-        let mut trash = Trash::new(hist_path.clone(), trash_dir.clone()).unwrap();
-        trash.toggle_explain();
+        toggle(&mut selected, &[(0, 1)]);
+        assert_eq!(selected, vec![vec![true, false], vec![true]]);
 
-        let mut target_fld = PathBuf::from_iter([
-            dirs::home_dir().unwrap(),
-            PathBuf::from("dev/trash/dev-folder/")
-        ]);
+        // A partly selected operation gets selected whole
+        toggle(&mut selected, &[(0, 0), (0, 1)]);
+        assert_eq!(selected, vec![vec![true, true], vec![true]]);
 
-        target_fld.push("two.txt");
+        toggle(&mut selected, &[(0, 0), (0, 1)]);
+        assert_eq!(selected, vec![vec![false, false], vec![true]]);
+    }
 
-        let mv_file = vec![target_fld.to_string_lossy().to_string()];
+    #[test]
+    fn test_day_label() {
+        let today = jiff::civil::date(2024, 5, 2);
 
-        trash.remove(mv_file).unwrap();
+        assert_eq!(day_label(today, today), "Today");
+        assert_eq!(day_label(jiff::civil::date(2024, 5, 1), today), "Yesterday");
+        assert_eq!(day_label(jiff::civil::date(2024, 4, 30), today), "2024-04-30");
+    }
 
-        assert!(target_fld.exists());
+    #[test]
+    #[cfg(unix)]
+    fn test_latest_restore_keeps_bytes() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let (tmp_dir, mut trash) = trash_dir();
+        let file = tmp_dir.path().join(std::ffi::OsStr::from_bytes(b"test_dir/caf\xe9.txt"));
+
+        fs::write(&file, "caf\u{e9}").unwrap();
+        trash.remove(vec![file.clone().into_os_string()]).unwrap();
+        latest(&mut trash, file.clone(), true, true).unwrap();
+
+        assert_eq!(fs::read_to_string(&file).unwrap(), "caf\u{e9}");
+        assert!(trash.history().is_empty());
     }
 
     #[test]
-    fn test_undo() {
-        let (tmp_dir, hist_path) = trash_dir();
-        let mut trash_dir = tmp_dir.path().to_owned();
-        let mut test_dir = tmp_dir.path().to_owned();
+    fn test_diff() {
+        let (tmp_dir, mut trash) = trash_dir();
+        let file = tmp_dir.path().join("test_dir/test0.txt");
 
-        trash_dir.push("trash_dir");
-        test_dir.push("test_dir");
+        fs::write(&file, "one\n").unwrap();
+        trash.remove(vec![file.display().to_string()]).unwrap();
+        let id = trash.history()[0][0].2.id.clone();
 
-        env::set_current_dir(&test_dir).unwrap();
+        // Nothing at the original path counts as a difference
+        assert!(diff(&trash, &id).unwrap());
 
-        let mut trash = Trash::new(hist_path.clone(), trash_dir.clone()).unwrap();
+        fs::write(&file, "one\n").unwrap();
+        assert!(!diff(&trash, &id).unwrap());
 
-        test_dir.push("test1.txt");
+        fs::write(&file, "two\n").unwrap();
+        assert!(diff(&trash, &id).unwrap());
+    }
 
-        trash.remove(vec!["test1.txt".to_string()]).unwrap();
+    #[test]
+    fn test_expire() {
+        let (tmp_dir, mut trash) = trash_dir();
+        let test_dir = tmp_dir.path().join("test_dir");
+
+        trash.remove(vec![test_dir.join("test0.txt").to_string_lossy().to_string()]).unwrap();
+        trash.remove(vec![test_dir.join("test1.txt").to_string_lossy().to_string()]).unwrap();
 
-        assert!(!test_dir.exists());
+        let mut trash = backdate(trash, (0, 0), Duration::from_secs(20 * 24 * 60 * 60));
+        expire(&mut trash, Duration::from_secs(14 * 24 * 60 * 60)).unwrap();
 
-        trash.undo().unwrap();
+        // History is already saved, whatever the command goes on to do
+        let (saved, _) = trash::history::load(trash.hist_path()).unwrap();
 
-        assert!(test_dir.exists())
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0][0].0, test_dir.join("test1.txt"));
     }
 
     #[test]
-    fn test_non_empty_directory_doesnt_fail() {
-        let (tmp_dir, hist_path) = trash_dir();
-        let mut trash_dir = tmp_dir.path().to_owned();
-        let mut test_dir = tmp_dir.path().to_owned();
+    fn test_empty_older_than() {
+        let (tmp_dir, mut trash) = trash_dir();
+
+        trash.remove(vec![tmp_dir.path().join("test_dir/test*.txt").to_string_lossy().to_string()]).unwrap();
 
-        trash_dir.push("trash_dir");
-        test_dir.push("test_dir");
+        let mut trash = backdate(trash, (0, 0), Duration::from_secs(100 * 24 * 60 * 60));
+        let old = trash.trashed_path((0, 0)).to_path_buf();
 
-        let non_empty_dir = test_dir.clone().join("non-empty");
-        create_dir(&non_empty_dir).unwrap();
-        
-        let mut non_empty_dir2 = trash_dir.clone().join("non-empty");
-        create_dir(&non_empty_dir2).unwrap();
-        
-        let mut f1 = File::create(non_empty_dir.join("test1.txt")).unwrap();
-        f1.write_all(b"stuff").unwrap();
+        empty(&mut trash, false, Some(Duration::from_secs(90 * 24 * 60 * 60)), true, false).unwrap();
 
-        let mut f2 = File::create(non_empty_dir2.join("test1.txt")).unwrap();
-        f2.write_all(b"stuff").unwrap();
+        assert!(!old.exists());
+        assert_eq!(trash.item_count(), 2);
+    }
 
-        env::set_current_dir(&test_dir).unwrap();
+    #[test]
+    fn test_config_set_trash_dir() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path().join("root");
+        let original = tmp_dir.path().join("a.txt");
 
-        let mut trash = Trash::new(hist_path.clone(), trash_dir.clone()).unwrap();
+        fs::write(&original, "a").unwrap();
 
-        trash.remove(vec!["non-empty".to_string()]).unwrap();
+        let mut trash = Trash::try_new(Some(&root), &load_config(Some(&root)).unwrap(), &Overrides::default()).unwrap();
+        trash.remove(vec![original.clone()]).unwrap();
+        trash.write().unwrap();
 
-        assert!(!non_empty_dir.exists());
+        let moved = tmp_dir.path().join("files");
+        configure(Some(&root), &ConfigAction::Set { key: String::from("trash_dir"), value: moved.to_string_lossy().to_string() }).unwrap();
 
-        assert!(non_empty_dir2.exists());
+        // The next run uses the new trash dir and still knows about everything trashed before
+        let trash = Trash::try_new(Some(&root), &load_config(Some(&root)).unwrap(), &Overrides::default()).unwrap();
 
-        non_empty_dir2.pop();
+        assert!(trash.trash_path().starts_with(&moved));
+        assert_eq!(trash.history().len(), 1);
+        assert_eq!(trash.original_path((0, 0)), original);
+    }
 
-        assert!(non_empty_dir2.join("non-empty.1").exists());
+    #[test]
+    fn test_original_pattern() {
+        let pattern = original_pattern("src/*.rs").unwrap();
+
+        assert!(pattern.matches_path(&env::current_dir().unwrap().join("src/main.rs")));
+        assert!(original_pattern("*.rs").unwrap().matches("main.rs"));
+        assert!(is_glob("src/**/*.rs") && !is_glob("notes.txt"));
     }
-}
\ No newline at end of file
+}
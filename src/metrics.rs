@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{TrashError, TrashResult};
+
+/// Operation and error counts, kept between runs in `metrics.json` next to the history
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Counters {
+    operations: BTreeMap<String, u64>,
+    errors: BTreeMap<String, u64>
+}
+
+pub fn counters_path(hist_path: &Path) -> PathBuf {
+    hist_path.with_file_name("metrics.json")
+}
+
+impl Counters {
+    pub fn load(path: &Path) -> TrashResult<Self> {
+        match File::open(path) {
+            Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(TrashError::from(e))
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> TrashResult<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn record(&mut self, op: &str, ok: bool) {
+        *self.operations.entry(op.to_string()).or_default() += 1;
+
+        if !ok {
+            *self.errors.entry(op.to_string()).or_default() += 1;
+        }
+    }
+}
+
+/// What is in the trash right now
+#[derive(Debug, Default)]
+pub struct Gauges {
+    pub items: usize,
+    pub bytes: u64,
+    pub oldest_age_secs: Option<u64>
+}
+
+/// Prometheus text exposition format
+pub fn render(gauges: &Gauges, counters: &Counters) -> String {
+    let mut out = String::new();
+
+    family(&mut out, "trash_items_total", "gauge", "Items currently in the trash.", &[(None, gauges.items as u64)]);
+    family(&mut out, "trash_bytes_total", "gauge", "Bytes used by items in the trash.", &[(None, gauges.bytes)]);
+
+    // Left out altogether while the trash is empty, there is no age to give
+    if let Some(age) = gauges.oldest_age_secs {
+        family(&mut out, "trash_oldest_item_age_seconds", "gauge", "Time since the oldest item in the trash was trashed.", &[(None, age)]);
+    }
+
+    family(&mut out, "trash_operations_total", "counter", "Trash operations run, by operation.", &by_op(&counters.operations));
+    family(&mut out, "trash_errors_total", "counter", "Trash operations that failed, by operation.", &by_op(&counters.errors));
+
+    out
+}
+
+/// One metric with its help and type lines, then a sample per label
+fn family(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(Option<&str>, u64)]) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+
+    for (op, value) in samples {
+        let _ = match op {
+            Some(op) => writeln!(out, "{}{{op=\"{}\"}} {}", name, op, value),
+            None => writeln!(out, "{} {}", name, value)
+        };
+    }
+}
+
+fn by_op(counts: &BTreeMap<String, u64>) -> Vec<(Option<&str>, u64)> {
+    counts.iter().map(|(op, &n)| (Some(op.as_str()), n)).collect()
+}
+
+/// Written to a temporary file first so a collector never reads half of it
+pub fn write_textfile(path: &Path, text: &str) -> TrashResult<()> {
+    let tmp = path.with_extension("prom.tmp");
+
+    fs::write(&tmp, text)?;
+    fs::rename(&tmp, path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut counters = Counters::default();
+
+        counters.record("remove", true);
+        counters.record("remove", true);
+        counters.record("restore", false);
+
+        let text = render(&Gauges { items: 3, bytes: 2048, oldest_age_secs: None }, &counters);
+
+        assert!(text.contains("# TYPE trash_items_total gauge\ntrash_items_total 3\n"));
+        assert!(text.contains("trash_bytes_total 2048\n"));
+        assert!(!text.contains("trash_oldest_item_age_seconds"));
+        assert!(text.contains("trash_operations_total{op=\"remove\"} 2\ntrash_operations_total{op=\"restore\"} 1\n"));
+        assert!(text.contains("# TYPE trash_errors_total counter\ntrash_errors_total{op=\"restore\"} 1\n"));
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use clap::ValueEnum;
+use glob::Pattern;
+use jiff::civil::DateTime;
+use log::warn;
+
+use crate::freedesktop::{parse_trashinfo, FreeDesktop};
+use crate::schedule::to_system_time;
+use crate::{TrashError, TrashResult};
+
+/// rip's list of what is in its graveyard, one `time\tsource\tdest` line per item
+const RIP_RECORD: &str = ".record";
+
+/// Another trash tool whose deleted files can be moved into this one
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Source {
+    /// The freedesktop.org trash used by trash-cli and file managers
+    #[value(name = "trash-cli")]
+    TrashCli,
+    /// rip's graveyard
+    Rip
+}
+
+/// An item in the other tool's trash
+#[derive(Debug, PartialEq)]
+pub struct Found {
+    pub original: PathBuf,
+    pub stored: PathBuf,
+    pub trashed_at: Option<SystemTime>
+}
+
+impl Found {
+    /// Whether its original path matches `pattern`, the same way items in history are matched
+    pub fn matches(&self, pattern: &Pattern) -> bool {
+        crate::matches_original(pattern, &self.original)
+    }
+}
+
+/// The last of `found` trashed from each original path, in the order they came
+pub fn newest(found: Vec<Found>) -> Vec<Found> {
+    let mut seen = HashSet::new();
+    let mut newest: Vec<_> = found.into_iter().rev().filter(|f| seen.insert(f.original.clone())).collect();
+
+    newest.reverse();
+    newest
+}
+
+impl Source {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::TrashCli => "trash-cli",
+            Self::Rip => "rip"
+        }
+    }
+
+    /// Where the tool keeps its trash unless told otherwise
+    pub fn default_dir(&self) -> Option<PathBuf> {
+        match self {
+            Self::TrashCli => FreeDesktop::home().map(|fd| fd.dir().to_path_buf()),
+            Self::Rip => match std::env::var_os("GRAVEYARD") {
+                Some(dir) => Some(PathBuf::from(dir)),
+                None => Some(PathBuf::from(format!("/tmp/graveyard-{}", crate::current_user()?)))
+            }
+        }
+    }
+
+    /// Every item still there, oldest first. Entries whose file is gone are warned about and left out.
+    pub fn scan(&self, dir: &Path) -> TrashResult<Vec<Found>> {
+        let mut found = match self {
+            Self::TrashCli => scan_trash_cli(dir)?,
+            Self::Rip => scan_rip(dir)?
+        };
+
+        found.retain(|f| {
+            let exists = f.stored.symlink_metadata().is_ok();
+
+            if !exists {
+                warn!("Skipping {}, {} no longer exists", f.original.display(), f.stored.display());
+            }
+
+            exists
+        });
+
+        found.sort_by_key(|f| f.trashed_at);
+
+        Ok(found)
+    }
+
+    /// Drops the tool's own record of an item once it has been moved out, so it doesn't
+    /// list something it can no longer restore
+    pub fn forget(&self, dir: &Path, found: &Found) -> TrashResult<()> {
+        match self {
+            // Its directorysizes entry too, if it was a directory
+            Self::TrashCli => FreeDesktop::new(dir.to_path_buf()).remove_info(&found.stored)?,
+            Self::Rip => {
+                let path = dir.join(RIP_RECORD);
+                let record = fs::read_to_string(&path)?;
+                let kept: String = record
+                    .lines()
+                    .filter(|line| rip_line(line).is_none_or(|(_, _, stored)| dir.join(stored) != found.stored))
+                    .map(|line| format!("{}\n", line))
+                    .collect();
+
+                fs::write(&path, kept)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn scan_trash_cli(dir: &Path) -> TrashResult<Vec<Found>> {
+    let entries = match crate::move_files::read_dir(&dir.join("info"), true) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(TrashError(format!("{}: {}", dir.display(), e)))
+    };
+
+    let mut found = vec![];
+
+    for entry in entries {
+        let path = entry.path();
+
+        let Some(name) = path.file_name().and_then(|n| n.to_str()).and_then(|n| n.strip_suffix(".trashinfo")) else {
+            continue
+        };
+
+        let (original, trashed_at) = match parse_trashinfo(&fs::read_to_string(&path)?) {
+            Some(info) => info,
+            None => {
+                warn!("Skipping {}, it isn't a valid trashinfo file", path.display());
+                continue
+            }
+        };
+
+        // Relative paths belong to trashes at the top of other mounts, which aren't searched
+        if original.is_relative() {
+            warn!("Skipping {}, its original path {} isn't absolute", path.display(), original.display());
+            continue
+        }
+
+        found.push(Found { original, stored: dir.join("files").join(name), trashed_at });
+    }
+
+    Ok(found)
+}
+
+fn scan_rip(dir: &Path) -> TrashResult<Vec<Found>> {
+    let record = match fs::read_to_string(dir.join(RIP_RECORD)) {
+        Ok(record) => record,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(TrashError(format!("{}: {}", dir.display(), e)))
+    };
+
+    Ok(record
+        .lines()
+        .filter_map(rip_line)
+        .map(|(time, original, stored)| Found {
+            original: PathBuf::from(original),
+            stored: dir.join(stored),
+            // ctime style, like `Thu May  2 03:00:00 2024`
+            trashed_at: DateTime::strptime("%a %b %e %H:%M:%S %Y", time.trim()).ok().and_then(to_system_time)
+        })
+        .collect())
+}
+
+/// Graveyard paths in the record are absolute, `dir.join` keeps them as they are
+fn rip_line(line: &str) -> Option<(&str, &str, &str)> {
+    let mut parts = line.splitn(3, '\t');
+    Some((parts.next()?, parts.next()?, parts.next()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan() {
+        let dir = tempfile::tempdir().unwrap();
+        let trash = dir.path().join("Trash");
+
+        fs::create_dir_all(trash.join("info")).unwrap();
+        fs::create_dir_all(trash.join("files")).unwrap();
+        fs::write(trash.join("files/my notes.txt"), "").unwrap();
+        fs::write(trash.join("info/my notes.txt.trashinfo"), "[Trash Info]\nPath=/home/me/my%20notes.txt\nDeletionDate=2024-05-02T03:00:00\n").unwrap();
+        fs::write(trash.join("info/gone.trashinfo"), "[Trash Info]\nPath=/home/me/gone\nDeletionDate=2024-05-01T03:00:00\n").unwrap();
+
+        let found = Source::TrashCli.scan(&trash).unwrap();
+
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].original, PathBuf::from("/home/me/my notes.txt"));
+        assert!(found[0].trashed_at.is_some());
+
+        Source::TrashCli.forget(&trash, &found[0]).unwrap();
+        assert!(!trash.join("info/my notes.txt.trashinfo").exists());
+
+        let graveyard = dir.path().join("graveyard");
+        let stored = graveyard.join("home/me/a.txt");
+
+        fs::create_dir_all(stored.parent().unwrap()).unwrap();
+        fs::write(&stored, "").unwrap();
+        fs::write(
+            graveyard.join(RIP_RECORD),
+            format!("Thu May  2 03:00:00 2024\t/home/me/a.txt\t{}\nThu May  2 04:00:00 2024\t/home/me/b.txt\t/nowhere/b.txt\n", stored.display())
+        ).unwrap();
+
+        let found = Source::Rip.scan(&graveyard).unwrap();
+
+        assert_eq!(found, vec![Found { original: PathBuf::from("/home/me/a.txt"), stored: stored.clone(), trashed_at: found[0].trashed_at }]);
+        assert!(found[0].trashed_at.is_some());
+
+        Source::Rip.forget(&graveyard, &found[0]).unwrap();
+        assert_eq!(fs::read_to_string(graveyard.join(RIP_RECORD)).unwrap(), "Thu May  2 04:00:00 2024\t/home/me/b.txt\t/nowhere/b.txt\n");
+    }
+}
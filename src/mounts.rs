@@ -0,0 +1,186 @@
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+use crate::backend::TrashBackend;
+use crate::freedesktop::FreeDesktop;
+use crate::move_files::MoveOptions;
+use crate::{secure, ItemMeta, TrashResult};
+
+/// Keeps items from other filesystems (USB sticks, other disks) in a trash at the top of their
+/// own, `$topdir/.Trash-$uid` as the freedesktop.org spec has it, instead of copying them over
+/// to the trash. Everything else goes to `home`, as does everything while it's off.
+#[derive(Debug)]
+pub struct PerMount {
+    home: Box<dyn TrashBackend>,
+    /// The filesystem `home` keeps items on
+    home_dev: Option<u64>,
+    enabled: bool
+}
+
+impl PerMount {
+    pub fn new(home: Box<dyn TrashBackend>, trash_path: &Path) -> Self {
+        Self { home, home_dev: device(trash_path), enabled: false }
+    }
+
+    pub fn set_home(&mut self, home: Box<dyn TrashBackend>) {
+        self.home = home;
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// The trash on `original`'s own filesystem, if that isn't the one items are kept on and
+    /// its trash is ours to use
+    pub fn trash_for(&self, original: &Path) -> Option<FreeDesktop> {
+        let dev = device(original).filter(|&dev| self.enabled && Some(dev) != self.home_dev)?;
+        let top = top_dir(original, dev);
+        let dir = top.join(trash_name());
+
+        // A mount point can't go in a trash inside itself
+        (top != original && usable(&dir, &top)).then(|| FreeDesktop::new(dir))
+    }
+
+    /// The per-mount trash `stored` was put in, whether or not they're still being used
+    fn holding(&self, stored: &Path) -> Option<FreeDesktop> {
+        let dir = stored.parent().filter(|p| p.file_name() == Some(OsStr::new("files")))?.parent()?;
+
+        (dir.file_name() == Some(OsStr::new(&trash_name()))).then(|| FreeDesktop::new(dir.to_path_buf()))
+    }
+}
+
+impl TrashBackend for PerMount {
+    fn place(&self, original: &Path, taken: &dyn Fn(&Path) -> bool) -> (PathBuf, String) {
+        match self.trash_for(original) {
+            Some(fd) => (fd.free_path(original, taken), format!("the trash on its own filesystem, {}", fd.dir().display())),
+            None => self.home.place(original, taken)
+        }
+    }
+
+    fn move_to_trash(&self, original: &Path, stored: &Path, meta: &ItemMeta, opts: &MoveOptions, on_copied: &mut dyn FnMut(u64)) -> TrashResult<PathBuf> {
+        match self.holding(stored) {
+            Some(fd) => {
+                secure::create_private_dir(fd.dir())?;
+                fd.move_to_trash(original, stored, meta, opts, on_copied)
+            },
+            None => self.home.move_to_trash(original, stored, meta, opts, on_copied)
+        }
+    }
+
+    fn restore(&self, stored: &Path, dest: &Path, meta: &ItemMeta, opts: &MoveOptions, on_copied: &mut dyn FnMut(u64)) -> TrashResult<()> {
+        match self.holding(stored) {
+            Some(fd) => fd.restore(stored, dest, meta, opts, on_copied),
+            None => self.home.restore(stored, dest, meta, opts, on_copied)
+        }
+    }
+
+    fn purge(&self, stored: &Path) -> TrashResult<()> {
+        match self.holding(stored) {
+            Some(fd) => fd.purge(stored),
+            None => self.home.purge(stored)
+        }
+    }
+
+    /// Only `home`, the per-mount trashes are wherever the items in history say
+    fn list(&self) -> TrashResult<Vec<PathBuf>> {
+        self.home.list()
+    }
+}
+
+/// The highest directory above `path` that is still on `dev`, where its filesystem is mounted
+fn top_dir(path: &Path, dev: u64) -> PathBuf {
+    path.ancestors()
+        .skip(1)
+        .take_while(|dir| device(dir) == Some(dev))
+        .last()
+        .unwrap_or(path)
+        .to_path_buf()
+}
+
+#[cfg(unix)]
+fn device(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    path.symlink_metadata().ok().map(|m| m.dev())
+}
+
+#[cfg(not(unix))]
+fn device(_path: &Path) -> Option<u64> {
+    None
+}
+
+#[cfg(unix)]
+fn trash_name() -> String {
+    format!(".Trash-{}", unsafe { libc::geteuid() })
+}
+
+#[cfg(not(unix))]
+fn trash_name() -> String {
+    String::from(".Trash")
+}
+
+/// An existing trash has to be a real directory of ours, anyone could have left a link to
+/// somewhere else. A missing one is fine if it can be made.
+#[cfg(unix)]
+fn usable(dir: &Path, top: &Path) -> bool {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::MetadataExt;
+
+    match dir.symlink_metadata() {
+        Ok(meta) => meta.is_dir() && meta.uid() == unsafe { libc::geteuid() },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => CString::new(top.as_os_str().as_bytes())
+            // SAFETY: nul terminated and lives until the call returns
+            .is_ok_and(|top| unsafe { libc::access(top.as_ptr(), libc::W_OK) } == 0),
+        Err(_) => false
+    }
+}
+
+#[cfg(not(unix))]
+fn usable(_dir: &Path, _top: &Path) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::Dir;
+
+    #[test]
+    #[cfg(unix)]
+    fn test_per_mount() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let original = tmp_dir.path().join("a/b.txt");
+        std::fs::create_dir_all(original.parent().unwrap()).unwrap();
+        std::fs::write(&original, "b").unwrap();
+
+        let dev = device(&original).unwrap();
+        let top = top_dir(&original, dev);
+
+        assert!(original.starts_with(&top) && top.parent().is_none_or(|p| device(p) != Some(dev)));
+
+        let mut backend = PerMount::new(Box::new(Dir::new(tmp_dir.path().join("trash"))), tmp_dir.path());
+
+        // Same filesystem as the trash, or turned off, and it goes to the trash
+        assert_eq!(backend.place(&original, &|_| false).0, tmp_dir.path().join("trash/b.txt"));
+
+        backend.home_dev = Some(dev + 1);
+        assert!(backend.trash_for(&original).is_none());
+
+        backend.set_enabled(true);
+        let mount_trash = top.join(trash_name());
+
+        match backend.trash_for(&original) {
+            Some(fd) => {
+                let (stored, reason) = backend.place(&original, &|_| false);
+                assert_eq!(stored, mount_trash.join("files/b.txt"));
+                assert!(reason.contains("its own filesystem"));
+                assert_eq!(fd.dir(), mount_trash);
+            },
+            // Not ours to write to, so it's still the trash
+            None => assert_eq!(backend.place(&original, &|_| false).0, tmp_dir.path().join("trash/b.txt"))
+        }
+
+        assert_eq!(backend.holding(&mount_trash.join("files/b.txt")).map(|fd| fd.dir().to_path_buf()), Some(mount_trash));
+        assert!(backend.holding(&tmp_dir.path().join("trash/files/b.txt")).is_none());
+    }
+}
@@ -0,0 +1,1046 @@
+use std::borrow::Cow;
+use std::fs::{self, File, FileTimes, Metadata};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::ValueEnum;
+use log::{debug, trace, warn};
+use colorize::colorize;
+
+pub const DEFAULT_BUF_SIZE: usize = 1024 * 1024;
+pub const DEFAULT_RETRIES: usize = 3;
+/// Wait before the first retry, doubled for each one after
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+/// Files at least this big are kept out of the page cache while being copied
+const LARGE_FILE: u64 = 64 * 1024 * 1024;
+/// How much of a large file is copied between dropping it from the cache
+const CACHE_CHUNK: u64 = 16 * 1024 * 1024;
+
+/// How files are moved and copied. Each `Trash` has its own, so two in one process can be set
+/// up differently.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MoveOptions {
+    /// Size of the buffer files are copied through
+    pub buffer_size: usize,
+    /// Walk directories in name order rather than whatever order the filesystem lists them in
+    pub sorted: bool,
+    /// How many more times a move is tried when a file is briefly held by something else
+    pub retries: usize,
+    pub special: Special
+}
+
+impl Default for MoveOptions {
+    fn default() -> Self {
+        Self { buffer_size: DEFAULT_BUF_SIZE, sorted: false, retries: DEFAULT_RETRIES, special: Special::default() }
+    }
+}
+
+/// What to do with FIFOs, sockets and devices that have to be copied to another filesystem
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Special {
+    /// Leave them where they are, along with the directories holding them
+    #[default]
+    Skip,
+    /// Make new ones in their place. Devices need root.
+    Recreate,
+    /// Delete them, there's nothing in them to keep
+    Delete
+}
+
+/// Editors, virus scanners and running programs hold files for a moment, which fails with
+/// these rather than anything that trying again can't fix
+fn is_transient(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::ResourceBusy | io::ErrorKind::WouldBlock | io::ErrorKind::ExecutableFileBusy)
+}
+
+/// Runs `op`, trying again up to `retries` times with a growing wait after each transient failure
+fn retry<T>(path: &Path, retries: usize, mut op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut delay = RETRY_DELAY;
+
+    for _ in 0..retries {
+        match op() {
+            Err(e) if is_transient(&e) => {
+                debug!("{}", colorize!(Fyb->"Busy, trying again in", b->format!("{:?}", delay), b->path, b->"-", b->e));
+                std::thread::sleep(delay);
+                delay *= 2;
+            },
+            res => return res
+        }
+    }
+
+    op()
+}
+
+/// What's in `dir`, by name if `sorted`
+pub fn read_dir(dir: &Path, sorted: bool) -> io::Result<Vec<fs::DirEntry>> {
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+
+    if sorted {
+        entries.sort_by_key(|e| e.file_name());
+    }
+
+    Ok(entries)
+}
+
+/// `path` with the `\\?\` prefix that lifts the 260 character limit on paths in Windows, which
+/// deeply nested trees like node_modules go over. Only absolute drive and UNC paths can take it.
+#[cfg(windows)]
+pub fn long_path(path: &Path) -> Cow<'_, Path> {
+    use std::ffi::OsString;
+    use std::path::{Component, Prefix};
+
+    let mut parts = path.components();
+
+    let start = match parts.next() {
+        Some(Component::Prefix(prefix)) if path.has_root() => match prefix.kind() {
+            Prefix::Disk(drive) => OsString::from(format!(r"\\?\{}:\", drive as char)),
+            Prefix::UNC(server, share) => {
+                let mut start = OsString::from(r"\\?\UNC\");
+                start.push(server);
+                start.push(r"\");
+                start.push(share);
+                start.push(r"\");
+                start
+            },
+            // Already verbatim, or a device
+            _ => return Cow::Borrowed(path)
+        },
+        _ => return Cow::Borrowed(path)
+    };
+
+    // Nothing is normalized behind the prefix, so . and .. are dealt with here
+    let mut long = PathBuf::from(start);
+
+    for part in parts {
+        match part {
+            Component::Normal(name) => long.push(name),
+            Component::ParentDir => {
+                long.pop();
+            },
+            _ => {}
+        }
+    }
+
+    Cow::Owned(long)
+}
+
+#[cfg(not(windows))]
+pub fn long_path(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
+
+/// `fs::canonicalize`, without the `\\?\` prefix Windows gives every path it returns, so the
+/// result compares equal to, and prints like, paths from anywhere else
+#[cfg(windows)]
+pub fn canonicalize(path: &Path) -> io::Result<PathBuf> {
+    use std::ffi::OsString;
+    use std::path::{Component, Prefix};
+
+    let path = fs::canonicalize(path)?;
+    let mut parts = path.components();
+
+    let start = match parts.next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::VerbatimDisk(drive) => OsString::from(format!(r"{}:\", drive as char)),
+            Prefix::VerbatimUNC(server, share) => {
+                let mut start = OsString::from(r"\\");
+                start.push(server);
+                start.push(r"\");
+                start.push(share);
+                start.push(r"\");
+                start
+            },
+            _ => return Ok(path)
+        },
+        _ => return Ok(path)
+    };
+
+    let mut plain = PathBuf::from(start);
+    plain.extend(parts.filter(|part| matches!(part, Component::Normal(_))));
+
+    Ok(plain)
+}
+
+#[cfg(not(windows))]
+pub fn canonicalize(path: &Path) -> io::Result<PathBuf> {
+    fs::canonicalize(path)
+}
+
+/// Moves `from` to `to` like `fs::rename`, falling back to copying and deleting
+/// when the two are on different filesystems (the trash usually lives on a tmpfs).
+pub fn rename(from: &Path, to: &Path, opts: &MoveOptions) -> io::Result<()> {
+    rename_with(from, to, opts, &mut |_| {})
+}
+
+/// Same as `rename`, calling `on_copied` with the number of bytes each time some have
+/// been copied. A plain rename copies nothing.
+pub fn rename_with(from: &Path, to: &Path, opts: &MoveOptions, on_copied: &mut dyn FnMut(u64)) -> io::Result<()> {
+    let (from, to) = (&*long_path(from), &*long_path(to));
+
+    match retry(from, opts.retries, || fs::rename(from, to)) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            trace!("{}", colorize!(Fyb->"Different filesystems, rename failed, copying", b->from));
+            move_across(from, to, opts, on_copied)
+        },
+        res => res
+    }
+}
+
+/// Copies `from` to `to`, directories included, carrying over what metadata it can.
+/// Never overwrites anything, and removes whatever it managed to copy if it fails part way.
+pub fn copy(from: &Path, to: &Path, opts: &MoveOptions) -> io::Result<()> {
+    let (from, to) = (&*long_path(from), &*long_path(to));
+
+    if to.symlink_metadata().is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", to.display())))
+    }
+
+    copy_tree(from, to, opts).inspect_err(|_| {
+        let _ = match to.symlink_metadata() {
+            Ok(m) if m.is_dir() => fs::remove_dir_all(to),
+            _ => fs::remove_file(to)
+        };
+    })
+}
+
+fn copy_tree(from: &Path, to: &Path, opts: &MoveOptions) -> io::Result<()> {
+    let meta = from.symlink_metadata()?;
+    let file_type = meta.file_type();
+
+    if file_type.is_dir() {
+        fs::create_dir(to)?;
+
+        for entry in read_dir(from, opts.sorted)? {
+            copy_tree(&entry.path(), &to.join(entry.file_name()), opts)?;
+        }
+    } else if file_type.is_symlink() {
+        copy_link(from, to)?;
+    } else if file_type.is_file() {
+        copy_file(from, to, opts, &mut |_| {})?;
+    } else if opts.special != Special::Recreate || !recreate(&meta, from, to) {
+        warn!("{}", colorize!(Fyb->"Skipping", b->from, b->"- not a regular file, directory or symlink"));
+        return Ok(())
+    }
+
+    preserve_metadata(&meta, from, to);
+
+    Ok(())
+}
+
+/// Moves one entry at a time so that every file is always in at least one place.
+/// Anything that can't be copied is left behind, along with the directories holding it.
+/// The walk stays on one thread, as copying the data takes far longer than listing it.
+fn move_across(from: &Path, to: &Path, opts: &MoveOptions, on_copied: &mut dyn FnMut(u64)) -> io::Result<()> {
+    let meta = from.symlink_metadata()?;
+    let file_type = meta.file_type();
+
+    if file_type.is_dir() {
+        fs::create_dir(to)?;
+
+        for entry in read_dir(from, opts.sorted)? {
+            move_across(&entry.path(), &to.join(entry.file_name()), opts, on_copied)?;
+        }
+
+        preserve_metadata(&meta, from, to);
+
+        if let Err(e) = fs::remove_dir(from) {
+            warn!("{}", colorize!(Fyb->"Could not remove", b->from, e));
+        }
+
+        return Ok(())
+    }
+
+    if file_type.is_symlink() {
+        copy_link(from, to)?;
+    } else if file_type.is_file() {
+        copy_file(from, to, opts, on_copied)?;
+    } else {
+        match opts.special {
+            Special::Recreate if recreate(&meta, from, to) => {},
+            Special::Delete => {
+                warn!("{}", colorize!(Fyb->"Deleting", b->from, b->"- not a regular file, directory or symlink"));
+                return retry(from, opts.retries, || fs::remove_file(from))
+            },
+            _ => {
+                warn!("{}", colorize!(Fyb->"Skipping", b->from, b->"- not a regular file, directory or symlink (see --special-files)"));
+                return Ok(())
+            }
+        }
+    }
+
+    preserve_metadata(&meta, from, to);
+    retry(from, opts.retries, || fs::remove_file(from))
+}
+
+/// Flushes `path` to disk, everything in it if it's a directory, so a crash right after
+/// can't lose what has just been moved there
+pub fn sync_tree(path: &Path) -> io::Result<()> {
+    let meta = path.symlink_metadata()?;
+
+    if meta.is_dir() {
+        for entry in read_dir(path, false)? {
+            sync_tree(&entry.path())?;
+        }
+
+        sync_dir(path)?;
+    } else if meta.is_file() {
+        File::open(path)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+/// Flushes the entries of `dir`, which is what makes a rename or removal in it stick
+#[cfg(unix)]
+pub fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+/// Directories can't be opened to sync them here, renames are as durable as they get
+#[cfg(not(unix))]
+pub fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Carries over what a plain copy loses. Failures only get logged, the data
+/// itself has already made it across.
+fn preserve_metadata(meta: &Metadata, from: &Path, to: &Path) {
+    // Has to come first, changing the owner clears a file's capabilities and setuid bits
+    preserve_owner(meta, to);
+    // Before permissions, which could stop the copy being opened to set them
+    preserve_times(meta, to);
+    preserve_permissions(meta, to);
+    preserve_xattrs(from, to);
+}
+
+/// Modification and access times, which build systems and backup tools go by. Directories
+/// get theirs once everything is in them, as that's what the copy gets through last.
+/// Opening a symlink would follow it and opening a FIFO would wait for a writer.
+fn preserve_times(meta: &Metadata, to: &Path) {
+    if !meta.is_file() && !meta.is_dir() {
+        return
+    }
+
+    let mut times = FileTimes::new();
+
+    if let Ok(mtime) = meta.modified() {
+        times = times.set_modified(mtime);
+    }
+
+    if let Ok(atime) = meta.accessed() {
+        times = times.set_accessed(atime);
+    }
+
+    if let Err(e) = File::open(to).and_then(|f| f.set_times(times)) {
+        debug!("{}", colorize!(Fyb->"Could not preserve timestamps of", b->to, e));
+    }
+}
+
+/// Mode bits on unix, so executables stay executable, and the read-only flag elsewhere.
+/// A symlink's own permissions don't matter and setting them would follow it.
+fn preserve_permissions(meta: &Metadata, to: &Path) {
+    if meta.file_type().is_symlink() {
+        return
+    }
+
+    if let Err(e) = fs::set_permissions(to, meta.permissions()) {
+        debug!("{}", colorize!(Fyb->"Could not preserve permissions of", b->to, e));
+    }
+}
+
+fn copy_file(from: &Path, to: &Path, opts: &MoveOptions, on_copied: &mut dyn FnMut(u64)) -> io::Result<()> {
+    let mut src = retry(from, opts.retries, || File::open(from))?;
+    let len = src.metadata()?.len();
+
+    // APFS clones by path, so it has to go before anything creates `to`
+    #[cfg(target_os = "macos")]
+    if clone_path(from, to).is_ok() {
+        on_copied(len);
+        return Ok(())
+    }
+
+    let mut dst = File::options().write(true).create_new(true).open(to)?;
+
+    // Different btrfs subvolumes or bind mounts are one filesystem as far as cloning goes,
+    // even though rename says otherwise. A clone shares the data until either file changes.
+    match clone_file(&src, &dst) {
+        Ok(()) => {
+            on_copied(len);
+            return Ok(())
+        },
+        Err(e) => trace!("Could not clone {:?}, copying instead: {}", from, e)
+    }
+
+    let extents = data_extents(&src, len);
+
+    // Cleans up after itself like the copy below, nothing has been written yet. A sparse file
+    // would be filled in by reserving its whole length.
+    if extents.is_none() {
+        preallocate(&dst, len).inspect_err(|_| {
+            let _ = fs::remove_file(to);
+        })?;
+    }
+
+    // Don't leave half a file behind if the copy fails part way through
+    copy_contents(&mut src, &mut dst, extents, opts.buffer_size, on_copied).inspect_err(|_| {
+        let _ = fs::remove_file(to);
+    })
+}
+
+/// Copies all of `src`, or just the stretches of data in `extents` so the holes between them
+/// stay holes in the copy. Reads and writes go through a `buf_size` buffer if they have to.
+fn copy_contents(src: &mut File, dst: &mut File, extents: Option<Vec<(u64, u64)>>, buf_size: usize, on_copied: &mut dyn FnMut(u64)) -> io::Result<()> {
+    // Only needed if the kernel can't do the copy for us
+    let mut buf = vec![];
+    let mut zero_copy = true;
+
+    let len = src.metadata()?.len();
+    // Trashing a huge file shouldn't push everything else out of the page cache
+    let large = len >= LARGE_FILE;
+    let sparse = extents.is_some();
+    let (mut pos, mut dropped) = (0, 0);
+
+    for (start, extent) in extents.unwrap_or(vec![(0, u64::MAX)]) {
+        if sparse {
+            src.seek(SeekFrom::Start(start))?;
+            dst.seek(SeekFrom::Start(start))?;
+            pos = start;
+        }
+
+        let mut left = extent;
+
+        while left > 0 {
+            let n = if zero_copy {
+                match copy_range(src, dst, left.min(CACHE_CHUNK) as usize) {
+                    Ok(n) if n > 0 || pos >= len => Some(n),
+                    // Some filesystems claim files are empty to copy_file_range (e.g. procfs)
+                    Ok(_) => None,
+                    Err(e) if can_fall_back(&e) => {
+                        trace!("copy_file_range not usable for {:?}, reading and writing instead: {}", src, e);
+                        None
+                    },
+                    Err(e) => return Err(e)
+                }
+            } else {
+                None
+            };
+
+            // Both file offsets are wherever the copy has got to, so the loop just carries on from there
+            let n = match n {
+                Some(n) => n,
+                None => {
+                    if zero_copy {
+                        zero_copy = false;
+                        buf = vec![0; buf_size.max(1)];
+                    }
+
+                    let want = buf.len().min(left.try_into().unwrap_or(usize::MAX));
+                    let n = src.read(&mut buf[..want])?;
+                    dst.write_all(&buf[..n])?;
+                    n
+                }
+            };
+
+            if n == 0 {
+                break
+            }
+
+            on_copied(n as u64);
+            pos += n as u64;
+            left -= n as u64;
+
+            if large && pos - dropped >= CACHE_CHUNK {
+                drop_cached(src, dst, dropped, pos - dropped);
+                dropped = pos;
+            }
+        }
+    }
+
+    if large {
+        drop_cached(src, dst, dropped, pos.saturating_sub(dropped));
+    }
+
+    // The source shrank while it was being copied, don't keep the preallocated tail. A sparse
+    // copy gets its full length, which is how a hole at the end is made.
+    let end = if sparse { len } else { pos };
+
+    if dst.metadata()?.len() != end {
+        dst.set_len(end)?;
+    }
+
+    Ok(())
+}
+
+/// Where the data in `src` is, as (offset, length), if it has holes: a file taking up less room
+/// on disk than its length. None means copy all of it.
+#[cfg(target_os = "linux")]
+fn data_extents(src: &File, len: u64) -> Option<Vec<(u64, u64)>> {
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::MetadataExt;
+
+    if src.metadata().ok()?.blocks() * 512 >= len {
+        return None
+    }
+
+    let (fd, mut offset, mut extents) = (src.as_raw_fd(), 0, vec![]);
+
+    while offset < len {
+        // SAFETY: the descriptor is open for as long as the borrow lasts. Copying seeks both
+        // files to every extent itself, so moving the offset around here doesn't matter.
+        let start = unsafe { libc::lseek64(fd, offset as libc::off64_t, libc::SEEK_DATA) };
+
+        if start < 0 {
+            // ENXIO: no more data, the rest is one hole. Anything else: the filesystem can't say.
+            return (io::Error::last_os_error().raw_os_error() == Some(libc::ENXIO)).then_some(extents)
+        }
+
+        // SAFETY: as above
+        let end = unsafe { libc::lseek64(fd, start, libc::SEEK_HOLE) };
+
+        if end <= start {
+            return None
+        }
+
+        extents.push((start as u64, (end - start) as u64));
+        offset = end as u64;
+    }
+
+    Some(extents)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn data_extents(_src: &File, _len: u64) -> Option<Vec<(u64, u64)>> {
+    None
+}
+
+/// Reserves space for the whole file up front, which keeps it in one piece on disk and
+/// runs out of space before copying rather than half way through.
+#[cfg(target_os = "linux")]
+fn preallocate(file: &File, len: u64) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    if len == 0 {
+        return Ok(())
+    }
+
+    // SAFETY: the descriptor is open for as long as the borrow lasts
+    let res = unsafe { libc::fallocate(file.as_raw_fd(), 0, 0, len as libc::off64_t) };
+
+    if res == 0 {
+        return Ok(())
+    }
+
+    match io::Error::last_os_error() {
+        e if e.raw_os_error() == Some(libc::ENOSPC) => Err(e),
+        // Not every filesystem can do it, setting the length is the next best thing
+        e => {
+            trace!("fallocate failed, setting the length instead: {}", e);
+            file.set_len(len)
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn preallocate(file: &File, len: u64) -> io::Result<()> {
+    file.set_len(len)
+}
+
+/// Has the kernel copy up to `len` bytes between the current offsets of both files,
+/// without them passing through userspace
+#[cfg(target_os = "linux")]
+fn copy_range(src: &File, dst: &File, len: usize) -> io::Result<usize> {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: both descriptors are open for as long as the borrows last. Null offsets
+    // make the kernel use and advance the files' own offsets.
+    let res = unsafe {
+        libc::copy_file_range(src.as_raw_fd(), std::ptr::null_mut(), dst.as_raw_fd(), std::ptr::null_mut(), len, 0)
+    };
+
+    match res {
+        -1 => Err(io::Error::last_os_error()),
+        n => Ok(n as usize)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn copy_range(_src: &File, _dst: &File, _len: usize) -> io::Result<usize> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Makes `dst` share the data of `src` on disk (a reflink), which only copy-on-write
+/// filesystems like btrfs and XFS can do
+#[cfg(target_os = "linux")]
+fn clone_file(src: &File, dst: &File) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: both descriptors are open for as long as the borrows last
+    match unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn clone_file(_src: &File, _dst: &File) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Creates `to` as a clone of `from`, sharing its data on APFS
+#[cfg(target_os = "macos")]
+fn clone_path(from: &Path, to: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let (from, to) = (CString::new(from.as_os_str().as_bytes())?, CString::new(to.as_os_str().as_bytes())?);
+
+    // SAFETY: both are nul terminated and live until the call returns
+    match unsafe { libc::clonefile(from.as_ptr(), to.as_ptr(), 0) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(())
+    }
+}
+
+/// Errors meaning copy_file_range can't be used here (old kernel, filesystem pair it
+/// doesn't support, ...) rather than that the copy itself went wrong
+fn can_fall_back(e: &io::Error) -> bool {
+    #[cfg(target_os = "linux")]
+    if matches!(e.raw_os_error(), Some(libc::ENOSYS | libc::EXDEV | libc::EINVAL | libc::EOPNOTSUPP | libc::EPERM)) {
+        return true
+    }
+
+    e.kind() == io::ErrorKind::Unsupported
+}
+
+/// Tells the kernel we're done with a range of both files. The destination has to be
+/// written out first, dirty pages can't be dropped.
+#[cfg(target_os = "linux")]
+fn drop_cached(src: &File, dst: &File, offset: u64, len: u64) {
+    use std::os::fd::AsRawFd;
+
+    let (offset, len) = (offset as libc::off64_t, len as libc::off64_t);
+    let flags = libc::SYNC_FILE_RANGE_WAIT_BEFORE | libc::SYNC_FILE_RANGE_WRITE | libc::SYNC_FILE_RANGE_WAIT_AFTER;
+
+    // SAFETY: both descriptors are open for as long as the borrows last, and these
+    // calls only ever give advice about them
+    unsafe {
+        libc::sync_file_range(dst.as_raw_fd(), offset, len, flags);
+        libc::posix_fadvise(dst.as_raw_fd(), offset, len, libc::POSIX_FADV_DONTNEED);
+        libc::posix_fadvise(src.as_raw_fd(), offset, len, libc::POSIX_FADV_DONTNEED);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn drop_cached(_src: &File, _dst: &File, _offset: u64, _len: u64) {}
+
+/// Makes a FIFO, socket or device at `to` like the one at `from`, false if it couldn't
+fn recreate(meta: &Metadata, from: &Path, to: &Path) -> bool {
+    match make_node(meta, to) {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("{}", colorize!(Fyb->"Could not recreate", b->from, b->"-", b->e));
+            false
+        }
+    }
+}
+
+#[cfg(unix)]
+fn make_node(meta: &Metadata, to: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::MetadataExt;
+
+    let to = CString::new(to.as_os_str().as_bytes())?;
+
+    // SAFETY: `to` is nul terminated and lives until the call returns
+    match unsafe { libc::mknod(to.as_ptr(), meta.mode() as libc::mode_t, meta.rdev() as libc::dev_t) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(())
+    }
+}
+
+#[cfg(not(unix))]
+fn make_node(_meta: &Metadata, _to: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "special files can't be made here"))
+}
+
+#[cfg(unix)]
+fn copy_link(from: &Path, to: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(from)?, to)
+}
+
+#[cfg(windows)]
+fn copy_link(from: &Path, to: &Path) -> io::Result<()> {
+    let target = fs::read_link(from)?;
+
+    if from.is_dir() {
+        std::os::windows::fs::symlink_dir(target, to)
+    } else {
+        std::os::windows::fs::symlink_file(target, to)
+    }
+}
+
+/// Copies keep the owner of the source when we are allowed to (i.e. running as root),
+/// otherwise everything would come out owned by whoever ran trash.
+#[cfg(unix)]
+fn preserve_owner(meta: &Metadata, to: &Path) {
+    use std::os::unix::fs::{lchown, MetadataExt};
+
+    if let Err(e) = lchown(to, Some(meta.uid()), Some(meta.gid())) {
+        debug!("{}", colorize!(Fyb->"Could not preserve ownership of", b->to, e));
+    }
+}
+
+#[cfg(not(unix))]
+fn preserve_owner(_meta: &Metadata, _to: &Path) {}
+
+/// Whether the features we've been built with carry over the xattr `name`:
+/// - `security.selinux` (`selinux`): a copy gets the default label of wherever it lands,
+///   which can lock a service out of it once it is restored
+/// - `security.capability` (`capabilities`): a setcap'd binary stops working without it
+/// - `system.posix_acl_*` (`acl`): a shared directory restored without its ACLs shuts out
+///   everyone it was shared with. They're set after the mode, which would otherwise
+///   overwrite the ACL's mask.
+/// - anything else (`xattrs`): user.* tags, macOS quarantine flags and Finder metadata, ...
+///   but no other `system.*`, which the kernel manages
+#[cfg(all(unix, any(feature = "selinux", feature = "capabilities", feature = "xattrs", feature = "acl")))]
+fn carried(name: &std::ffi::OsStr) -> bool {
+    match name.to_str() {
+        Some("security.selinux") => cfg!(any(feature = "selinux", feature = "xattrs")),
+        Some("security.capability") => cfg!(feature = "capabilities"),
+        Some("system.posix_acl_access" | "system.posix_acl_default") => cfg!(feature = "acl"),
+        Some(name) if name.starts_with("system.") => false,
+        _ => cfg!(feature = "xattrs")
+    }
+}
+
+#[cfg(all(unix, any(feature = "selinux", feature = "capabilities", feature = "xattrs", feature = "acl")))]
+fn preserve_xattrs(from: &Path, to: &Path) {
+    let names = match xattr::list(from) {
+        Ok(names) => names,
+        Err(e) => {
+            debug!("{}", colorize!(Fyb->"Could not list extended attributes of", b->from, e));
+            return
+        }
+    };
+
+    for name in names.filter(|name| carried(name)) {
+        let res = match xattr::get(from, &name) {
+            Ok(Some(value)) => xattr::set(to, &name, &value),
+            Ok(None) => Ok(()),
+            Err(e) => Err(e)
+        };
+
+        if let Err(e) = res {
+            debug!("{}", colorize!(Fyb->"Could not preserve", b->name.to_string_lossy(), b->"of", b->to, e));
+        }
+    }
+}
+
+#[cfg(not(all(unix, any(feature = "selinux", feature = "capabilities", feature = "xattrs", feature = "acl"))))]
+fn preserve_xattrs(_from: &Path, _to: &Path) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_move_across() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let from = tmp_dir.path().join("from");
+        let to = tmp_dir.path().join("to");
+
+        fs::create_dir_all(from.join("nested/empty")).unwrap();
+        File::create(from.join("nested/file.txt")).unwrap().write_all(&[7; DEFAULT_BUF_SIZE * 3 + 1]).unwrap();
+
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("nested/file.txt", from.join("link")).unwrap();
+
+        let mut copied = 0;
+        move_across(&from, &to, &MoveOptions::default(), &mut |n| copied += n).unwrap();
+
+        assert_eq!(copied, DEFAULT_BUF_SIZE as u64 * 3 + 1);
+
+        assert!(!from.exists());
+        assert!(to.join("nested/empty").is_dir());
+        assert_eq!(fs::read(to.join("nested/file.txt")).unwrap(), vec![7; DEFAULT_BUF_SIZE * 3 + 1]);
+
+        #[cfg(unix)]
+        assert_eq!(fs::read_link(to.join("link")).unwrap(), Path::new("nested/file.txt"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_move_across_keeps_links() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let (from, to, outside) = (tmp_dir.path().join("from"), tmp_dir.path().join("to"), tmp_dir.path().join("outside"));
+
+        fs::create_dir_all(outside.join("big")).unwrap();
+        fs::write(outside.join("big/file.txt"), "not copied").unwrap();
+        fs::create_dir(&from).unwrap();
+
+        std::os::unix::fs::symlink(&outside, from.join("abs_dir")).unwrap();
+        std::os::unix::fs::symlink("../outside/big/file.txt", from.join("rel_file")).unwrap();
+        std::os::unix::fs::symlink("missing", from.join("dangling")).unwrap();
+
+        let mut copied = 0;
+        move_across(&from, &to, &MoveOptions::default(), &mut |n| copied += n).unwrap();
+
+        // Links come across as links, whatever they point to is left alone
+        assert_eq!(copied, 0);
+        assert_eq!(fs::read_link(to.join("abs_dir")).unwrap(), outside);
+        assert_eq!(fs::read_link(to.join("rel_file")).unwrap(), Path::new("../outside/big/file.txt"));
+        assert_eq!(fs::read_link(to.join("dangling")).unwrap(), Path::new("missing"));
+        assert!(outside.join("big/file.txt").exists());
+        assert!(!from.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_move_across_special() {
+        use std::os::unix::fs::FileTypeExt;
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let (from, to) = (tmp_dir.path().join("from"), tmp_dir.path().join("to"));
+        let fifo = |dir: &Path| {
+            fs::create_dir_all(dir).unwrap();
+            let path = std::ffi::CString::new(dir.join("pipe").as_os_str().as_encoded_bytes()).unwrap();
+            assert_eq!(unsafe { libc::mkfifo(path.as_ptr(), 0o640) }, 0);
+        };
+
+        // Skipped by default, which leaves the directory holding it behind too
+        fifo(&from);
+        move_across(&from, &to, &MoveOptions::default(), &mut |_| {}).unwrap();
+        assert!(from.join("pipe").exists() && !to.join("pipe").exists());
+        fs::remove_dir_all(&to).unwrap();
+
+        let recreate = MoveOptions { special: Special::Recreate, ..MoveOptions::default() };
+        move_across(&from, &to, &recreate, &mut |_| {}).unwrap();
+        assert!(!from.exists());
+        assert!(fs::symlink_metadata(to.join("pipe")).unwrap().file_type().is_fifo());
+        fs::remove_dir_all(&to).unwrap();
+
+        let delete = MoveOptions { special: Special::Delete, ..MoveOptions::default() };
+        fifo(&from);
+        move_across(&from, &to, &delete, &mut |_| {}).unwrap();
+        assert!(!from.exists() && to.is_dir());
+        assert!(fs::symlink_metadata(to.join("pipe")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_move_across_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let (from, to) = (tmp_dir.path().join("from"), tmp_dir.path().join("to"));
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+
+        fs::create_dir(&from).unwrap();
+        fs::write(from.join("run.sh"), "#!/bin/sh").unwrap();
+        fs::set_permissions(from.join("run.sh"), fs::Permissions::from_mode(0o750)).unwrap();
+        fs::set_permissions(&from, fs::Permissions::from_mode(0o711)).unwrap();
+
+        move_across(&from, &to, &MoveOptions::default(), &mut |_| {}).unwrap();
+
+        assert_eq!(mode(&to.join("run.sh")), 0o750);
+        assert_eq!(mode(&to), 0o711);
+    }
+
+    #[test]
+    fn test_move_across_keeps_times() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let (from, to) = (tmp_dir.path().join("from"), tmp_dir.path().join("to"));
+        let then = std::time::SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let times = FileTimes::new().set_modified(then).set_accessed(then);
+
+        fs::create_dir(&from).unwrap();
+        File::create(from.join("a.txt")).unwrap().set_times(times).unwrap();
+        File::open(&from).unwrap().set_times(times).unwrap();
+
+        move_across(&from, &to, &MoveOptions::default(), &mut |_| {}).unwrap();
+
+        for path in [to.join("a.txt"), to] {
+            let meta = fs::metadata(&path).unwrap();
+            assert_eq!((meta.modified().unwrap(), meta.accessed().unwrap()), (then, then));
+        }
+    }
+
+    #[cfg(all(unix, feature = "xattrs"))]
+    #[test]
+    fn test_move_across_keeps_xattrs() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let (from, to) = (tmp_dir.path().join("from.txt"), tmp_dir.path().join("to.txt"));
+
+        fs::write(&from, "tagged").unwrap();
+
+        // Not every filesystem the tests run on has user xattrs
+        if xattr::set(&from, "user.xdg.tags", b"red").is_err() {
+            return
+        }
+
+        move_across(&from, &to, &MoveOptions::default(), &mut |_| {}).unwrap();
+
+        assert_eq!(xattr::get(&to, "user.xdg.tags").unwrap(), Some(b"red".to_vec()));
+    }
+
+    #[cfg(all(target_os = "linux", feature = "acl"))]
+    #[test]
+    fn test_move_across_keeps_acls() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let (from, to) = (tmp_dir.path().join("shared"), tmp_dir.path().join("trashed"));
+
+        // user::rw-, user:1000:r--, group::r--, mask::r--, other::---, as the kernel stores it
+        let mut acl = 2u32.to_le_bytes().to_vec();
+
+        for (tag, perm, id) in [(0x01u16, 6u16, u32::MAX), (0x02, 4, 1000), (0x04, 4, u32::MAX), (0x10, 4, u32::MAX), (0x20, 0, u32::MAX)] {
+            acl.extend(tag.to_le_bytes().iter().chain(&perm.to_le_bytes()).chain(&id.to_le_bytes()));
+        }
+
+        fs::create_dir(&from).unwrap();
+
+        // Not every filesystem the tests run on has ACLs
+        if xattr::set(&from, "system.posix_acl_access", &acl).is_err() || xattr::set(&from, "system.posix_acl_default", &acl).is_err() {
+            return
+        }
+
+        move_across(&from, &to, &MoveOptions::default(), &mut |_| {}).unwrap();
+
+        assert_eq!(xattr::get(&to, "system.posix_acl_access").unwrap(), Some(acl.clone()));
+        assert_eq!(xattr::get(&to, "system.posix_acl_default").unwrap(), Some(acl));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_copy_sparse() {
+        use std::os::unix::fs::MetadataExt;
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let (from, to) = (tmp_dir.path().join("disk.img"), tmp_dir.path().join("copy.img"));
+        let len = 256 * 1024 * 1024;
+
+        // Data at the start and in the middle, holes everywhere else including the end
+        let mut file = File::create(&from).unwrap();
+        file.write_all(b"boot").unwrap();
+        file.seek(SeekFrom::Start(len / 2)).unwrap();
+        file.write_all(b"data").unwrap();
+        file.set_len(len).unwrap();
+
+        let mut copied = 0;
+        copy_file(&from, &to, &MoveOptions::default(), &mut |n| copied += n).unwrap();
+
+        let meta = fs::metadata(&to).unwrap();
+        let mut data = vec![0; 4];
+        let mut copy = File::open(&to).unwrap();
+        copy.seek(SeekFrom::Start(len / 2)).unwrap();
+        copy.read_exact(&mut data).unwrap();
+
+        assert_eq!(meta.len(), len);
+        assert_eq!(data, b"data");
+        assert!(meta.blocks() * 512 < len / 4 && copied < len / 4);
+    }
+
+    #[test]
+    fn test_rename_same_fs() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let (from, to) = (tmp_dir.path().join("big.img"), tmp_dir.path().join("trash.img"));
+
+        fs::write(&from, vec![1; DEFAULT_BUF_SIZE * 2]).unwrap();
+
+        #[cfg(unix)]
+        let ino = std::os::unix::fs::MetadataExt::ino(&fs::metadata(&from).unwrap());
+
+        // One rename, however big the file: nothing is read or copied
+        let mut copied = 0;
+        rename_with(&from, &to, &MoveOptions::default(), &mut |n| copied += n).unwrap();
+
+        assert_eq!(copied, 0);
+        assert!(!from.exists());
+
+        #[cfg(unix)]
+        assert_eq!(std::os::unix::fs::MetadataExt::ino(&fs::metadata(&to).unwrap()), ino);
+    }
+
+    #[test]
+    fn test_sync_tree() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+
+        fs::create_dir_all(tmp_dir.path().join("d/nested")).unwrap();
+        fs::write(tmp_dir.path().join("d/nested/file.txt"), "a").unwrap();
+
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("missing", tmp_dir.path().join("d/link")).unwrap();
+
+        // Links are left alone rather than followed
+        assert!(sync_tree(&tmp_dir.path().join("d")).is_ok());
+        assert!(sync_tree(&tmp_dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_retry() {
+        let mut tries = 0;
+
+        let res = retry(Path::new("busy"), DEFAULT_RETRIES, || {
+            tries += 1;
+            if tries < 3 { Err(io::Error::from(io::ErrorKind::ResourceBusy)) } else { Ok(tries) }
+        });
+
+        assert_eq!(res.unwrap(), 3);
+
+        // Anything else fails straight away
+        tries = 0;
+        assert!(retry(Path::new("gone"), DEFAULT_RETRIES, || { tries += 1; fs::remove_file("/nowhere/at/all") }).is_err());
+        assert_eq!(tries, 1);
+    }
+
+    #[test]
+    fn test_move_across_refuses_to_clobber() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let from = tmp_dir.path().join("from.txt");
+        let to = tmp_dir.path().join("to.txt");
+
+        File::create(&from).unwrap();
+        File::create(&to).unwrap();
+
+        assert!(move_across(&from, &to, &MoveOptions::default(), &mut |_| {}).is_err());
+        assert!(from.exists());
+    }
+
+    #[test]
+    fn test_copy() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let from = tmp_dir.path().join("from");
+        let to = tmp_dir.path().join("to");
+
+        fs::create_dir_all(from.join("nested")).unwrap();
+        fs::write(from.join("nested/file.txt"), "data").unwrap();
+
+        copy(&from, &to, &MoveOptions::default()).unwrap();
+
+        assert_eq!(fs::read(from.join("nested/file.txt")).unwrap(), b"data");
+        assert_eq!(fs::read(to.join("nested/file.txt")).unwrap(), b"data");
+        assert_eq!(copy(&from, &to, &MoveOptions::default()).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_long_path() {
+        assert_eq!(long_path(Path::new(r"C:\a\.\b\..\c.txt")), Path::new(r"\\?\C:\a\c.txt"));
+        assert_eq!(long_path(Path::new(r"\\server\share\a")), Path::new(r"\\?\UNC\server\share\a"));
+        assert_eq!(long_path(Path::new(r"\\?\C:\a")), Path::new(r"\\?\C:\a"));
+        assert_eq!(long_path(Path::new(r"a\b")), Path::new(r"a\b"));
+
+        let dir = tempfile::tempdir().unwrap();
+        assert!(!canonicalize(dir.path()).unwrap().starts_with(r"\\?\"));
+    }
+
+    #[test]
+    fn test_can_fall_back() {
+        assert!(can_fall_back(&io::ErrorKind::Unsupported.into()));
+        assert!(!can_fall_back(&io::ErrorKind::PermissionDenied.into()));
+
+        #[cfg(target_os = "linux")]
+        assert!(can_fall_back(&io::Error::from_raw_os_error(libc::EXDEV)));
+    }
+}
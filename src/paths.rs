@@ -0,0 +1,94 @@
+//! Saves paths as plain strings where they can be, and as their raw bytes where they aren't
+//! valid UTF-8, which serde would otherwise refuse to write at all. Used with
+//! `#[serde(with = "paths")]`.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Repr {
+    Text(String),
+    Bytes { bytes: Vec<u8> }
+}
+
+pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    match path.to_str() {
+        Some(text) => serializer.serialize_str(text),
+        None => Repr::Bytes { bytes: to_bytes(path) }.serialize(serializer)
+    }
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+    Repr::deserialize(deserializer).map(Repr::into_path)
+}
+
+impl Repr {
+    fn into_path(self) -> PathBuf {
+        match self {
+            Repr::Text(text) => PathBuf::from(text),
+            Repr::Bytes { bytes } => from_bytes(bytes)
+        }
+    }
+}
+
+#[cfg(unix)]
+fn to_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(unix)]
+fn from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(std::ffi::OsString::from_vec(bytes))
+}
+
+// Names that aren't valid Unicode are next to unheard of outside unix
+#[cfg(not(unix))]
+fn to_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(not(unix))]
+fn from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// The same for an optional path, with `#[serde(with = "paths::option")]`
+pub mod option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(path: &Option<PathBuf>, serializer: S) -> Result<S::Ok, S::Error> {
+        match path {
+            Some(path) => super::serialize(path, serializer),
+            None => serializer.serialize_none()
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<PathBuf>, D::Error> {
+        Ok(Option::<Repr>::deserialize(deserializer)?.map(Repr::into_path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HistoryPair, ItemMeta};
+
+    #[test]
+    #[cfg(unix)]
+    fn test_round_trip() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let odd = PathBuf::from(std::ffi::OsStr::from_bytes(b"/a/caf\xe9.txt"));
+        let pair = HistoryPair(odd.clone(), PathBuf::from("/trash/cafe.txt"), ItemMeta { link: Some(odd.clone()), ..Default::default() });
+
+        let json = serde_json::to_string(&pair).unwrap();
+        let back: HistoryPair = serde_json::from_str(&json).unwrap();
+
+        assert!(json.starts_with(r#"[{"bytes":[47,97,47,"#) && json.contains(r#""/trash/cafe.txt""#));
+        assert_eq!((back.0, back.1, back.2.link), (odd.clone(), PathBuf::from("/trash/cafe.txt"), Some(odd)));
+    }
+}
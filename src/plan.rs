@@ -0,0 +1,194 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::table::{Cell, Table};
+
+/// How `--explain` reports what it would have done
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum PlanFormat {
+    /// A table with one action per row
+    #[default]
+    Text,
+    /// A JSON object with an `actions` list
+    Json
+}
+
+/// One step an operation would take, and why
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    /// Into the trash or back out of it
+    Move { from: PathBuf, to: PathBuf, reason: String },
+    /// Out of the trash, leaving the item where it is
+    Copy { from: PathBuf, to: PathBuf, reason: String },
+    CreateDir { path: PathBuf, reason: String },
+    Delete { path: PathBuf, reason: String },
+    /// Left alone
+    Skip { path: PathBuf, reason: String }
+}
+
+impl Action {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Move { .. } => "move",
+            Self::Copy { .. } => "copy",
+            Self::CreateDir { .. } => "mkdir",
+            Self::Delete { .. } => "delete",
+            Self::Skip { .. } => "skip"
+        }
+    }
+
+    /// Whatever the action leaves at a path that wasn't there before
+    fn creates(&self) -> Option<&Path> {
+        match self {
+            Self::Move { to, .. } | Self::Copy { to, .. } => Some(to),
+            Self::CreateDir { path, .. } => Some(path),
+            _ => None
+        }
+    }
+}
+
+/// Everything an explained operation would have done, in order
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Plan {
+    /// The trash the plan moves things into, only saved plans have it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trash: Option<PathBuf>,
+    pub actions: Vec<Action>,
+    /// What the filesystem looked like when the plan was made, checked again before applying it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub expect: Vec<Expect>
+}
+
+/// `state` is None where nothing may exist yet
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Expect {
+    pub path: PathBuf,
+    pub state: Option<Snapshot>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub dir: bool,
+    pub size: u64,
+    pub mtime: Option<SystemTime>
+}
+
+impl Snapshot {
+    /// Symlinks are taken as they are, not followed
+    pub fn of(path: &Path) -> Option<Self> {
+        let meta = path.symlink_metadata().ok()?;
+
+        Some(Self { dir: meta.is_dir(), size: meta.len(), mtime: meta.modified().ok() })
+    }
+}
+
+impl Plan {
+    pub fn push(&mut self, action: Action) {
+        self.actions.push(action);
+    }
+
+    /// Whether an earlier action would already have put something at `path`. Nothing really
+    /// moves while explaining, so the filesystem alone can't tell.
+    pub fn claims(&self, path: &Path) -> bool {
+        self.actions.iter().any(|a| a.creates() == Some(path))
+    }
+
+    /// Notes down how every path the plan touches looks right now
+    pub fn snapshot(&mut self) {
+        self.expect = self.actions
+            .iter()
+            .flat_map(|action| match action {
+                Action::Move { from, to, .. } | Action::Copy { from, to, .. } => vec![(from, true), (to, false)],
+                Action::CreateDir { path, .. } => vec![(path, false)],
+                Action::Delete { path, .. } => vec![(path, true)],
+                Action::Skip { .. } => vec![]
+            })
+            .map(|(path, exists)| Expect { path: path.clone(), state: if exists { Snapshot::of(path) } else { None } })
+            .collect();
+    }
+
+    /// Every path that no longer looks the way it did when the plan was made
+    pub fn changed(&self) -> Vec<String> {
+        self.expect
+            .iter()
+            .filter_map(|expect| match (&expect.state, Snapshot::of(&expect.path)) {
+                (Some(was), Some(now)) if *was == now => None,
+                (None, None) => None,
+                (Some(_), Some(_)) => Some(format!("{} has changed", expect.path.display())),
+                (Some(_), None) => Some(format!("{} no longer exists", expect.path.display())),
+                (None, Some(_)) => Some(format!("{} exists now", expect.path.display()))
+            })
+            .collect()
+    }
+
+    pub fn print(&self, format: PlanFormat, truncate: bool) {
+        match format {
+            PlanFormat::Json => match serde_json::to_string_pretty(self) {
+                Ok(json) => println!("{}", json),
+                Err(e) => log::error!("{}", e)
+            },
+            PlanFormat::Text => {
+                let mut table = Table::new(vec!["Action", "Path", "To", "Reason"], truncate);
+
+                for action in self.actions.iter() {
+                    let (path, to, reason) = match action {
+                        Action::Move { from, to, reason } | Action::Copy { from, to, reason } => (from, Cell::path(to), reason),
+                        Action::CreateDir { path, reason } | Action::Delete { path, reason } | Action::Skip { path, reason } => {
+                            (path, Cell::plain(""), reason)
+                        }
+                    };
+
+                    table.row(vec![Cell::plain(action.kind()), Cell::path(path), to, Cell::plain(reason)]);
+                }
+
+                table.print();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan() {
+        let mut plan = Plan::default();
+
+        plan.push(Action::CreateDir { path: PathBuf::from("/a"), reason: "to hold /a/b".to_string() });
+        plan.push(Action::Move { from: PathBuf::from("/t/b"), to: PathBuf::from("/a/b"), reason: "restore".to_string() });
+        plan.push(Action::Skip { path: PathBuf::from("/c"), reason: "no longer in the trash".to_string() });
+
+        assert!(plan.claims(Path::new("/a")));
+        assert!(plan.claims(Path::new("/a/b")));
+        assert!(!plan.claims(Path::new("/t/b")));
+        assert!(!plan.claims(Path::new("/c")));
+
+        let json = serde_json::to_string(&plan.actions[1]).unwrap();
+
+        assert_eq!(json, r#"{"action":"move","from":"/t/b","to":"/a/b","reason":"restore"}"#);
+        assert_eq!(serde_json::from_str::<Action>(&json).unwrap(), plan.actions[1]);
+    }
+
+    #[test]
+    fn test_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let (from, to) = (dir.path().join("a"), dir.path().join("b"));
+        let mut plan = Plan::default();
+
+        std::fs::write(&from, "a").unwrap();
+        plan.push(Action::Move { from: from.clone(), to: to.clone(), reason: "trash".to_string() });
+        plan.snapshot();
+
+        assert!(plan.changed().is_empty());
+
+        std::fs::write(&from, "ab").unwrap();
+        std::fs::write(&to, "").unwrap();
+
+        assert_eq!(plan.changed(), vec![format!("{} has changed", from.display()), format!("{} exists now", to.display())]);
+    }
+}
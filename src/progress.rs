@@ -0,0 +1,231 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// Events are sent at most this often, apart from the last one
+const INTERVAL: Duration = Duration::from_millis(200);
+/// Weight of the newest sample in the moving average of the copy rate
+const ALPHA: f64 = 0.3;
+const BAR_WIDTH: usize = 20;
+
+/// How to report the progress of operations on stderr
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ProgressFormat {
+    /// One JSON object per line
+    Json,
+    /// A bar redrawn in place, with copy rate and time left
+    Bar
+}
+
+#[derive(Serialize, Debug, Default)]
+struct Event {
+    files_done: usize,
+    files_total: usize,
+    bytes_done: u64,
+    bytes_total: u64,
+    path: Option<PathBuf>,
+    /// Only known once something has had to be copied
+    bytes_per_sec: Option<u64>,
+    eta_secs: Option<u64>
+}
+
+/// Keeps track of how far along an operation is and reports it, if asked to.
+/// Nothing is reported outside of `start` and `finish`.
+#[derive(Debug, Default)]
+pub struct Progress {
+    format: Option<ProgressFormat>,
+    active: bool,
+    state: Event,
+    /// Size of the item being worked on
+    current: Option<u64>,
+    /// Bytes of every item finished so far, copies of the current one are counted on top
+    bytes_finished: u64,
+    /// Bytes copied so far, renames happen all at once and would throw the rate off
+    copied: u64,
+    /// Moving average of bytes copied per second
+    rate: Option<f64>,
+    last_sample: Option<(Instant, u64)>,
+    last_emit: Option<Instant>
+}
+
+impl Progress {
+    pub fn new(format: Option<ProgressFormat>) -> Self {
+        Self { format, ..Default::default() }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.format.is_some()
+    }
+
+    pub fn start(&mut self, files_total: usize, bytes_total: u64) {
+        *self = Self::new(self.format);
+        self.active = true;
+        self.state.files_total = files_total;
+        self.state.bytes_total = bytes_total;
+    }
+
+    /// Moves on to the next item, counting the previous one as done
+    pub fn item(&mut self, path: &Path, size: u64) {
+        self.complete_current();
+        self.current = Some(size);
+        self.state.path = Some(path.to_path_buf());
+        self.emit(false);
+    }
+
+    pub fn copied(&mut self, bytes: u64) {
+        self.state.bytes_done += bytes;
+        self.copied += bytes;
+        self.emit(false);
+    }
+
+    pub fn finish(&mut self) {
+        self.complete_current();
+        self.state.path = None;
+        self.emit(true);
+        self.active = false;
+    }
+
+    fn complete_current(&mut self) {
+        if let Some(size) = self.current.take() {
+            self.state.files_done += 1;
+            self.bytes_finished += size;
+            self.state.bytes_done = self.bytes_finished;
+        }
+    }
+
+    fn emit(&mut self, force: bool) {
+        let format = match self.format {
+            Some(f) if self.active => f,
+            _ => return
+        };
+
+        if !force && self.last_emit.is_some_and(|t| t.elapsed() < INTERVAL) {
+            return
+        }
+
+        let now = Instant::now();
+        self.last_emit = Some(now);
+        self.sample(now);
+
+        match format {
+            ProgressFormat::Json => {
+                if let Ok(line) = serde_json::to_string(&self.state) {
+                    let _ = writeln!(io::stderr(), "{}", line);
+                }
+            },
+            ProgressFormat::Bar => {
+                // Clear the line first, the previous one may have been longer
+                let end = if self.active && force { "\n" } else { "" };
+                let _ = write!(io::stderr(), "\r\x1b[2K{}{}", self.bar(), end);
+            }
+        }
+    }
+
+    /// Folds the copy rate since the last sample into the moving average
+    fn sample(&mut self, now: Instant) {
+        if let Some((then, copied)) = self.last_sample {
+            let secs = now.duration_since(then).as_secs_f64();
+
+            if secs > 0.0 && self.copied > copied {
+                let rate = (self.copied - copied) as f64 / secs;
+                self.rate = Some(self.rate.map_or(rate, |r| ALPHA * rate + (1.0 - ALPHA) * r));
+            }
+        }
+
+        self.last_sample = Some((now, self.copied));
+
+        self.state.bytes_per_sec = self.rate.map(|r| r as u64);
+        self.state.eta_secs = self.rate
+            .filter(|&r| r > 0.0)
+            .map(|r| (self.state.bytes_total.saturating_sub(self.state.bytes_done) as f64 / r).ceil() as u64);
+    }
+
+    fn bar(&self) -> String {
+        let Event { files_done, files_total, bytes_done, bytes_total, .. } = self.state;
+
+        let frac = match (bytes_total, files_total) {
+            (0, 0) => 1.0,
+            (0, files) => files_done as f64 / files as f64,
+            (bytes, _) => bytes_done as f64 / bytes as f64
+        }.min(1.0);
+
+        let filled = (frac * BAR_WIDTH as f64).round() as usize;
+
+        let mut line = format!(
+            "[{}{}] {:>3}% {}/{} {}/{}",
+            "#".repeat(filled),
+            ".".repeat(BAR_WIDTH - filled),
+            (frac * 100.0).floor(),
+            files_done,
+            files_total,
+            crate::human_size(bytes_done),
+            crate::human_size(bytes_total)
+        );
+
+        if let (Some(rate), Some(eta)) = (self.state.bytes_per_sec, self.state.eta_secs) {
+            line.push_str(&format!("  {}/s  ETA {}", crate::human_size(rate), format_eta(eta)));
+        }
+
+        line
+    }
+}
+
+/// m:ss, or h:mm:ss past the hour
+fn format_eta(secs: u64) -> String {
+    match secs {
+        0..=3599 => format!("{}:{:02}", secs / 60, secs % 60),
+        _ => format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_counts() {
+        let mut progress = Progress::new(None);
+
+        progress.start(2, 30);
+        progress.item(Path::new("/a"), 10);
+        progress.copied(4);
+
+        assert_eq!((progress.state.files_done, progress.state.bytes_done), (0, 4));
+
+        // Copies of a finished item never count twice
+        progress.copied(6);
+        progress.item(Path::new("/b"), 20);
+
+        assert_eq!((progress.state.files_done, progress.state.bytes_done), (1, 10));
+
+        progress.finish();
+
+        assert_eq!((progress.state.files_done, progress.state.bytes_done), (2, 30));
+        assert!(progress.state.path.is_none());
+    }
+
+    #[test]
+    fn test_rate_and_eta() {
+        let mut progress = Progress::new(None);
+        let start = Instant::now();
+
+        progress.start(1, 3000);
+        progress.item(Path::new("/a"), 3000);
+        progress.sample(start);
+        progress.copied(1000);
+        progress.sample(start + Duration::from_secs(1));
+
+        assert_eq!(progress.state.bytes_per_sec, Some(1000));
+        assert_eq!(progress.state.eta_secs, Some(2));
+        assert!(progress.bar().starts_with("[#######.............]  33% 0/1"));
+    }
+
+    #[test]
+    fn test_format_eta() {
+        assert_eq!(format_eta(65), "1:05");
+        assert_eq!(format_eta(3723), "1:02:03");
+    }
+}
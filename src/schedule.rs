@@ -0,0 +1,253 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use jiff::civil::DateTime;
+use jiff::tz::TimeZone;
+use jiff::{Span, Zoned};
+use serde::{Serialize, Deserialize};
+
+use crate::{TrashError, TrashResult};
+
+/// Gives up looking for a matching time this far away, e.g. for `0 0 31 2 *`
+const SEARCH_DAYS: i32 = 5 * 366;
+
+/// A standard five field cron expression: minute, hour, day of month, month, day of week.
+/// Fields take `*`, numbers, ranges (`1-5`), steps (`*/15`, `0-30/10`) and lists of those.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether day of month/week were `*`. When both are restricted, either one matching is enough.
+    any_day: bool,
+    any_weekday: bool
+}
+
+impl FromStr for Cron {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!("expected 5 fields in cron schedule `{}`, got {}", s, fields.len()))
+        };
+
+        let field = |spec: &str, min, max| parse_field(spec, min, max).map_err(|e| format!("{} in cron schedule `{}`", e, s));
+
+        let mut weekdays = field(weekday, 0, 7)?;
+
+        // Sunday is both 0 and 7
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+
+        Ok(Self {
+            minutes: field(minute, 0, 59)?,
+            hours: field(hour, 0, 23)?,
+            days: field(day, 1, 31)?,
+            months: field(month, 1, 12)?,
+            weekdays,
+            any_day: day == "*",
+            any_weekday: weekday == "*"
+        })
+    }
+}
+
+impl TryFrom<String> for Cron {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// One field as a bitset of the values it allows
+fn parse_field(spec: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut bits = 0;
+
+    for part in spec.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|&s| s > 0).ok_or(format!("bad step `{}`", step))?),
+            None => (part, 1)
+        };
+
+        let num = |n: &str| n.parse::<u32>().ok().filter(|n| (min..=max).contains(n)).ok_or(format!("`{}` is out of range {}-{}", n, min, max));
+
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (num(start)?, num(end)?),
+            // `5/10` means from 5 onwards
+            None if part.contains('/') => (num(range)?, max),
+            None => (num(range)?, num(range)?)
+        };
+
+        if start > end {
+            return Err(format!("range `{}` runs backwards", range))
+        }
+
+        for n in (start..=end).step_by(step as usize) {
+            bits |= 1 << n;
+        }
+    }
+
+    Ok(bits)
+}
+
+impl Cron {
+    fn day_matches(&self, dt: DateTime) -> bool {
+        let day = self.days & (1 << dt.day()) != 0;
+        let weekday = self.weekdays & (1 << dt.weekday().to_sunday_zero_offset()) != 0;
+
+        let day = match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday
+        };
+
+        day && self.months & (1 << dt.month()) != 0
+    }
+
+    /// The latest matching minute at or before `from`
+    pub fn prev(&self, from: DateTime) -> Option<DateTime> {
+        let mut dt = from.date().at(from.hour(), from.minute(), 0, 0);
+        let minute = Span::new().minutes(1);
+
+        for _ in 0..SEARCH_DAYS * (24 + 60) {
+            dt = if !self.day_matches(dt) {
+                dt.date().yesterday().ok()?.at(23, 59, 0, 0)
+            } else if self.hours & (1 << dt.hour()) == 0 {
+                dt.date().at(dt.hour(), 0, 0, 0).checked_sub(minute).ok()?
+            } else if self.minutes & (1 << dt.minute()) == 0 {
+                dt.checked_sub(minute).ok()?
+            } else {
+                return Some(dt)
+            };
+        }
+
+        None
+    }
+
+    /// The earliest matching minute after `after`
+    pub fn next(&self, after: DateTime) -> Option<DateTime> {
+        let minute = Span::new().minutes(1);
+        let mut dt = after.date().at(after.hour(), after.minute(), 0, 0).checked_add(minute).ok()?;
+
+        for _ in 0..SEARCH_DAYS * (24 + 60) {
+            dt = if !self.day_matches(dt) {
+                dt.date().tomorrow().ok()?.at(0, 0, 0, 0)
+            } else if self.hours & (1 << dt.hour()) == 0 {
+                dt.date().at(dt.hour(), 0, 0, 0).checked_add(Span::new().hours(1)).ok()?
+            } else if self.minutes & (1 << dt.minute()) == 0 {
+                dt.checked_add(minute).ok()?
+            } else {
+                return Some(dt)
+            };
+        }
+
+        None
+    }
+}
+
+/// Local wall clock time to a point in time. None for times skipped by a DST change.
+pub fn to_system_time(dt: DateTime) -> Option<SystemTime> {
+    dt.to_zoned(TimeZone::system()).ok().map(|z| SystemTime::from(z.timestamp()))
+}
+
+/// When each scheduled task last ran, kept between runs so missed ones can be caught up on
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State(BTreeMap<String, SystemTime>);
+
+impl State {
+    pub fn load(path: &Path) -> TrashResult<Self> {
+        match File::open(path) {
+            Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(TrashError::from(e))
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> TrashResult<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Whether `task` has missed its most recent scheduled time. Only that one time counts,
+    /// a task that missed several (the machine was off for a week) runs just once.
+    pub fn is_due(&self, task: &str, cron: &Cron, now: &Zoned) -> bool {
+        let Some(scheduled) = cron.prev(now.datetime()).and_then(to_system_time) else {
+            return false
+        };
+
+        match self.0.get(task) {
+            Some(&last) => last < scheduled,
+            None => true
+        }
+    }
+
+    pub fn ran(&mut self, task: &str, at: SystemTime) {
+        self.0.insert(task.to_string(), at);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jiff::civil::date;
+
+    #[test]
+    fn test_parse_cron() {
+        let cron: Cron = "*/15 3,4 1-5 * 7".parse().unwrap();
+
+        assert_eq!(cron.minutes, 1 | 1 << 15 | 1 << 30 | 1 << 45);
+        assert_eq!(cron.hours, 1 << 3 | 1 << 4);
+        assert_eq!(cron.weekdays, 1);
+
+        assert!("0 3 * *".parse::<Cron>().is_err());
+        assert!("60 * * * *".parse::<Cron>().is_err());
+        assert!("5-1 * * * *".parse::<Cron>().is_err());
+        assert!("*/0 * * * *".parse::<Cron>().is_err());
+    }
+
+    #[test]
+    fn test_prev_and_next() {
+        let cron: Cron = "0 3 * * *".parse().unwrap();
+        let noon = date(2024, 5, 2).at(12, 30, 15, 0);
+
+        assert_eq!(cron.prev(noon), Some(date(2024, 5, 2).at(3, 0, 0, 0)));
+        assert_eq!(cron.next(noon), Some(date(2024, 5, 3).at(3, 0, 0, 0)));
+        assert_eq!(cron.prev(date(2024, 5, 2).at(3, 0, 0, 0)), Some(date(2024, 5, 2).at(3, 0, 0, 0)));
+
+        // Day of month or day of week, 2024-05-02 is a Thursday
+        let cron: Cron = "30 9 15 * 1".parse().unwrap();
+
+        assert_eq!(cron.next(noon), Some(date(2024, 5, 6).at(9, 30, 0, 0)));
+        assert_eq!(cron.prev(noon), Some(date(2024, 4, 29).at(9, 30, 0, 0)));
+
+        assert_eq!("0 0 31 2 *".parse::<Cron>().unwrap().next(noon), None);
+    }
+
+    #[test]
+    fn test_catch_up() {
+        let cron: Cron = "0 3 * * *".parse().unwrap();
+        let now = date(2024, 5, 9).at(12, 0, 0, 0).to_zoned(TimeZone::system()).unwrap();
+        let mut state = State::default();
+
+        assert!(state.is_due("purge_expired", &cron, &now));
+
+        // Last ran days ago, the machine was off since: runs once to catch up
+        state.ran("purge_expired", to_system_time(date(2024, 5, 2).at(3, 0, 5, 0)).unwrap());
+        assert!(state.is_due("purge_expired", &cron, &now));
+
+        state.ran("purge_expired", to_system_time(date(2024, 5, 9).at(3, 0, 5, 0)).unwrap());
+        assert!(!state.is_due("purge_expired", &cron, &now));
+    }
+}
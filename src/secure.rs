@@ -0,0 +1,84 @@
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+use crate::{TrashError, TrashResult};
+
+/// Creates `path` and any missing parents readable by the owner only
+#[cfg(unix)]
+pub fn create_private_dir(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    fs::DirBuilder::new().recursive(true).mode(0o700).create(path)
+}
+
+#[cfg(not(unix))]
+pub fn create_private_dir(path: &Path) -> io::Result<()> {
+    fs::create_dir_all(path)
+}
+
+/// Creates or truncates a file readable by the owner only. The mode only applies to new files.
+#[cfg(unix)]
+pub fn create_private_file(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)
+}
+
+#[cfg(not(unix))]
+pub fn create_private_file(path: &Path) -> io::Result<File> {
+    OpenOptions::new().write(true).create(true).truncate(true).open(path)
+}
+
+/// Fails if anyone but the current user could swap out or plant things in `path`: it is
+/// world writable, or it (or whatever it links to) belongs to someone else
+#[cfg(unix)]
+pub fn check_private(path: &Path) -> TrashResult<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let meta = fs::metadata(path).map_err(|e| TrashError(format!("{}: {}", path.display(), e)))?;
+    let linked = path.symlink_metadata().is_ok_and(|m| m.file_type().is_symlink());
+    let uid = unsafe { libc::geteuid() };
+
+    if meta.uid() != uid {
+        let what = if linked { "is a symlink to something owned" } else { "is owned" };
+        return Err(TrashError(format!("{} {} by uid {}, not you", path.display(), what, meta.uid())))
+    }
+
+    if meta.mode() & 0o002 != 0 {
+        return Err(TrashError(format!("{} is writable by everyone", path.display())))
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn check_private(_path: &Path) -> TrashResult<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn test_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let trash = dir.path().join("a/trash");
+
+        create_private_dir(&trash).unwrap();
+        create_private_file(&trash.join("history.json")).unwrap();
+
+        assert_eq!(fs::metadata(&trash).unwrap().permissions().mode() & 0o777, 0o700);
+        assert_eq!(fs::metadata(trash.join("history.json")).unwrap().permissions().mode() & 0o777, 0o600);
+        assert!(check_private(&trash).is_ok());
+
+        fs::set_permissions(&trash, fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(check_private(&trash).is_err());
+
+        // / belongs to root, so a link to it is refused unless running as root
+        std::os::unix::fs::symlink("/", dir.path().join("link")).unwrap();
+        assert_eq!(check_private(&dir.path().join("link")).is_err(), unsafe { libc::geteuid() } != 0);
+    }
+}
@@ -0,0 +1,135 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{config, secure};
+use crate::{TrashError, TrashResult};
+
+/// When expired items are purged, if there is an age to purge at
+const PURGE_SCHEDULE: &str = "0 3 * * *";
+
+/// Only on a terminal and only until there is a config file, anything else gets the defaults
+pub fn should_run(dir: &Path) -> bool {
+    !config::config_path(dir).exists() && io::stdin().is_terminal() && io::stdout().is_terminal()
+}
+
+pub fn run(dir: &Path) -> TrashResult<()> {
+    let shell = std::env::var("SHELL").unwrap_or_default();
+
+    wizard(&mut io::stdin().lock(), &mut io::stdout(), dir, rc_file(&shell, dirs::home_dir().as_deref()))
+}
+
+/// Asks where the trash goes, how long items stay and whether to alias rm, then writes config.toml.
+/// The file is written even if every answer is the default, so nothing is asked again.
+fn wizard(input: &mut impl BufRead, output: &mut impl Write, dir: &Path, rc: Option<(PathBuf, &str)>) -> TrashResult<()> {
+    let path = config::config_path(dir);
+
+    secure::create_private_dir(dir)?;
+
+    writeln!(output, "No config yet, a few questions to set trash up. Change anything later with `trash config set`.")?;
+
+    let mut settings = vec![];
+
+    if let Some(data) = dirs::data_dir().map(|dir| dir.join("trash")) {
+        let question = format!("Keep trashed items in {} so they survive a reboot? Otherwise they go in the temp directory.", data.display());
+
+        if yes(input, output, &question)? {
+            settings.push(("trash_dir", toml::Value::String(data.to_string_lossy().into_owned())));
+        }
+    }
+
+    loop {
+        let age = ask(input, output, "Delete items after how long in the trash, like 30d? Leave blank to keep them.")?;
+
+        if age.is_empty() {
+            break
+        }
+
+        match crate::parse_age(&age) {
+            Ok(_) => {
+                settings.push(("expire_after", toml::Value::String(age)));
+                settings.push(("schedule.purge_expired", toml::Value::String(PURGE_SCHEDULE.to_string())));
+                writeln!(output, "Expired items are purged by `trash maintain`, run it from cron or a systemd timer.")?;
+                break
+            },
+            Err(e) => writeln!(output, "{}", e)?
+        }
+    }
+
+    if let Some((rc, alias)) = rc {
+        if yes(input, output, &format!("Add `{}` to {}?", alias, rc.display()))? {
+            let mut file = OpenOptions::new().create(true).append(true).open(&rc)?;
+            writeln!(file, "\n{}", alias)?;
+        }
+    }
+
+    fs::write(&path, "")?;
+
+    for (key, value) in settings {
+        config::set_value(&path, key, value)?;
+    }
+
+    writeln!(output, "Saved to {}", path.display())?;
+
+    Ok(())
+}
+
+/// The shell's startup file and the line that aliases rm in it
+fn rc_file(shell: &str, home: Option<&Path>) -> Option<(PathBuf, &'static str)> {
+    let home = home?;
+
+    match Path::new(shell).file_name()?.to_str()? {
+        "bash" => Some((home.join(".bashrc"), "alias rm=trash")),
+        "zsh" => Some((home.join(".zshrc"), "alias rm=trash")),
+        "fish" => Some((home.join(".config/fish/config.fish"), "alias rm trash")),
+        _ => None
+    }
+}
+
+fn ask(input: &mut impl BufRead, output: &mut impl Write, question: &str) -> TrashResult<String> {
+    write!(output, "{} ", question)?;
+    output.flush()?;
+
+    let mut answer = String::new();
+
+    if input.read_line(&mut answer)? == 0 {
+        return Err(TrashError::new("Setup cancelled"))
+    }
+
+    Ok(answer.trim().to_string())
+}
+
+fn yes(input: &mut impl BufRead, output: &mut impl Write, question: &str) -> TrashResult<bool> {
+    let answer = ask(input, output, &format!("{} [y/N]", question))?;
+    Ok(matches!(answer.to_lowercase().as_str(), "y" | "yes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wizard() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_dir = dir.path().join("config");
+        let rc = dir.path().join(".bashrc");
+        let layers = config::Layers::user(config::config_path(&config_dir));
+
+        let mut input = io::Cursor::new("n\nsoon\n30d\ny\n");
+        wizard(&mut input, &mut io::sink(), &config_dir, rc_file("/bin/bash", Some(dir.path()))).unwrap();
+
+        let config = config::Config::load_layers(&layers).unwrap();
+
+        assert_eq!(config.trash_dir, None);
+        assert!(config.expire_after.is_some());
+        assert!(config.schedule.purge_expired.is_some());
+        assert_eq!(fs::read_to_string(&rc).unwrap(), "\nalias rm=trash\n");
+
+        // Defaults still leave a config behind
+        fs::remove_file(&layers.user).unwrap();
+        wizard(&mut io::Cursor::new("\n\n"), &mut io::sink(), &config_dir, None).unwrap();
+
+        assert!(layers.user.exists());
+        assert_eq!(rc_file("/usr/bin/tcsh", Some(dir.path())), None);
+    }
+}
@@ -0,0 +1,109 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ItemMeta, TrashError, TrashResult};
+
+/// Bumped whenever a change to the format would stop an older export from importing
+pub const VERSION: u32 = 1;
+
+/// Everything history knows about the trash, without the items themselves
+#[derive(Serialize, Deserialize, Debug)]
+pub struct State {
+    pub version: u32,
+    /// The trash directory it was exported from
+    pub trash_dir: PathBuf,
+    pub operations: Vec<Operation>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Operation {
+    pub items: Vec<Item>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Item {
+    pub original: PathBuf,
+    pub trashed: PathBuf,
+    #[serde(flatten)]
+    pub(crate) meta: ItemMeta
+}
+
+/// `OLD=NEW`, swapping the `OLD` prefix of paths for `NEW` on import
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rewrite {
+    from: PathBuf,
+    to: PathBuf
+}
+
+impl FromStr for Rewrite {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((from, to)) if !from.is_empty() => Ok(Self { from: PathBuf::from(from), to: PathBuf::from(to) }),
+            _ => Err(format!("{} isn't OLD=NEW", s))
+        }
+    }
+}
+
+impl Rewrite {
+    /// Only whole components match, `/mnt/a=/b` leaves `/mnt/ab` alone
+    pub fn apply(rewrites: &[Rewrite], path: &Path) -> PathBuf {
+        rewrites
+            .iter()
+            .find_map(|r| path.strip_prefix(&r.from).ok().map(|rest| r.to.join(rest)))
+            .unwrap_or_else(|| path.to_path_buf())
+    }
+}
+
+/// JSON for `.json` files, TOML for anything else
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "json")
+}
+
+impl State {
+    pub fn save(&self, path: &Path) -> TrashResult<()> {
+        let text = if is_json(path) {
+            serde_json::to_string_pretty(self)?
+        } else {
+            toml::to_string(self).map_err(|e| TrashError(e.to_string()))?
+        };
+
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> TrashResult<Self> {
+        let text = fs::read_to_string(path).map_err(|e| TrashError(format!("{}: {}", path.display(), e)))?;
+
+        let state: Self = if is_json(path) {
+            serde_json::from_str(&text)?
+        } else {
+            toml::from_str(&text).map_err(|e| TrashError(format!("{}: {}", path.display(), e)))?
+        };
+
+        if state.version > VERSION {
+            return Err(TrashError(format!("{} is from a newer version of trash (state version {})", path.display(), state.version)))
+        }
+
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite() {
+        let rewrites = vec!["/tmp/trash=/mnt/disk/trash".parse::<Rewrite>().unwrap(), "/home/old=/home/new".parse().unwrap()];
+
+        assert_eq!(Rewrite::apply(&rewrites, Path::new("/tmp/trash/a.txt")), PathBuf::from("/mnt/disk/trash/a.txt"));
+        assert_eq!(Rewrite::apply(&rewrites, Path::new("/home/old/b")), PathBuf::from("/home/new/b"));
+        assert_eq!(Rewrite::apply(&rewrites, Path::new("/home/older/b")), PathBuf::from("/home/older/b"));
+        assert!("no-equals".parse::<Rewrite>().is_err());
+    }
+}
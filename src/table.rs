@@ -0,0 +1,232 @@
+use std::io::{self, IsTerminal};
+use std::path::Path;
+
+use colorize::colorize;
+
+use crate::link;
+
+const GAP: &str = "  ";
+/// Paths are never squeezed narrower than this, however small the terminal
+const MIN_PATH_WIDTH: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Style {
+    Id,
+    Path,
+    Plain
+}
+
+#[derive(Debug)]
+pub struct Cell {
+    text: String,
+    style: Style
+}
+
+impl Cell {
+    pub fn id(text: &str) -> Self {
+        Self { text: text.to_string(), style: Style::Id }
+    }
+
+    pub fn path(path: &Path) -> Self {
+        Self { text: path.display().to_string(), style: Style::Path }
+    }
+
+    pub fn plain(text: impl ToString) -> Self {
+        Self { text: text.to_string(), style: Style::Plain }
+    }
+}
+
+#[derive(Debug)]
+enum Line {
+    Row(Vec<Cell>),
+    /// Printed as is, without taking part in the column layout
+    Text(String)
+}
+
+/// Column-aligned output sized to the terminal. Path columns absorb any shortfall in
+/// width by losing characters from their middle, where they matter least.
+#[derive(Debug)]
+pub struct Table {
+    headers: Vec<&'static str>,
+    lines: Vec<Line>,
+    truncate: bool
+}
+
+impl Table {
+    pub fn new(headers: Vec<&'static str>, truncate: bool) -> Self {
+        Self { headers, lines: vec![], truncate }
+    }
+
+    pub fn row(&mut self, cells: Vec<Cell>) {
+        self.lines.push(Line::Row(cells));
+    }
+
+    pub fn text(&mut self, text: String) {
+        self.lines.push(Line::Text(text));
+    }
+
+    pub fn print(&self) {
+        let max_width = if self.truncate { terminal_width() } else { None };
+
+        let header = self.headers
+            .iter()
+            .zip(self.widths(max_width))
+            .map(|(h, w)| colorize!(Fyb->format!("{:w$}", h, w = w)))
+            .collect::<Vec<_>>()
+            .join(GAP);
+
+        println!("{}", header.trim_end());
+
+        for line in self.render(max_width) {
+            println!("{}", line);
+        }
+    }
+
+    fn render(&self, max_width: Option<usize>) -> Vec<String> {
+        let widths = self.widths(max_width);
+
+        self.lines
+            .iter()
+            .map(|line| match line {
+                Line::Text(text) => text.clone(),
+                Line::Row(cells) => {
+                    let last = cells.len().saturating_sub(1);
+
+                    cells
+                        .iter()
+                        .zip(widths.iter())
+                        .enumerate()
+                        .map(|(i, (cell, &w))| render_cell(cell, w, i == last))
+                        .collect::<Vec<_>>()
+                        .join(GAP)
+                }
+            })
+            .collect()
+    }
+
+    /// Natural column widths, with path columns shrunk to fit `max_width` if there is one
+    fn widths(&self, max_width: Option<usize>) -> Vec<usize> {
+        let mut widths: Vec<usize> = self.headers.iter().map(|h| h.chars().count()).collect();
+        let mut is_path = vec![false; widths.len()];
+
+        for line in self.lines.iter() {
+            if let Line::Row(cells) = line {
+                for (i, cell) in cells.iter().enumerate().take(widths.len()) {
+                    widths[i] = widths[i].max(cell.text.chars().count());
+                    is_path[i] |= cell.style == Style::Path;
+                }
+            }
+        }
+
+        let max_width = match max_width {
+            Some(w) => w,
+            None => return widths
+        };
+
+        let total = |widths: &[usize]| widths.iter().sum::<usize>() + GAP.len() * widths.len().saturating_sub(1);
+
+        // Take one character at a time from the widest path column until everything fits
+        while total(&widths) > max_width {
+            let widest = (0..widths.len())
+                .filter(|&i| is_path[i] && widths[i] > MIN_PATH_WIDTH)
+                .max_by_key(|&i| widths[i]);
+
+            match widest {
+                Some(i) => widths[i] -= 1,
+                None => break
+            }
+        }
+
+        widths
+    }
+}
+
+fn render_cell(cell: &Cell, width: usize, last: bool) -> String {
+    let shown = truncate_middle(&cell.text, width);
+    let pad = if last { 0 } else { width - shown.chars().count() };
+
+    // Pad outside the styling so escape codes don't count towards the width
+    let styled = match cell.style {
+        Style::Id => colorize!(Fbb->shown.as_str()),
+        // Only link paths that are shown in full, a truncated path isn't much use as a link target
+        Style::Path if shown == cell.text => colorize!(b->link(Path::new(&shown))),
+        Style::Path => colorize!(b->shown.as_str()),
+        Style::Plain => shown
+    };
+
+    format!("{}{}", styled, " ".repeat(pad))
+}
+
+/// Shortens `text` to `width` characters by replacing its middle with an ellipsis
+pub fn truncate_middle(text: &str, width: usize) -> String {
+    let len = text.chars().count();
+
+    if len <= width {
+        return text.to_string()
+    }
+
+    if width == 0 {
+        return String::new()
+    }
+
+    let head = (width - 1) / 2;
+    let tail = width - 1 - head;
+
+    let start: String = text.chars().take(head).collect();
+    let end: String = text.chars().skip(len - tail).collect();
+
+    format!("{}…{}", start, end)
+}
+
+/// Width of the terminal on stdout, or None when it isn't a terminal (e.g. piped to a file)
+fn terminal_width() -> Option<usize> {
+    if !io::stdout().is_terminal() {
+        return None
+    }
+
+    if let Some(cols) = std::env::var("COLUMNS").ok().and_then(|c| c.parse().ok()) {
+        return Some(cols)
+    }
+
+    ioctl_width().or(Some(80))
+}
+
+#[cfg(unix)]
+fn ioctl_width() -> Option<usize> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+
+    // SAFETY: TIOCGWINSZ only writes into the winsize struct we hand it
+    let res = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+
+    (res == 0 && size.ws_col > 0).then_some(size.ws_col as usize)
+}
+
+#[cfg(not(unix))]
+fn ioctl_width() -> Option<usize> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_truncate_middle() {
+        assert_eq!(truncate_middle("/home/me/file.txt", 20), "/home/me/file.txt");
+        assert_eq!(truncate_middle("/home/me/projects/foo/file.txt", 11), "/home…e.txt");
+        assert_eq!(truncate_middle("abc", 1), "…");
+    }
+
+    #[test]
+    fn test_widths_shrink_paths_only() {
+        let mut table = Table::new(vec!["ID", "Original", "Trash"], true);
+
+        table.row(vec![Cell::id("abcdef"), Cell::path(&PathBuf::from("/".repeat(60))), Cell::path(&PathBuf::from("/tmp/a"))]);
+        table.row(vec![Cell::id("ghijklmnop"), Cell::path(&PathBuf::from("/b")), Cell::path(&PathBuf::from("/tmp/b"))]);
+
+        assert_eq!(table.widths(None), vec![10, 60, 6]);
+        assert_eq!(table.widths(Some(40)), vec![10, 20, 6]);
+        assert_eq!(table.widths(Some(10)), vec![10, MIN_PATH_WIDTH, 6]);
+    }
+}
@@ -0,0 +1,115 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use log::{debug, warn};
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Seconds a delivery may take before it is given up on
+const TIMEOUT: &str = "10";
+
+/// Where to POST maintenance events, from the `[webhook]` table in config.toml
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Webhook {
+    pub url: String,
+    /// Only purges freeing more than this are reported
+    #[serde(default, deserialize_with = "size")]
+    pub purge_over: u64
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Purged { items: usize, bytes_freed: u64 },
+    /// The oldest items purged to bring the trash back under `max_size`, however much that freed
+    Evicted { items: usize, bytes_freed: u64, max_size: u64 },
+    /// History entries whose item is gone from the trash, by original path
+    Unrestorable { paths: Vec<PathBuf> }
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    #[serde(flatten)]
+    event: &'a Event,
+    host: Option<String>,
+    time: String
+}
+
+impl Webhook {
+    /// Delivered with curl, failures are only warned about and never fail the operation itself
+    pub fn notify(&self, event: &Event) {
+        let payload = Payload { event, host: hostname(), time: jiff::Timestamp::now().to_string() };
+
+        let body = match serde_json::to_vec(&payload) {
+            Ok(body) => body,
+            Err(e) => return warn!("Webhook not sent: {}", e)
+        };
+
+        debug!("Sending {} to {}", String::from_utf8_lossy(&body), self.url);
+
+        let child = Command::new("curl")
+            .args(["-fsS", "--max-time", TIMEOUT, "-X", "POST", "-H", "Content-Type: application/json", "--data-binary", "@-"])
+            .arg(&self.url)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn();
+
+        let res = child.and_then(|mut child| {
+            child.stdin.take().unwrap().write_all(&body)?;
+            child.wait()
+        });
+
+        match res {
+            Ok(status) if status.success() => {},
+            Ok(status) => warn!("Webhook to {} failed: curl {}", self.url, status),
+            Err(e) => warn!("Webhook to {} failed, could not run curl: {}", self.url, e)
+        }
+    }
+}
+
+fn size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    let size = String::deserialize(deserializer)?;
+    crate::parse_size(&size).map(|s| s as u64).map_err(serde::de::Error::custom)
+}
+
+#[cfg(unix)]
+fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return None
+    }
+
+    let len = buf.iter().position(|&b| b == 0)?;
+    String::from_utf8(buf[..len].to_vec()).ok()
+}
+
+#[cfg(not(unix))]
+fn hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload() {
+        let event = Event::Purged { items: 2, bytes_freed: 4096 };
+        let payload = Payload { event: &event, host: Some("box".to_string()), time: "2024-05-02T03:00:00Z".to_string() };
+
+        assert_eq!(
+            serde_json::to_string(&payload).unwrap(),
+            r#"{"event":"purged","items":2,"bytes_freed":4096,"host":"box","time":"2024-05-02T03:00:00Z"}"#
+        );
+
+        let event = Event::Evicted { items: 3, bytes_freed: 1024, max_size: 2048 };
+        let payload = Payload { event: &event, host: None, time: "2024-05-02T03:00:00Z".to_string() };
+
+        assert_eq!(
+            serde_json::to_string(&payload).unwrap(),
+            r#"{"event":"evicted","items":3,"bytes_freed":1024,"max_size":2048,"host":null,"time":"2024-05-02T03:00:00Z"}"#
+        );
+    }
+}